@@ -0,0 +1,189 @@
+// Keyboard-interactive authentication, with the server's prompts answered by
+// the user through the frontend
+
+use crate::errors;
+use serde::{Deserialize, Serialize};
+use ssh2::{KeyboardInteractivePrompt, Prompt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, State};
+
+// How long to wait for the user to answer before giving up on the login
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+static NEXT_AUTH_ID: AtomicU64 = AtomicU64::new(1);
+
+// Authentication methods the frontend can force through SSHConnectionConfig,
+// also reported by collect_diagnostics as the method that was used
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    Password,
+    Key,
+    Agent,
+    KeyboardInteractive,
+}
+
+// Logins waiting on the user, keyed by auth id
+pub type PendingPrompts = Arc<Mutex<HashMap<String, mpsc::Sender<Vec<String>>>>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptInfo {
+    pub text: String,
+    // False for secrets such as passwords and OTP codes
+    pub echo: bool,
+}
+
+// Payload of the `ssh-keyboard-interactive` event
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyboardInteractiveEvent {
+    pub auth_id: String,
+    pub username: String,
+    pub instructions: String,
+    pub prompts: Vec<PromptInfo>,
+}
+
+// Forwards each round of server prompts to the frontend and blocks the login
+// until submit_keyboard_interactive delivers the answers
+pub struct FrontendPrompter<'a> {
+    app: &'a AppHandle,
+    pending: &'a PendingPrompts,
+    auth_id: String,
+}
+
+impl<'a> FrontendPrompter<'a> {
+    pub fn new(app: &'a AppHandle, pending: &'a PendingPrompts) -> Self {
+        FrontendPrompter {
+            app,
+            pending,
+            auth_id: format!("auth-{}", NEXT_AUTH_ID.fetch_add(1, Ordering::SeqCst)),
+        }
+    }
+}
+
+impl KeyboardInteractivePrompt for FrontendPrompter<'_> {
+    fn prompt<'b>(&mut self, username: &str, instructions: &str, prompts: &[Prompt<'b>]) -> Vec<String> {
+        // Servers may send rounds with no prompts that just need an empty reply
+        if prompts.is_empty() {
+            return Vec::new();
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        match self.pending.lock() {
+            Ok(mut pending) => pending.insert(self.auth_id.clone(), sender),
+            Err(_) => return Vec::new(),
+        };
+
+        let _ = self.app.emit("ssh-keyboard-interactive", KeyboardInteractiveEvent {
+            auth_id: self.auth_id.clone(),
+            username: username.to_string(),
+            instructions: instructions.to_string(),
+            prompts: prompts.iter()
+                .map(|p| PromptInfo { text: p.text.to_string(), echo: p.echo })
+                .collect(),
+        });
+
+        // No answer in time leaves the responses empty, which fails the login
+        let responses = receiver.recv_timeout(PROMPT_TIMEOUT).unwrap_or_default();
+
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&self.auth_id);
+        }
+        responses
+    }
+}
+
+// Answers the server's password prompt with the password from the config,
+// once, and hands every other prompt (an OTP, say, or a second password
+// prompt after a wrong password) to `inner`
+pub struct PasswordPrompter<'a, P> {
+    password: &'a str,
+    inner: &'a mut P,
+    answered: bool,
+}
+
+impl<'a, P> PasswordPrompter<'a, P> {
+    pub fn new(password: &'a str, inner: &'a mut P) -> Self {
+        PasswordPrompter { password, inner, answered: false }
+    }
+}
+
+impl<P: KeyboardInteractivePrompt> KeyboardInteractivePrompt for PasswordPrompter<'_, P> {
+    fn prompt<'b>(&mut self, username: &str, instructions: &str, prompts: &[Prompt<'b>]) -> Vec<String> {
+        let asks_password = match prompts {
+            [prompt] => !prompt.echo && prompt.text.to_lowercase().contains("password"),
+            _ => false,
+        };
+        if asks_password && !self.answered {
+            self.answered = true;
+            return vec![self.password.to_string()];
+        }
+        self.inner.prompt(username, instructions, prompts)
+    }
+}
+
+// Answers nothing, for logging in again without the user: a
+// keyboard-interactive login that needs answers fails instead of waiting
+pub struct NoPrompter;
+
+impl KeyboardInteractivePrompt for NoPrompter {
+    fn prompt<'b>(&mut self, _username: &str, _instructions: &str, _prompts: &[Prompt<'b>]) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+// Answers the prompts of an `ssh-keyboard-interactive` event, one response per
+// prompt in the same order
+#[tauri::command]
+pub async fn submit_keyboard_interactive(
+    auth_id: String,
+    responses: Vec<String>,
+    pending: State<'_, PendingPrompts>,
+) -> Result<bool, errors::SSHError> {
+    let pending = pending.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    match pending.get(&auth_id) {
+        Some(sender) => Ok(sender.send(responses).is_ok()),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    // Stands in for the user, recording the prompts it was shown
+    #[derive(Default)]
+    struct ScriptedPrompter {
+        asked: Vec<String>,
+    }
+
+    impl KeyboardInteractivePrompt for ScriptedPrompter {
+        fn prompt<'b>(&mut self, _username: &str, _instructions: &str, prompts: &[Prompt<'b>]) -> Vec<String> {
+            self.asked.extend(prompts.iter().map(|p| p.text.to_string()));
+            prompts.iter().map(|_| "from user".to_string()).collect()
+        }
+    }
+
+    fn prompt(text: &str) -> Prompt<'_> {
+        Prompt { text: Cow::Borrowed(text), echo: false }
+    }
+
+    #[test]
+    fn password_is_answered_once_and_the_rest_goes_to_the_user() {
+        let mut user = ScriptedPrompter::default();
+        let mut prompter = PasswordPrompter::new("secret", &mut user);
+
+        assert_eq!(prompter.prompt("alice", "", &[prompt("Password: ")]), ["secret"]);
+        assert_eq!(prompter.prompt("alice", "", &[prompt("Verification code: ")]), ["from user"]);
+        // Asked again means the first answer was wrong, so the user decides
+        assert_eq!(prompter.prompt("alice", "", &[prompt("Password: ")]), ["from user"]);
+        assert!(prompter.prompt("alice", "", &[]).is_empty());
+
+        assert_eq!(user.asked, ["Verification code: ", "Password: "]);
+    }
+}
@@ -0,0 +1,841 @@
+// Recursive directory upload and download over SFTP
+//
+// Both directions walk the whole tree first, so progress can be reported
+// against the full file and byte counts, then copy one file at a time. As
+// with resumable downloads, the connection's lock is taken per chunk, so the
+// connection stays usable during a long transfer. A file that fails is
+// recorded and the rest carry on; cancel_transfer stops the whole transfer
+// between chunks.
+//
+// Symlinks to directories are never followed, which is what keeps a link
+// pointing back up the tree from making the walk loop forever.
+
+use crate::transfer::{resolve_remote_path, DOWNLOAD_CHUNK_SIZE, PROGRESS_INTERVAL};
+use crate::{errors, get_client, ConnectionsStore, SharedClient};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use ssh2::{FileType, OpenFlags, OpenType, Sftp};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::{AppHandle, Emitter, State};
+
+static NEXT_TRANSFER_ID: AtomicU64 = AtomicU64::new(1);
+
+// Cancel flags of the directory transfers in progress, by transfer ID
+pub type TransfersStore = Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExistingFiles {
+    #[default]
+    Overwrite,
+    Skip,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DirectoryTransferOptions {
+    // Names or relative paths to leave out, with `*`, `**` and `?` wildcards,
+    // e.g. "node_modules", ".git/", "build/*.o" or "src/**/*.tmp". A pattern
+    // without a slash is matched against every file and directory name, and
+    // one ending in a slash only against directories.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // What to do with files that already exist at the destination
+    #[serde(default)]
+    pub existing: ExistingFiles,
+    // Recreate symlinks as symlinks instead of copying the files they point
+    // to. Absolute targets inside the tree being copied are rewritten to
+    // point into the copy; relative ones are kept as they are.
+    #[serde(default)]
+    pub preserve_symlinks: bool,
+    // ID for cancel_transfer and the progress events; generated if not given,
+    // but choosing one lets the frontend cancel before this call returns
+    pub transfer_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+// Payload of the `directory-transfer-progress` event
+#[derive(Debug, Clone, Serialize)]
+pub struct DirectoryTransferProgress {
+    pub transfer_id: String,
+    pub connection_id: String,
+    pub direction: TransferDirection,
+    // Relative to the directory being transferred
+    pub current_file: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedTransfer {
+    // Relative to the directory being transferred
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryTransferResult {
+    pub transfer_id: String,
+    pub files_total: usize,
+    pub files_transferred: usize,
+    pub files_skipped: usize,
+    pub failed: Vec<FailedTransfer>,
+    pub bytes_transferred: u64,
+    pub elapsed_ms: u64,
+    pub cancelled: bool,
+}
+
+// Matches `?` (any one character), `*` (any run of characters within one
+// path component) and `**` (any run, slashes included). `**/` also matches
+// no directories at all, so "src/**/*.o" takes "src/a.o" too.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_chars(&pattern, &text)
+}
+
+fn glob_match_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            if let ['/', after @ ..] = rest {
+                if glob_match_chars(after, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|i| glob_match_chars(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => {
+            let component = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=component).any(|i| glob_match_chars(rest, &text[i..]))
+        }
+        ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob_match_chars(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match_chars(rest, &text[1..]),
+    }
+}
+
+// `relative` uses `/` separators whichever side it came from. A pattern
+// ending in `/` only leaves out directories.
+fn is_excluded(relative: &str, is_dir: bool, exclude: &[String]) -> bool {
+    let name = relative.rsplit('/').next().unwrap_or(relative);
+    exclude.iter().any(|pattern| {
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern.as_str(), false),
+        };
+        let pattern = pattern.trim_start_matches('/');
+        if dir_only && !is_dir {
+            false
+        } else if pattern.contains('/') {
+            glob_match(pattern, relative)
+        } else {
+            glob_match(pattern, name)
+        }
+    })
+}
+
+fn join_relative(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+struct TreeFile {
+    relative: String,
+    size: u64,
+    // Permission bits to give the copy, when known
+    mode: Option<i32>,
+}
+
+struct TreeLink {
+    relative: String,
+    target: PathBuf,
+}
+
+// What a walk found, with relative paths. Directories come before anything
+// inside them, so they can be created in order.
+#[derive(Default)]
+struct Tree {
+    directories: Vec<String>,
+    files: Vec<TreeFile>,
+    // Only collected with preserve_symlinks; otherwise links to files are
+    // listed as files
+    links: Vec<TreeLink>,
+    // Directories that couldn't be listed, reported as failures
+    unreadable: Vec<FailedTransfer>,
+}
+
+#[cfg(unix)]
+fn local_mode(metadata: &fs::Metadata) -> Option<i32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some((metadata.permissions().mode() & 0o777) as i32)
+}
+
+#[cfg(not(unix))]
+fn local_mode(_metadata: &fs::Metadata) -> Option<i32> {
+    None
+}
+
+// Without preserve_symlinks, symlinks to files are followed and symlinks to
+// directories are skipped
+fn walk_local(root: &Path, relative: &str, options: &DirectoryTransferOptions, tree: &mut Tree) {
+    let entries = match fs::read_dir(root.join(relative)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tree.unreadable.push(FailedTransfer {
+                path: relative.to_string(),
+                error: format!("Failed to list local directory: {}", e),
+            });
+            return;
+        }
+    };
+
+    let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = join_relative(relative, &entry.file_name().to_string_lossy());
+        let Ok(metadata) = fs::symlink_metadata(entry.path()) else { continue };
+        if is_excluded(&path, metadata.is_dir(), &options.exclude) {
+            continue;
+        }
+
+        if metadata.is_dir() {
+            tree.directories.push(path.clone());
+            walk_local(root, &path, options, tree);
+        } else if metadata.is_file() {
+            tree.files.push(TreeFile { relative: path, size: metadata.len(), mode: local_mode(&metadata) });
+        } else if metadata.file_type().is_symlink() && options.preserve_symlinks {
+            match fs::read_link(entry.path()) {
+                Ok(target) => tree.links.push(TreeLink { relative: path, target }),
+                Err(e) => tree.unreadable.push(FailedTransfer {
+                    path,
+                    error: format!("Failed to read local symlink: {}", e),
+                }),
+            }
+        } else if metadata.file_type().is_symlink() {
+            if let Ok(target) = fs::metadata(entry.path()) {
+                if target.is_file() {
+                    tree.files.push(TreeFile { relative: path, size: target.len(), mode: local_mode(&target) });
+                }
+            }
+        }
+    }
+}
+
+// What a remote symlink turned out to be
+enum LinkTarget {
+    File(ssh2::FileStat),
+    Link(Result<PathBuf, ssh2::Error>),
+}
+
+// Same rules as walk_local. Each directory is listed under its own lock.
+fn walk_remote(client: &SharedClient, sftp: &Sftp, root: &str, relative: &str, options: &DirectoryTransferOptions, tree: &mut Tree) -> Result<()> {
+    let listing = {
+        let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+        let path = Path::new(root).join(relative);
+        match sftp.readdir(&path) {
+            Ok(entries) => entries.into_iter()
+                .map(|(path, stat)| {
+                    // readdir reports links themselves, so look through them
+                    // here, or read where they point when preserving them
+                    let target = match stat.file_type() {
+                        FileType::Symlink if options.preserve_symlinks => Some(LinkTarget::Link(sftp.readlink(&path))),
+                        FileType::Symlink => sftp.stat(&path).ok().map(LinkTarget::File),
+                        _ => None,
+                    };
+                    (path, stat, target)
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                tree.unreadable.push(FailedTransfer {
+                    path: relative.to_string(),
+                    error: format!("Failed to list remote directory: {}", e),
+                });
+                return Ok(());
+            }
+        }
+    };
+
+    let mut listing: Vec<_> = listing.into_iter()
+        .filter_map(|(path, stat, target)| {
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            Some((name, stat, target))
+        })
+        .filter(|(name, _, _)| name != "." && name != "..")
+        .collect();
+    listing.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (name, stat, target) in listing {
+        let path = join_relative(relative, &name);
+        if is_excluded(&path, stat.file_type() == FileType::Directory, &options.exclude) {
+            continue;
+        }
+
+        let file_stat = match stat.file_type() {
+            FileType::Directory => {
+                tree.directories.push(path.clone());
+                walk_remote(client, sftp, root, &path, options, tree)?;
+                continue;
+            }
+            FileType::RegularFile => stat,
+            FileType::Symlink => match target {
+                Some(LinkTarget::File(target)) if target.is_file() => target,
+                Some(LinkTarget::Link(Ok(target))) => {
+                    tree.links.push(TreeLink { relative: path, target });
+                    continue;
+                }
+                Some(LinkTarget::Link(Err(e))) => {
+                    tree.unreadable.push(FailedTransfer {
+                        path,
+                        error: format!("Failed to read remote symlink: {}", e),
+                    });
+                    continue;
+                }
+                _ => continue,
+            },
+            _ => continue,
+        };
+        tree.files.push(TreeFile {
+            relative: path,
+            size: file_stat.size.unwrap_or(0),
+            mode: file_stat.perm.map(|p| (p & 0o777) as i32),
+        });
+    }
+    Ok(())
+}
+
+// Progress and results shared by both directions
+struct Transfer<'a> {
+    app: &'a AppHandle,
+    connection_id: &'a str,
+    transfer_id: String,
+    direction: TransferDirection,
+    cancel: Arc<AtomicBool>,
+    started: Instant,
+    last_progress: Instant,
+    files_total: usize,
+    files_done: usize,
+    bytes_total: u64,
+    bytes_done: u64,
+    files_transferred: usize,
+    files_skipped: usize,
+    failed: Vec<FailedTransfer>,
+}
+
+impl Transfer<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+
+    fn progress(&mut self, current_file: &str, force: bool) {
+        if !force && self.last_progress.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        let _ = self.app.emit("directory-transfer-progress", DirectoryTransferProgress {
+            transfer_id: self.transfer_id.clone(),
+            connection_id: self.connection_id.to_string(),
+            direction: self.direction,
+            current_file: current_file.to_string(),
+            files_done: self.files_done,
+            files_total: self.files_total,
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+        });
+        self.last_progress = Instant::now();
+    }
+
+    // Symlinks count as files, of no size
+    fn start(&mut self, tree: &mut Tree) {
+        self.files_total = tree.files.len() + tree.links.len();
+        self.bytes_total = tree.files.iter().map(|f| f.size).sum();
+        self.failed.append(&mut tree.unreadable);
+    }
+
+    fn skip(&mut self, relative: &str, size: u64) {
+        self.files_skipped += 1;
+        self.files_done += 1;
+        self.bytes_total -= size;
+        self.progress(relative, true);
+    }
+
+    fn finish(&mut self, relative: &str, result: Result<()>) {
+        match result {
+            Ok(()) => self.files_transferred += 1,
+            Err(e) => self.failed.push(FailedTransfer { path: relative.to_string(), error: e.to_string() }),
+        }
+        self.files_done += 1;
+        self.progress(relative, true);
+    }
+
+    fn into_result(self) -> DirectoryTransferResult {
+        let cancelled = self.is_cancelled();
+        DirectoryTransferResult {
+            transfer_id: self.transfer_id,
+            files_total: self.files_total,
+            files_transferred: self.files_transferred,
+            files_skipped: self.files_skipped,
+            failed: self.failed,
+            bytes_transferred: self.bytes_done,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            cancelled,
+        }
+    }
+}
+
+// Copies `reader` to `writer` a chunk at a time. `remote_reads` says which
+// side is the remote file, whose calls need the connection's lock.
+fn copy_chunks(
+    client: &SharedClient,
+    transfer: &mut Transfer,
+    relative: &str,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    remote_reads: bool,
+) -> Result<()> {
+    let mut buf = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+    loop {
+        if transfer.is_cancelled() {
+            anyhow::bail!("Transfer cancelled");
+        }
+
+        let n = if remote_reads {
+            let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+            reader.read(&mut buf).context("Failed to read remote file")?
+        } else {
+            reader.read(&mut buf).context("Failed to read local file")?
+        };
+        if n == 0 {
+            return Ok(());
+        }
+
+        if remote_reads {
+            writer.write_all(&buf[..n]).context("Failed to write local file")?;
+        } else {
+            let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+            writer.write_all(&buf[..n]).context("Failed to write remote file")?;
+        }
+
+        transfer.bytes_done += n as u64;
+        transfer.progress(relative, false);
+    }
+}
+
+fn upload_file(client: &SharedClient, sftp: &Sftp, transfer: &mut Transfer, local_root: &Path, remote_root: &str, file: &TreeFile) -> Result<()> {
+    let mut local = fs::File::open(local_root.join(&file.relative)).context("Failed to open local file")?;
+    let remote_path = Path::new(remote_root).join(&file.relative);
+
+    let mut remote = {
+        let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        sftp.open_mode(&remote_path, flags, file.mode.unwrap_or(0o644), OpenType::File)
+            .context("Failed to open remote file")?
+    };
+
+    let result = copy_chunks(client, transfer, &file.relative, &mut local, &mut remote, false);
+
+    // The SFTP handle talks to the server when dropped, so do that under the lock
+    let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    drop(remote);
+    result
+}
+
+fn download_file(client: &SharedClient, sftp: &Sftp, transfer: &mut Transfer, remote_root: &str, local_root: &Path, file: &TreeFile) -> Result<()> {
+    let local_path = local_root.join(&file.relative);
+    let mut local = fs::File::create(&local_path).context("Failed to create local file")?;
+    #[cfg(unix)]
+    if let Some(mode) = file.mode {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = local.set_permissions(fs::Permissions::from_mode(mode as u32));
+    }
+
+    let mut remote = {
+        let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+        sftp.open(Path::new(remote_root).join(&file.relative)).context("Failed to open remote file")?
+    };
+
+    let result = copy_chunks(client, transfer, &file.relative, &mut remote, &mut local, true);
+
+    let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    drop(remote);
+    result
+}
+
+// `root` followed by a local relative path, as a remote path
+fn remote_join(root: &str, relative: &Path) -> String {
+    relative.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .fold(root.trim_end_matches('/').to_string(), |path, part| format!("{}/{}", path, part))
+}
+
+#[cfg(unix)]
+fn create_local_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+// Windows has separate links for files and directories, so look at what the
+// target is, relative to the link's own directory
+#[cfg(windows)]
+fn create_local_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    let resolved = link.parent().map(|dir| dir.join(target)).unwrap_or_else(|| target.to_path_buf());
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+// Starts SFTP and resolves the remote root against the working directory
+fn open_sftp(client: &SharedClient, remote_path: &str) -> Result<(Sftp, String)> {
+    let client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    let sftp = client.session.sftp().context("Failed to start SFTP subsystem")?;
+    let root = resolve_remote_path(&client.current_directory, remote_path);
+    Ok((sftp, root.to_string_lossy().into_owned()))
+}
+
+fn upload_tree(connections: &ConnectionsStore, transfer: &mut Transfer, local_root: &Path, remote_path: &str, options: &DirectoryTransferOptions) -> Result<()> {
+    if !local_root.is_dir() {
+        anyhow::bail!("{} is not a local directory", local_root.display());
+    }
+
+    let client = get_client(connections, transfer.connection_id)?;
+    let (sftp, remote_root) = open_sftp(&client, remote_path)?;
+
+    let mut tree = Tree::default();
+    walk_local(local_root, "", options, &mut tree);
+    transfer.start(&mut tree);
+    transfer.progress("", true);
+
+    let result = (|| -> Result<()> {
+        // Create the remote directories up front. If one can't be made, its
+        // files fail on their own and are reported with the reason.
+        {
+            let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+            let root = Path::new(&remote_root);
+            if !sftp.stat(root).map(|s| s.is_dir()).unwrap_or(false) {
+                sftp.mkdir(root, 0o755).context("Failed to create the remote directory")?;
+            }
+            for directory in &tree.directories {
+                let path = root.join(directory);
+                if sftp.stat(&path).map(|s| s.is_dir()).unwrap_or(false) {
+                    continue;
+                }
+                if let Err(e) = sftp.mkdir(&path, 0o755) {
+                    transfer.failed.push(FailedTransfer {
+                        path: directory.clone(),
+                        error: format!("Failed to create remote directory: {}", e),
+                    });
+                }
+            }
+        }
+
+        for file in &tree.files {
+            if transfer.is_cancelled() {
+                break;
+            }
+
+            if options.existing == ExistingFiles::Skip {
+                let exists = {
+                    let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+                    sftp.stat(&Path::new(&remote_root).join(&file.relative)).is_ok()
+                };
+                if exists {
+                    transfer.skip(&file.relative, file.size);
+                    continue;
+                }
+            }
+
+            let result = upload_file(&client, &sftp, transfer, local_root, &remote_root, file);
+            transfer.finish(&file.relative, result);
+        }
+
+        let local_canonical = local_root.canonicalize().unwrap_or_else(|_| local_root.to_path_buf());
+        for link in &tree.links {
+            if transfer.is_cancelled() {
+                break;
+            }
+
+            let target = match link.target.strip_prefix(&local_canonical) {
+                Ok(rest) if link.target.is_absolute() => remote_join(&remote_root, rest),
+                // Relative links made on Windows use backslashes
+                _ => link.target.to_string_lossy().replace('\\', "/"),
+            };
+            let link_path = Path::new(&remote_root).join(&link.relative);
+            let result = (|| -> Result<bool> {
+                let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+                if sftp.lstat(&link_path).is_ok() {
+                    if options.existing == ExistingFiles::Skip {
+                        return Ok(false);
+                    }
+                    sftp.unlink(&link_path).context("Failed to replace the existing remote file")?;
+                }
+                sftp.symlink(Path::new(&target), &link_path).context("Failed to create remote symlink")?;
+                Ok(true)
+            })();
+            match result {
+                Ok(false) => transfer.skip(&link.relative, 0),
+                result => transfer.finish(&link.relative, result.map(|_| ())),
+            }
+        }
+        Ok(())
+    })();
+
+    let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    drop(sftp);
+    result
+}
+
+fn download_tree(connections: &ConnectionsStore, transfer: &mut Transfer, remote_path: &str, local_root: &Path, options: &DirectoryTransferOptions) -> Result<()> {
+    let client = get_client(connections, transfer.connection_id)?;
+    let (sftp, remote_root) = open_sftp(&client, remote_path)?;
+
+    let result = (|| -> Result<()> {
+        {
+            let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+            let stat = sftp.stat(Path::new(&remote_root)).context("Failed to read the remote directory")?;
+            if !stat.is_dir() {
+                anyhow::bail!("{} is not a remote directory", remote_root);
+            }
+        }
+
+        let mut tree = Tree::default();
+        walk_remote(&client, &sftp, &remote_root, "", options, &mut tree)?;
+        transfer.start(&mut tree);
+        transfer.progress("", true);
+
+        fs::create_dir_all(local_root).context("Failed to create the local directory")?;
+        for directory in &tree.directories {
+            if let Err(e) = fs::create_dir_all(local_root.join(directory)) {
+                transfer.failed.push(FailedTransfer {
+                    path: directory.clone(),
+                    error: format!("Failed to create local directory: {}", e),
+                });
+            }
+        }
+
+        for file in &tree.files {
+            if transfer.is_cancelled() {
+                break;
+            }
+
+            if options.existing == ExistingFiles::Skip && local_root.join(&file.relative).exists() {
+                transfer.skip(&file.relative, file.size);
+                continue;
+            }
+
+            let result = download_file(&client, &sftp, transfer, &remote_root, local_root, file);
+            transfer.finish(&file.relative, result);
+        }
+
+        for link in &tree.links {
+            if transfer.is_cancelled() {
+                break;
+            }
+
+            let target = match link.target.strip_prefix(Path::new(&remote_root)) {
+                Ok(rest) if link.target.has_root() => local_root.join(rest),
+                _ => link.target.clone(),
+            };
+            let link_path = local_root.join(&link.relative);
+            let result = (|| -> Result<bool> {
+                if fs::symlink_metadata(&link_path).is_ok() {
+                    if options.existing == ExistingFiles::Skip {
+                        return Ok(false);
+                    }
+                    fs::remove_file(&link_path).context("Failed to replace the existing local file")?;
+                }
+                create_local_symlink(&target, &link_path).context("Failed to create local symlink")?;
+                Ok(true)
+            })();
+            match result {
+                Ok(false) => transfer.skip(&link.relative, 0),
+                result => transfer.finish(&link.relative, result.map(|_| ())),
+            }
+        }
+        Ok(())
+    })();
+
+    let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    drop(sftp);
+    result
+}
+
+// Registers the transfer's cancel flag for as long as it runs
+fn run_transfer(
+    app: &AppHandle,
+    transfers: &TransfersStore,
+    connection_id: &str,
+    direction: TransferDirection,
+    transfer_id: Option<String>,
+    run: impl FnOnce(&mut Transfer) -> Result<()>,
+) -> Result<DirectoryTransferResult> {
+    let transfer_id = transfer_id
+        .unwrap_or_else(|| format!("transfer-{}", NEXT_TRANSFER_ID.fetch_add(1, Ordering::SeqCst)));
+    let cancel = Arc::new(AtomicBool::new(false));
+    {
+        let mut transfers = transfers.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+        if transfers.contains_key(&transfer_id) {
+            anyhow::bail!("A transfer with ID {} is already running", transfer_id);
+        }
+        transfers.insert(transfer_id.clone(), cancel.clone());
+    }
+
+    let mut transfer = Transfer {
+        app,
+        connection_id,
+        transfer_id: transfer_id.clone(),
+        direction,
+        cancel,
+        started: Instant::now(),
+        last_progress: Instant::now(),
+        files_total: 0,
+        files_done: 0,
+        bytes_total: 0,
+        bytes_done: 0,
+        files_transferred: 0,
+        files_skipped: 0,
+        failed: Vec::new(),
+    };
+    let result = run(&mut transfer);
+
+    if let Ok(mut transfers) = transfers.lock() {
+        transfers.remove(&transfer_id);
+    }
+    result.map(|()| transfer.into_result())
+}
+
+// Uploads a local directory tree to `remote_path`, creating directories on
+// the server as needed. Files that fail are listed in the result's `failed`
+// rather than stopping the transfer.
+#[tauri::command]
+pub async fn upload_directory(
+    app: AppHandle,
+    connection_id: String,
+    local_path: String,
+    remote_path: String,
+    options: Option<DirectoryTransferOptions>,
+    connections: State<'_, ConnectionsStore>,
+    transfers: State<'_, TransfersStore>,
+) -> Result<DirectoryTransferResult, errors::SSHError> {
+    let options = options.unwrap_or_default();
+    let connections = connections.inner().clone();
+    let transfers = transfers.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let transfer_id = options.transfer_id.clone();
+        run_transfer(&app, &transfers, &connection_id, TransferDirection::Upload, transfer_id, |transfer| {
+            upload_tree(&connections, transfer, Path::new(&local_path), &remote_path, &options)
+        })
+        .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Directory upload failed: {}", e)))
+    })
+    .await
+    .map_err(|e| format!("Upload task failed: {}", e))?
+}
+
+// Downloads a remote directory tree into `local_path`, creating it if needed
+#[tauri::command]
+pub async fn download_directory(
+    app: AppHandle,
+    connection_id: String,
+    remote_path: String,
+    local_path: String,
+    options: Option<DirectoryTransferOptions>,
+    connections: State<'_, ConnectionsStore>,
+    transfers: State<'_, TransfersStore>,
+) -> Result<DirectoryTransferResult, errors::SSHError> {
+    let options = options.unwrap_or_default();
+    let connections = connections.inner().clone();
+    let transfers = transfers.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let transfer_id = options.transfer_id.clone();
+        run_transfer(&app, &transfers, &connection_id, TransferDirection::Download, transfer_id, |transfer| {
+            download_tree(&connections, transfer, &remote_path, Path::new(&local_path), &options)
+        })
+        .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Directory download failed: {}", e)))
+    })
+    .await
+    .map_err(|e| format!("Download task failed: {}", e))?
+}
+
+// Stops a directory transfer after its current chunk. The file being copied
+// is reported as failed and the rest are left untouched. Returns false if the
+// transfer had already finished.
+#[tauri::command]
+pub async fn cancel_transfer(
+    transfer_id: String,
+    transfers: State<'_, TransfersStore>,
+) -> Result<bool, errors::SSHError> {
+    let transfers = transfers.lock().map_err(|e| format!("Lock error: {}", e))?;
+    match transfers.get(&transfer_id) {
+        Some(cancel) => {
+            cancel.store(true, Ordering::SeqCst);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_wildcards() {
+        for (pattern, text, matches) in [
+            ("*.o", "main.o", true),
+            ("*.o", "main.c", false),
+            ("?.rs", "a.rs", true),
+            ("?.rs", "ab.rs", false),
+            ("build/*.o", "build/main.o", true),
+            ("build/*.o", "build/sub/main.o", false),
+            ("build/**", "build/sub/main.o", true),
+            ("src/**/*.tmp", "src/a/b/c.tmp", true),
+            ("src/**/*.tmp", "src/c.tmp", true),
+            ("src/**/*.tmp", "srcx/c.tmp", false),
+            ("**/cache", "a/b/cache", true),
+            ("**/cache", "cache", true),
+            ("a?b", "a/b", false),
+            ("*", "", true),
+            ("données*", "données.txt", true),
+        ] {
+            assert_eq!(glob_match(pattern, text), matches, "{:?} ~ {:?}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn exclusions() {
+        let exclude: Vec<String> = ["node_modules", ".git/", "build/*.o", "/dist", "docs/**/*.pdf"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        for (relative, is_dir, excluded) in [
+            ("node_modules", true, true),
+            ("web/node_modules", true, true),
+            (".git", true, true),
+            ("sub/.git", true, true),
+            (".git", false, false),
+            ("build/main.o", false, true),
+            ("build/sub/main.o", false, false),
+            ("dist", true, true),
+            ("docs/a/b/manual.pdf", false, true),
+            ("docs/manual.pdf", false, true),
+            ("src/main.rs", false, false),
+        ] {
+            assert_eq!(is_excluded(relative, is_dir, &exclude), excluded, "{:?}", relative);
+        }
+    }
+}
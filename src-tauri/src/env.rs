@@ -0,0 +1,273 @@
+// Environment variables carried across one-shot commands
+//
+// Every command runs in a fresh channel, so an `export` would normally be
+// lost straight away. Plain `export NAME=value` and `unset NAME` commands are
+// recognised (the way cd is) and their effect is kept in the client's session
+// env, which is exported again ahead of every later command. The session env
+// can also be set directly with set_remote_env and friends.
+
+use crate::{errors, shell_quote, with_client, ConnectionsStore};
+use std::collections::HashMap;
+use tauri::State;
+
+#[derive(Debug, PartialEq)]
+pub enum EnvChange {
+    // Names being exported; the values are read back from the shell so that
+    // things like `PATH=$PATH:/opt/bin` are expanded the same way they would be
+    Export(Vec<String>),
+    Unset(Vec<String>),
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Splits a command into words the way the shell would, removing quotes.
+// Returns None for anything beyond a single simple command (pipes, lists,
+// redirects, command substitutions), which we leave alone.
+pub fn split_words(command: &str) -> Option<Vec<String>> {
+    if command.contains("$(") {
+        return None;
+    }
+
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        '`' => return None,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.push(chars.next()?);
+            }
+            ';' | '&' | '|' | '<' | '>' | '`' | '(' | ')' | '\n' => return None,
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    Some(words)
+}
+
+// Recognises `export A=1 B="two words"` and `unset A B`
+pub fn parse_env_command(command: &str) -> Option<EnvChange> {
+    let words = split_words(command.trim())?;
+    let (first, rest) = words.split_first()?;
+    if rest.is_empty() {
+        return None;
+    }
+
+    // Options such as `export -n` or `unset -f` do something else entirely
+    if rest.iter().any(|w| w.starts_with('-')) {
+        return None;
+    }
+
+    match first.as_str() {
+        "export" => {
+            let mut names = Vec::new();
+            for word in rest {
+                match word.split_once('=') {
+                    Some((name, _)) if is_valid_name(name) => names.push(name.to_string()),
+                    // A bare `export NAME` exports whatever NAME already is.
+                    // Inherited variables are exported in every command anyway,
+                    // so recording one would only pin it to an empty value.
+                    None if is_valid_name(word) => {}
+                    _ => return None,
+                }
+            }
+            (!names.is_empty()).then_some(EnvChange::Export(names))
+        }
+        "unset" => rest.iter()
+            .map(|name| is_valid_name(name).then(|| name.clone()))
+            .collect::<Option<Vec<String>>>()
+            .map(EnvChange::Unset),
+        _ => None,
+    }
+}
+
+// Script that runs the export and then prints each variable's resulting
+// value, NUL-terminated so values containing newlines survive
+pub fn export_capture_script(command: &str, names: &[String]) -> String {
+    let values: Vec<String> = names.iter().map(|n| format!("\"${{{}-}}\"", n)).collect();
+    format!("{} && printf '%s\\0' {}", command, values.join(" "))
+}
+
+// Prefix that re-creates the session env, e.g. `export A='1' B='2'; `
+pub fn export_prefix(env: &HashMap<String, String>) -> String {
+    if env.is_empty() {
+        return String::new();
+    }
+
+    let mut names: Vec<&String> = env.keys().collect();
+    names.sort();
+    let assignments: Vec<String> = names.iter()
+        .map(|name| format!("{}={}", name, shell_quote(&env[*name])))
+        .collect();
+
+    format!("export {}; ", assignments.join(" "))
+}
+
+// Sets a variable for every later command on the connection. The value is
+// used as is, without the shell expanding anything in it.
+#[tauri::command]
+pub async fn set_remote_env(
+    connection_id: String,
+    name: String,
+    value: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<(), errors::SSHError> {
+    if !is_valid_name(&name) {
+        return Err(errors::SSHError::new(errors::SSHErrorKind::InvalidArgument, format!("Invalid variable name: {}", name)));
+    }
+    // The one character a shell variable can't hold
+    if value.contains('\0') {
+        return Err(errors::SSHError::new(errors::SSHErrorKind::InvalidArgument, "Variable values can't contain NUL characters"));
+    }
+
+    with_client(&connections, &connection_id, move |client| {
+        client.session_env.insert(name, value);
+        Ok(())
+    })
+    .await
+}
+
+// Stops exporting a variable. Returns false if it wasn't set.
+#[tauri::command]
+pub async fn unset_remote_env(
+    connection_id: String,
+    name: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<bool, errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        Ok(client.session_env.remove(&name).is_some())
+    })
+    .await
+}
+
+// The variables exported ahead of each command, whether set here or by an
+// earlier `export`
+#[tauri::command]
+pub async fn get_remote_env(
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<HashMap<String, String>, errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| Ok(client.session_env.clone())).await
+}
+
+#[tauri::command]
+pub async fn clear_remote_env(
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<(), errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        client.session_env.clear();
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    // Output of a script run by the local sh, standing in for the server's
+    fn sh(script: &str) -> String {
+        let output = Command::new("sh").arg("-c").arg(script).output().expect("run sh");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    // Runs an export the way execute_export does and returns the values it
+    // would record
+    fn capture(command: &str) -> HashMap<String, String> {
+        let Some(EnvChange::Export(names)) = parse_env_command(command) else {
+            panic!("not an export: {}", command);
+        };
+        let output = sh(&export_capture_script(command, &names));
+        names.into_iter().zip(output.split('\0').map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn split_words_removes_quotes() {
+        assert_eq!(split_words("export A=1 B='two words'").unwrap(), ["export", "A=1", "B=two words"]);
+        assert_eq!(split_words(r#"export A="it's \"q\"" B=a\ b"#).unwrap(), ["export", "A=it's \"q\"", "B=a b"]);
+        assert_eq!(split_words("export  A=é\t").unwrap(), ["export", "A=é"]);
+        assert_eq!(split_words("export A=''").unwrap(), ["export", "A="]);
+    }
+
+    #[test]
+    fn split_words_gives_up_on_anything_but_a_simple_command() {
+        for command in ["export A=1; ls", "export A=$(pwd)", "export A=`pwd`", "export A=1 | cat", "export A=1 > f", "export A='open"] {
+            assert_eq!(split_words(command), None, "{}", command);
+        }
+    }
+
+    #[test]
+    fn parses_export_and_unset() {
+        assert_eq!(parse_env_command("export A=1 B=\"x y\""), Some(EnvChange::Export(vec!["A".into(), "B".into()])));
+        assert_eq!(parse_env_command("  unset A B "), Some(EnvChange::Unset(vec!["A".into(), "B".into()])));
+        assert_eq!(parse_env_command("export -n A"), None);
+        assert_eq!(parse_env_command("export 1A=2"), None);
+        assert_eq!(parse_env_command("export"), None);
+        assert_eq!(parse_env_command("echo A=1"), None);
+    }
+
+    #[test]
+    fn bare_export_is_not_recorded() {
+        assert_eq!(parse_env_command("export HOME"), None);
+        assert_eq!(parse_env_command("export A=1 HOME"), Some(EnvChange::Export(vec!["A".into()])));
+    }
+
+    #[test]
+    fn exported_values_come_back_in_later_commands() {
+        let mut env = capture("export GREETING='hello world' QUOTE=\"it's\" EMPTY= UTF8=ünï");
+        env.extend(capture("export EXPANDED=\"$HOME/bin\""));
+        assert_eq!(env["GREETING"], "hello world");
+        assert_eq!(env["EXPANDED"], format!("{}/bin", sh("printf %s \"$HOME\"")));
+
+        let echoed = sh(&format!("{}printf '%s|' \"$GREETING\" \"$QUOTE\" \"$EMPTY\" \"$UTF8\" \"$EXPANDED\"", export_prefix(&env)));
+        assert_eq!(echoed, format!("hello world|it's||ünï|{}|", env["EXPANDED"]));
+    }
+
+    #[test]
+    fn values_with_newlines_survive() {
+        let env = HashMap::from([("MULTI".to_string(), "one\ntwo $HOME".to_string())]);
+        assert_eq!(sh(&format!("{}printf %s \"$MULTI\"", export_prefix(&env))), "one\ntwo $HOME");
+        assert_eq!(export_prefix(&HashMap::new()), "");
+    }
+}
@@ -0,0 +1,234 @@
+// Structured errors for the frontend
+//
+// Failures carry a stable `code` to switch on next to the human-readable
+// `message`. The code comes from what's in the anyhow chain: `Stage`
+// contexts attached where connecting can fail, typed errors (host keys,
+// MaxSessions), and failing that the libssh2 or I/O error underneath.
+//
+// Every command rejects with an SSHError. Failures that have nothing more
+// specific to say, such as a poisoned lock, come through as internal.
+
+use crate::{auth, health, known_hosts, MaxSessionsReached};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+
+// libssh2 error codes, from libssh2.h
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+// The socket itself stopped answering. That's a dead transport, so health.rs
+// reconnects on it and it's reported as connection_lost, unlike
+// LIBSSH2_ERROR_TIMEOUT above, a call outliving the session's timeout.
+pub const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+const LIBSSH2_ERROR_CHANNEL_ERRORS: &[i32] = &[
+    -20, // LIBSSH2_ERROR_CHANNEL_OUTOFORDER
+    -21, // LIBSSH2_ERROR_CHANNEL_FAILURE
+    -22, // LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED
+    -23, // LIBSSH2_ERROR_CHANNEL_UNKNOWN
+    -24, // LIBSSH2_ERROR_CHANNEL_WINDOW_EXCEEDED
+    -25, // LIBSSH2_ERROR_CHANNEL_PACKET_EXCEEDED
+    -26, // LIBSSH2_ERROR_CHANNEL_CLOSED
+    -27, // LIBSSH2_ERROR_CHANNEL_EOF_SENT
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum SSHErrorKind {
+    DnsResolution,
+    TcpConnect,
+    Handshake,
+    HostKeyUnknown,
+    HostKeyMismatch,
+    AuthFailed {
+        // In the order they were tried
+        methods_tried: Vec<auth::AuthMethod>,
+        // As the server advertised them, e.g. "publickey"
+        methods_offered: Vec<String>,
+    },
+    ConnectionLost,
+    Timeout,
+    ChannelError,
+    MaxSessionsReached,
+    NotConnected,
+    // A command's arguments were rejected before anything was sent
+    InvalidArgument,
+    // The command's shell couldn't find it (exit status 127)
+    CommandNotFound,
+    // sudo said no to the password it was given
+    SudoPasswordRejected,
+    // A remote file or directory that isn't there
+    PathNotFound,
+    // The server refused to read or change a remote path
+    PermissionDenied,
+    // A directory was asked for and the path is something else
+    NotADirectory,
+    Internal,
+}
+
+impl SSHErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SSHErrorKind::DnsResolution => "dns_resolution",
+            SSHErrorKind::TcpConnect => "tcp_connect",
+            SSHErrorKind::Handshake => "handshake",
+            SSHErrorKind::HostKeyUnknown => "host_key_unknown",
+            SSHErrorKind::HostKeyMismatch => "host_key_mismatch",
+            SSHErrorKind::AuthFailed { .. } => "auth_failed",
+            SSHErrorKind::ConnectionLost => "connection_lost",
+            SSHErrorKind::Timeout => "timeout",
+            SSHErrorKind::ChannelError => "channel_error",
+            SSHErrorKind::MaxSessionsReached => "max_sessions_reached",
+            SSHErrorKind::NotConnected => "not_connected",
+            SSHErrorKind::InvalidArgument => "invalid_argument",
+            SSHErrorKind::CommandNotFound => "command_not_found",
+            SSHErrorKind::SudoPasswordRejected => "sudo_password_rejected",
+            SSHErrorKind::PathNotFound => "path_not_found",
+            SSHErrorKind::PermissionDenied => "permission_denied",
+            SSHErrorKind::NotADirectory => "not_a_directory",
+            SSHErrorKind::Internal => "internal",
+        }
+    }
+}
+
+// Serializes as `{ "code": ..., "message": ..., ...variant fields }`
+#[derive(Debug, Clone, Serialize)]
+pub struct SSHError {
+    #[serde(flatten)]
+    pub kind: SSHErrorKind,
+    pub message: String,
+}
+
+impl SSHError {
+    pub fn new(kind: SSHErrorKind, message: impl Into<String>) -> Self {
+        SSHError { kind, message: message.into() }
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    // Classifies an error, with `message` as what the user sees
+    pub fn from_anyhow(e: &anyhow::Error, message: impl Into<String>) -> Self {
+        SSHError::new(classify(e), message)
+    }
+}
+
+// Failures from the Tauri layer itself (lock errors, failed tasks) and from
+// code that only produces strings
+impl From<String> for SSHError {
+    fn from(message: String) -> Self {
+        SSHError::new(SSHErrorKind::Internal, message)
+    }
+}
+
+impl fmt::Display for SSHError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// Context marking which step failed. It displays as its message, so it can
+// take the place of a plain string context.
+#[derive(Debug)]
+pub struct Stage {
+    pub kind: SSHErrorKind,
+    pub message: String,
+}
+
+impl Stage {
+    pub fn new(kind: SSHErrorKind, message: impl Into<String>) -> Self {
+        Stage { kind, message: message.into() }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Stage {}
+
+fn is_timeout(cause: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(e) = cause.downcast_ref::<ssh2::Error>() {
+        return matches!(e.code(), ssh2::ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT));
+    }
+    cause.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::TimedOut)
+}
+
+fn is_channel_error(cause: &(dyn std::error::Error + 'static)) -> bool {
+    cause.downcast_ref::<ssh2::Error>().is_some_and(|e| match e.code() {
+        ssh2::ErrorCode::Session(code) => LIBSSH2_ERROR_CHANNEL_ERRORS.contains(&code),
+        ssh2::ErrorCode::SFTP(_) => false,
+    })
+}
+
+// Markers and typed errors are looked for first (anyhow finds contexts at
+// any depth, the outermost first), then the libssh2 or I/O error underneath
+pub fn classify(e: &anyhow::Error) -> SSHErrorKind {
+    if let Some(stage) = e.downcast_ref::<Stage>() {
+        return stage.kind.clone();
+    }
+    match e.downcast_ref::<known_hosts::HostKeyError>() {
+        Some(known_hosts::HostKeyError::Unknown(_)) => return SSHErrorKind::HostKeyUnknown,
+        Some(known_hosts::HostKeyError::Mismatch(_)) => return SSHErrorKind::HostKeyMismatch,
+        None => {}
+    }
+    if e.downcast_ref::<MaxSessionsReached>().is_some() {
+        return SSHErrorKind::MaxSessionsReached;
+    }
+
+    if e.chain().any(is_timeout) {
+        SSHErrorKind::Timeout
+    } else if health::is_transport_error(e) {
+        SSHErrorKind::ConnectionLost
+    } else if e.chain().any(is_channel_error) {
+        SSHErrorKind::ChannelError
+    } else {
+        SSHErrorKind::Internal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssh_error(code: i32) -> anyhow::Error {
+        ssh2::Error::new(ssh2::ErrorCode::Session(code), "libssh2 failed").into()
+    }
+
+    fn host_key_error() -> known_hosts::HostKeyError {
+        known_hosts::HostKeyError::Mismatch(known_hosts::HostKeyInfo {
+            host: "example.com".to_string(),
+            port: 22,
+            key_type: "ssh-ed25519".to_string(),
+            fingerprint: "SHA256:abc".to_string(),
+        })
+    }
+
+    #[test]
+    fn libssh2_codes() {
+        assert_eq!(classify(&ssh_error(LIBSSH2_ERROR_TIMEOUT)), SSHErrorKind::Timeout);
+        assert_eq!(classify(&ssh_error(LIBSSH2_ERROR_SOCKET_TIMEOUT)), SSHErrorKind::ConnectionLost);
+        assert_eq!(classify(&ssh_error(-43)), SSHErrorKind::ConnectionLost);
+        assert_eq!(classify(&ssh_error(-21)), SSHErrorKind::ChannelError);
+        assert_eq!(classify(&ssh_error(-18)), SSHErrorKind::Internal);
+        assert_eq!(classify(&io::Error::from(io::ErrorKind::TimedOut).into()), SSHErrorKind::Timeout);
+    }
+
+    #[test]
+    fn markers_win_over_what_they_wrap() {
+        let auth = || Stage::new(SSHErrorKind::Handshake, "Handshake failed");
+
+        // Stage over a host key error, MaxSessions and a libssh2 code
+        let e = anyhow::Error::new(host_key_error()).context(MaxSessionsReached).context(auth());
+        assert_eq!(classify(&e), SSHErrorKind::Handshake);
+        assert_eq!(classify(&ssh_error(-43).context(auth())), SSHErrorKind::Handshake);
+
+        // A host key error over MaxSessions and the code underneath
+        let e = ssh_error(-43).context(MaxSessionsReached).context(host_key_error());
+        assert_eq!(classify(&e), SSHErrorKind::HostKeyMismatch);
+
+        // MaxSessions over the libssh2 code it came from
+        assert_eq!(classify(&ssh_error(-21).context(MaxSessionsReached)), SSHErrorKind::MaxSessionsReached);
+    }
+}
@@ -0,0 +1,217 @@
+// Remote file browsing over SFTP
+
+use crate::errors::{SSHError, SSHErrorKind};
+use crate::transfer::resolve_remote_path;
+use crate::{with_client, ConnectionsStore};
+use serde::Serialize;
+use ssh2::{ErrorCode, FileStat, Sftp};
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+// SFTP status codes from libssh2's sftp.h
+const LIBSSH2_FX_NO_SUCH_FILE: i32 = 2;
+const LIBSSH2_FX_PERMISSION_DENIED: i32 = 3;
+const LIBSSH2_FX_NO_SUCH_PATH: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+    // Sockets, devices, FIFOs
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoteEntry {
+    // Converted lossily if the name isn't valid UTF-8
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub file_type: EntryType,
+    // Whether the UI can navigate into it: directories, and symlinks that
+    // point to one
+    pub is_directory: bool,
+    // Permission bits in octal, e.g. "0755"
+    pub permissions: Option<String>,
+    // Unix time in seconds
+    pub mtime: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+fn entry_type(stat: &FileStat) -> EntryType {
+    let file_type = stat.file_type();
+    if file_type.is_symlink() {
+        EntryType::Symlink
+    } else if file_type.is_dir() {
+        EntryType::Directory
+    } else if file_type.is_file() {
+        EntryType::File
+    } else {
+        EntryType::Other
+    }
+}
+
+// Turns SFTP errors into ones that tell a missing path apart from one the
+// user isn't allowed to read
+fn sftp_error(e: ssh2::Error, path: &Path) -> SSHError {
+    match e.code() {
+        ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED) => {
+            SSHError::new(SSHErrorKind::PermissionDenied, format!("Permission denied: {}", path.display()))
+        }
+        ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_FILE) | ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_PATH) => {
+            SSHError::new(SSHErrorKind::PathNotFound, format!("Not found: {}", path.display()))
+        }
+        _ => {
+            let message = format!("Failed to read {}: {}", path.display(), e);
+            SSHError::from_anyhow(&e.into(), message)
+        }
+    }
+}
+
+fn read_directory(sftp: &Sftp, path: &Path) -> Result<Vec<RemoteEntry>, SSHError> {
+    let listing = sftp.readdir(path).map_err(|e| {
+        // Servers usually report a file as missing, so look at what's there
+        match sftp.stat(path) {
+            Ok(stat) if !stat.is_dir() => {
+                SSHError::new(SSHErrorKind::NotADirectory, format!("Not a directory: {}", path.display()))
+            }
+            _ => sftp_error(e, path),
+        }
+    })?;
+
+    let mut entries: Vec<RemoteEntry> = listing.into_iter()
+        .map(|(entry_path, stat)| {
+            let file_type = entry_type(&stat);
+            let is_directory = match file_type {
+                EntryType::Directory => true,
+                // stat follows the link; a broken one just isn't navigable
+                EntryType::Symlink => sftp.stat(&entry_path).map(|s| s.is_dir()).unwrap_or(false),
+                _ => false,
+            };
+
+            RemoteEntry {
+                name: entry_path.file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                path: entry_path.to_string_lossy().into_owned(),
+                size: stat.size.unwrap_or(0),
+                file_type,
+                is_directory,
+                permissions: stat.perm.map(|p| format!("{:04o}", p & 0o7777)),
+                mtime: stat.mtime,
+                uid: stat.uid,
+                gid: stat.gid,
+            }
+        })
+        .collect();
+
+    // Directories first, then by name, the way file managers show them
+    entries.sort_by(|a, b| b.is_directory.cmp(&a.is_directory).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+// Lists a remote directory for the file browser: each entry's name, type,
+// size, permission bits, mtime and owner, directories first. Relative paths
+// are resolved against the connection's current directory, and an empty
+// path lists the current directory itself. Fails with `path_not_found`,
+// `permission_denied` or `not_a_directory` when the path can't be listed.
+#[tauri::command]
+pub async fn sftp_list_dir(
+    connection_id: String,
+    remote_path: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<Vec<RemoteEntry>, SSHError> {
+    // The listing's own error rides inside with_client's, which is only
+    // for lock and task failures
+    with_client(&connections, &connection_id, move |client| {
+        let path = if remote_path.is_empty() {
+            PathBuf::from(&client.current_directory)
+        } else {
+            resolve_remote_path(&client.current_directory, &remote_path)
+        };
+
+        Ok(match client.session.sftp() {
+            Ok(sftp) => read_directory(&sftp, &path),
+            Err(e) => {
+                let message = format!("Failed to start SFTP subsystem: {}", e);
+                Err(SSHError::from_anyhow(&e.into(), message))
+            }
+        })
+    })
+    .await?
+}
+
+// The name sftp_list_dir had first, kept for existing callers
+#[tauri::command]
+pub async fn list_remote_directory(
+    connection_id: String,
+    path: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<Vec<RemoteEntry>, SSHError> {
+    sftp_list_dir(connection_id, path, connections).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn sftp_errors_keep_their_kind() {
+        let path = Path::new("/srv/x");
+        for (code, kind) in [
+            (LIBSSH2_FX_PERMISSION_DENIED, SSHErrorKind::PermissionDenied),
+            (LIBSSH2_FX_NO_SUCH_FILE, SSHErrorKind::PathNotFound),
+            (LIBSSH2_FX_NO_SUCH_PATH, SSHErrorKind::PathNotFound),
+            (4, SSHErrorKind::Internal),
+        ] {
+            let e = sftp_error(ssh2::Error::new(ErrorCode::SFTP(code), "failure"), path);
+            assert_eq!(e.kind, kind, "{}", code);
+            assert!(e.message.contains("/srv/x"), "{}", e.message);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn lists_a_known_layout() {
+        let config = test_support::test_config();
+        let mut client = test_support::connect(&config);
+        let root = test_support::run(&mut client, "mktemp -d");
+        test_support::run(&mut client, &format!(
+            "cd {} && mkdir 'sub dir' && chmod 750 'sub dir' && printf 12345 > file.txt && chmod 640 file.txt \
+             && touch -d @1700000000 file.txt && ln -s 'sub dir' to-dir && ln -s missing broken",
+            root
+        ));
+
+        let sftp = client.session.sftp().unwrap();
+        let entries = read_directory(&sftp, Path::new(&root)).unwrap();
+        let summary: Vec<_> = entries.iter()
+            .map(|e| (e.name.as_str(), e.file_type, e.is_directory, e.permissions.as_deref()))
+            .collect();
+        assert_eq!(summary, [
+            ("sub dir", EntryType::Directory, true, Some("0750")),
+            ("to-dir", EntryType::Symlink, true, Some("0777")),
+            ("broken", EntryType::Symlink, false, Some("0777")),
+            ("file.txt", EntryType::File, false, Some("0640")),
+        ]);
+        let file = &entries[3];
+        assert_eq!((file.size, file.mtime), (5, Some(1_700_000_000)));
+        assert_eq!(file.path, format!("{}/file.txt", root));
+
+        let e = read_directory(&sftp, &Path::new(&root).join("file.txt")).unwrap_err();
+        assert_eq!(e.kind, SSHErrorKind::NotADirectory);
+        let e = read_directory(&sftp, &Path::new(&root).join("nope")).unwrap_err();
+        assert_eq!(e.kind, SSHErrorKind::PathNotFound);
+        // root reads it anyway
+        test_support::run(&mut client, &format!("chmod 000 {}/'sub dir'", root));
+        if test_support::run(&mut client, "id -u") != "0" {
+            let e = read_directory(&sftp, &Path::new(&root).join("sub dir")).unwrap_err();
+            assert_eq!(e.kind, SSHErrorKind::PermissionDenied);
+        }
+
+        test_support::run(&mut client, &format!("chmod 750 {0}/'sub dir'; rm -rf {0}", root));
+    }
+}
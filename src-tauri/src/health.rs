@@ -0,0 +1,333 @@
+// Keepalives and dead-connection detection
+//
+// A connection whose network went away (laptop asleep, Wi-Fi dropped) stays
+// in the store looking healthy until something tries to use it. Keepalives
+// find those connections in the background, and commands that fail on a dead
+// transport report `disconnected` instead of a bare libssh2 error. Either
+// way the connection logs in again with its original config if it can
+// (emitting `ssh-reconnected`), and otherwise is dropped from the store with
+// `ssh-disconnected` emitted, so the frontend can update its list.
+
+use crate::{auth, errors, with_client, CancelFlags, ConnectionLabels, ConnectionsStore, SSHClient};
+use serde::Serialize;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+// How often the keepalive thread checks whether any connection is due one.
+// libssh2 itself only sends when a connection's interval has passed.
+const KEEPALIVE_TICK: Duration = Duration::from_secs(5);
+// How long check_connection_health waits for the server to answer
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// libssh2 error codes that mean the transport itself has failed. Not
+// LIBSSH2_ERROR_TIMEOUT (-9): that's a blocking call outliving the session
+// timeout, e.g. a slow command, and errors.rs reports it as a timeout.
+const TRANSPORT_ERROR_CODES: &[i32] = &[
+    -7,  // LIBSSH2_ERROR_SOCKET_SEND
+    -13, // LIBSSH2_ERROR_SOCKET_DISCONNECT
+    errors::LIBSSH2_ERROR_SOCKET_TIMEOUT,
+    -43, // LIBSSH2_ERROR_SOCKET_RECV
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DisconnectedEvent {
+    pub connection_id: String,
+    pub reason: String,
+}
+
+// Payload of the `ssh-reconnected` event
+#[derive(Debug, Clone, Serialize)]
+pub struct ReconnectedEvent {
+    pub connection_id: String,
+    // Set when the working directory couldn't be restored
+    pub warning: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionHealth {
+    pub alive: bool,
+    // Round trip of a trivial command, when the connection answered
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+fn is_transport_ssh_error(e: &ssh2::Error) -> bool {
+    match e.code() {
+        ssh2::ErrorCode::Session(code) => TRANSPORT_ERROR_CODES.contains(&code),
+        ssh2::ErrorCode::SFTP(_) => false,
+    }
+}
+
+fn is_transport_io_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    ) || e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<ssh2::Error>())
+        .is_some_and(is_transport_ssh_error)
+}
+
+// ssh2 turns its errors into io::Errors holding only the message, so for
+// channel reads and writes the libssh2 code is taken back from the session,
+// letting is_transport_error and errors::classify see it
+pub fn with_session_code(session: &ssh2::Session, e: io::Error) -> anyhow::Error {
+    match ssh2::Error::last_session_error(session) {
+        Some(ssh) if e.kind() == io::ErrorKind::Other && ssh.message() == e.to_string() => {
+            anyhow::Error::new(ssh).context(e.to_string())
+        }
+        _ => e.into(),
+    }
+}
+
+// Whether an error means the connection is dead, rather than that one
+// command or request failed
+pub fn is_transport_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause.downcast_ref::<ssh2::Error>().is_some_and(is_transport_ssh_error)
+            || cause.downcast_ref::<io::Error>().is_some_and(is_transport_io_error)
+    })
+}
+
+// Drops a dead connection from the store and tells the frontend. The
+// connection's own lock may still be held by the caller.
+pub fn remove_dead_connection(app: &AppHandle, connection_id: &str, reason: String) {
+    if let Ok(mut cancel_flags) = app.state::<CancelFlags>().lock() {
+        if let Some(cancels) = cancel_flags.remove(connection_id) {
+            cancels.cancel_all();
+        }
+    }
+    if let Ok(mut labels) = app.state::<ConnectionLabels>().lock() {
+        labels.remove(connection_id);
+    }
+    let removed = app.state::<ConnectionsStore>().lock()
+        .map(|mut connections| connections.remove(connection_id).is_some())
+        .unwrap_or(false);
+
+    if removed {
+        let _ = app.emit("ssh-disconnected", DisconnectedEvent {
+            connection_id: connection_id.to_string(),
+            reason,
+        });
+    }
+}
+
+// Logs a dead connection back in with its original config, unless it opted
+// out. Returns whether it's usable again; if not, the caller drops it.
+pub fn try_reconnect(app: &AppHandle, connection_id: &str, client: &mut SSHClient) -> bool {
+    if !client.auto_reconnect() {
+        return false;
+    }
+    match client.reconnect(&mut auth::NoPrompter) {
+        Ok(warning) => {
+            let _ = app.emit("ssh-reconnected", ReconnectedEvent {
+                connection_id: connection_id.to_string(),
+                warning,
+            });
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+// Sends keepalives on every idle connection for as long as the app runs.
+// Busy connections are skipped: whatever holds their lock is using the
+// transport anyway, and will notice if it's dead.
+pub fn spawn_keepalive_thread(app: AppHandle) {
+    thread::spawn(move || loop {
+        thread::sleep(KEEPALIVE_TICK);
+
+        let clients: Vec<_> = match app.state::<ConnectionsStore>().lock() {
+            Ok(connections) => connections.iter().map(|(id, c)| (id.clone(), c.clone())).collect(),
+            Err(_) => continue,
+        };
+
+        for (connection_id, client) in clients {
+            let Ok(mut client) = client.try_lock() else { continue };
+            if let Err(e) = client.session.keepalive_send() {
+                if is_transport_ssh_error(&e) && !try_reconnect(&app, &connection_id, &mut client) {
+                    remove_dead_connection(&app, &connection_id, format!("Keepalive failed: {}", e));
+                }
+            }
+        }
+    });
+}
+
+// Checks that the server still answers by running a trivial command, and
+// reports how long the round trip took. A dead connection is dropped.
+#[tauri::command]
+pub async fn check_connection_health(
+    app: AppHandle,
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ConnectionHealth, errors::SSHError> {
+    let id = connection_id.clone();
+    with_client(&connections, &connection_id, move |client| {
+        let started = Instant::now();
+        let previous_timeout = client.session.timeout();
+        client.session.set_timeout(HEALTH_CHECK_TIMEOUT.as_millis() as u32);
+        let result = (|| -> anyhow::Result<()> {
+            let mut channel = client.open_channel()?;
+            channel.exec("true")?;
+            channel.wait_close()?;
+            Ok(())
+        })();
+        client.session.set_timeout(previous_timeout);
+
+        match result {
+            Ok(()) => Ok(ConnectionHealth {
+                alive: true,
+                latency_ms: Some(started.elapsed().as_millis() as u64),
+                error: None,
+            }),
+            Err(e) => {
+                let alive = !is_transport_error(&e) || try_reconnect(&app, &id, client);
+                if !alive {
+                    remove_dead_connection(&app, &id, format!("Health check failed: {}", e));
+                }
+                Ok(ConnectionHealth {
+                    alive,
+                    latency_ms: None,
+                    error: Some(e.to_string()),
+                })
+            }
+        }
+    })
+    .await
+}
+
+// Sends a keepalive now and waits for the server to answer something,
+// returning the seconds until the next keepalive is due.
+//
+// libssh2 only reads the server's reply to a keepalive during some later
+// call, and a write to a peer that has gone away usually succeeds once, so
+// the send alone rarely notices a dead link. Opening (and closing) a channel
+// makes the round trip here; a blocking libssh2 call is what reads the
+// socket and sees it closed or reset.
+fn probe_keepalive(client: &mut SSHClient) -> anyhow::Result<u32> {
+    // A stalled socket would otherwise wait out the command timeout
+    let previous_timeout = client.session.timeout();
+    client.session.set_timeout(HEALTH_CHECK_TIMEOUT.as_millis() as u32);
+    let result = (|| -> anyhow::Result<u32> {
+        let next = client.session.keepalive_send()?;
+        let mut channel = client.open_channel()?;
+        channel.close()?;
+        Ok(next)
+    })();
+    client.session.set_timeout(previous_timeout);
+    result
+}
+
+// Sends one keepalive now, without waiting for the background thread, and
+// returns the seconds until the next one is due. A dead connection is
+// logged back in if it can be, and otherwise dropped and reported as an
+// error.
+#[tauri::command]
+pub async fn send_keepalive(
+    app: AppHandle,
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<u32, errors::SSHError> {
+    let id = connection_id.clone();
+    with_client(&connections, &connection_id, move |client| match probe_keepalive(client) {
+        Ok(next) => Ok(next),
+        Err(e) if is_transport_error(&e) => {
+            if try_reconnect(&app, &id, client) {
+                return probe_keepalive(client).map_err(|e| format!("Keepalive failed: {}", e));
+            }
+            let message = format!("Connection lost: {}", e);
+            remove_dead_connection(&app, &id, message.clone());
+            Err(message)
+        }
+        Err(e) => Err(format!("Keepalive failed: {}", e)),
+    })
+    .await
+}
+
+// Replaces the connection's session with a freshly authenticated one, keeping
+// its ID, working directory and session env. Unlike the automatic reconnect,
+// keyboard-interactive prompts go to the frontend, so it works for logins
+// that need an OTP. `clear_env` starts the new session without the session
+// env. Returns a warning if the working directory couldn't be restored.
+#[tauri::command]
+pub async fn reconnect(
+    app: AppHandle,
+    connection_id: String,
+    clear_env: Option<bool>,
+    connections: State<'_, ConnectionsStore>,
+    pending_prompts: State<'_, auth::PendingPrompts>,
+) -> Result<Option<String>, errors::SSHError> {
+    let pending_prompts = pending_prompts.inner().clone();
+    let id = connection_id.clone();
+    with_client(&connections, &connection_id, move |client| {
+        let mut prompter = auth::FrontendPrompter::new(&app, &pending_prompts);
+        let warning = client.reconnect(&mut prompter).map_err(|e| format!("Reconnect failed: {}", e))?;
+        if clear_env.unwrap_or(false) {
+            client.session_env.clear();
+        }
+        let _ = app.emit("ssh-reconnected", ReconnectedEvent { connection_id: id, warning: warning.clone() });
+        Ok(warning)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssh_error(code: i32) -> ssh2::Error {
+        ssh2::Error::new(ssh2::ErrorCode::Session(code), "test")
+    }
+
+    #[test]
+    fn session_timeout_is_not_a_dead_transport() {
+        let e = anyhow::Error::new(ssh_error(-9));
+        assert!(!is_transport_error(&e));
+        assert_eq!(crate::errors::classify(&e), crate::errors::SSHErrorKind::Timeout);
+    }
+
+    #[test]
+    fn socket_errors_are_a_dead_transport() {
+        for code in TRANSPORT_ERROR_CODES {
+            assert!(is_transport_error(&anyhow::Error::new(ssh_error(*code))), "{}", code);
+            let wrapped = io::Error::other(ssh_error(*code));
+            assert!(is_transport_error(&anyhow::Error::new(wrapped).context("Command execution failed")), "{}", code);
+        }
+        assert!(is_transport_error(&io::Error::from(io::ErrorKind::BrokenPipe).into()));
+        assert!(!is_transport_error(&anyhow::Error::new(ssh_error(-22))));
+    }
+
+    #[test]
+    fn channel_errors_from_another_source_are_left_alone() {
+        // What a channel read reports: the message without the code
+        let e = io::Error::from(ssh_error(-43));
+        assert_eq!(e.kind(), io::ErrorKind::Other);
+
+        // A session that never connected has no error of its own to match it
+        let session = ssh2::Session::new().unwrap();
+        let e = with_session_code(&session, e);
+        assert_eq!(e.to_string(), "test");
+        assert!(e.downcast_ref::<ssh2::Error>().is_none());
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn keepalive_probe_notices_a_dropped_connection() {
+        let config = crate::test_support::test_config();
+        let mut client = crate::test_support::connect(&config);
+        assert!(probe_keepalive(&mut client).is_ok());
+
+        // Kills the server's process for this connection, closing its socket
+        let _ = client.execute_command("kill -9 $PPID", None);
+        let e = probe_keepalive(&mut client).unwrap_err();
+        assert!(is_transport_error(&e), "{:#}", e);
+
+        assert!(client.reconnect(&mut auth::NoPrompter).is_ok());
+        assert!(probe_keepalive(&mut client).is_ok());
+    }
+}
@@ -0,0 +1,103 @@
+// Connections through a jump (bastion) host, like `ssh -J`
+//
+// libssh2 can only run a session over a real socket, so the target's session
+// gets one end of a loopback connection, and a thread carries the bytes
+// between the other end and a direct-tcpip channel on the jump host's
+// session. Dropping the JumpTunnel stops that thread, which closes the jump
+// host's session with it.
+
+use crate::known_hosts::HostKeyInfo;
+use crate::tunnel::{Forwarded, TunnelCounters, TUNNEL_POLL_INTERVAL};
+use crate::{normalize_host, SSHClient, SSHConnectionConfig};
+use anyhow::{Context, Result};
+use ssh2::KeyboardInteractivePrompt;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Keeps the jump host's session alive for as long as the target's needs it
+pub struct JumpTunnel {
+    stop: Arc<AtomicBool>,
+    // Host keys recorded on first use while connecting to the bastions
+    pub pinned_host_keys: Vec<HostKeyInfo>,
+}
+
+impl Drop for JumpTunnel {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+// A connected pair of loopback sockets
+fn socket_pair() -> Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let ours = TcpStream::connect(listener.local_addr()?)?;
+
+    // Skip anything else on this machine that connected in the meantime
+    loop {
+        let (theirs, peer) = listener.accept()?;
+        if peer == ours.local_addr()? {
+            return Ok((ours, theirs));
+        }
+    }
+}
+
+// Carries the target's traffic until either side closes or the tunnel is
+// dropped. This thread is the jump host's session's only user, so it can
+// stay in non-blocking mode throughout.
+fn pump_jump(jump: SSHClient, mut forwarded: Forwarded, stop: Arc<AtomicBool>) {
+    let counters = TunnelCounters::default();
+    jump.session.set_blocking(false);
+
+    while !stop.load(Ordering::SeqCst) && !forwarded.is_done() {
+        // Nothing else sends the jump host's keepalives
+        let _ = jump.session.keepalive_send();
+
+        match forwarded.pump(&counters) {
+            Ok(true) => {}
+            Ok(false) => thread::sleep(TUNNEL_POLL_INTERVAL),
+            Err(_) => break,
+        }
+    }
+
+    // The channel talks to the server when dropped, so do that while blocking
+    jump.session.set_blocking(true);
+    drop(forwarded);
+}
+
+// Connects and authenticates to the jump host, then opens a channel through
+// it to host:port. Returns the socket to run the target's session over.
+pub fn connect_through(
+    jump: &SSHConnectionConfig,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+    prompter: &mut impl KeyboardInteractivePrompt,
+) -> Result<(TcpStream, JumpTunnel)> {
+    let bastion = format!("{}:{}", jump.host, jump.port);
+
+    let mut client = SSHClient::new(jump, prompter)
+        .with_context(|| format!("Couldn't reach bastion {}", bastion))?;
+    client.authenticate(jump, prompter)
+        .with_context(|| format!("Bastion authentication failed on {}", bastion))?;
+
+    // Opening the channel is part of connecting, so it gets the connect timeout
+    let previous_timeout = client.session.timeout();
+    client.session.set_timeout(timeout.as_millis().min(u32::MAX as u128) as u32);
+    let channel = client.session.channel_direct_tcpip(normalize_host(host), port, None)
+        .with_context(|| format!("Couldn't reach {}:{} through bastion {}", host, port, bastion));
+    client.session.set_timeout(previous_timeout);
+    let channel = channel?;
+
+    let (ours, theirs) = socket_pair().context("Failed to set up the connection through the bastion")?;
+    let forwarded = Forwarded::new(theirs, channel)?;
+
+    let pinned_host_keys = std::mem::take(&mut client.pinned_host_keys);
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    thread::spawn(move || pump_jump(client, forwarded, thread_stop));
+
+    Ok((ours, JumpTunnel { stop, pinned_host_keys }))
+}
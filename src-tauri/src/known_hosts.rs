@@ -0,0 +1,305 @@
+// Host key verification against the user's known_hosts file
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::prelude::{Engine as _, BASE64_STANDARD_NO_PAD};
+use serde::Serialize;
+use ssh2::{CheckResult, HostKeyType, KnownHostFileKind, Session};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// The server's host key as shown to the user when asking whether to trust it
+#[derive(Debug, Clone, Serialize)]
+pub struct HostKeyInfo {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    // SHA256 fingerprint in the same format OpenSSH prints, e.g. "SHA256:nThb..."
+    pub fingerprint: String,
+}
+
+#[derive(Debug)]
+pub enum HostKeyError {
+    // The server has no entry in known_hosts yet
+    Unknown(HostKeyInfo),
+    // The server's key differs from the one on record, which may mean someone
+    // is intercepting the connection
+    Mismatch(HostKeyInfo),
+}
+
+impl HostKeyError {
+    pub fn host_key(&self) -> &HostKeyInfo {
+        match self {
+            HostKeyError::Unknown(info) | HostKeyError::Mismatch(info) => info,
+        }
+    }
+}
+
+impl fmt::Display for HostKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyError::Unknown(info) => write!(
+                f,
+                "Host key for {} is not in known_hosts ({} key {})",
+                host_entry_name(&info.host, info.port), info.key_type, info.fingerprint
+            ),
+            HostKeyError::Mismatch(info) => write!(
+                f,
+                "Host key for {} does not match known_hosts (server sent {} key {}). \
+                 Someone could be intercepting the connection",
+                host_entry_name(&info.host, info.port), info.key_type, info.fingerprint
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HostKeyError {}
+
+fn known_hosts_path() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .context("Could not determine home directory")?;
+
+    Ok(PathBuf::from(home).join(".ssh").join("known_hosts"))
+}
+
+fn key_type_name(key_type: HostKeyType) -> &'static str {
+    match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => "unknown",
+    }
+}
+
+// A server's host key as libssh2 hands it over after the handshake
+struct HostKey<'a> {
+    blob: &'a [u8],
+    key_type: HostKeyType,
+}
+
+fn session_host_key(session: &Session) -> Result<HostKey<'_>> {
+    let (blob, key_type) = session.host_key().context("Server did not provide a host key")?;
+    Ok(HostKey { blob, key_type })
+}
+
+fn key_info(key: &HostKey, host: &str, port: u16) -> HostKeyInfo {
+    // The same hash libssh2's host_key_hash gives, over the raw key blob
+    let hash = openssl::sha::sha256(key.blob);
+
+    HostKeyInfo {
+        host: host.to_string(),
+        port,
+        key_type: key_type_name(key.key_type).to_string(),
+        fingerprint: format!("SHA256:{}", BASE64_STANDARD_NO_PAD.encode(hash)),
+    }
+}
+
+pub fn host_key_info(session: &Session, host: &str, port: u16) -> Result<HostKeyInfo> {
+    Ok(key_info(&session_host_key(session)?, host, port))
+}
+
+// known_hosts only uses the bare host name for the default port
+fn host_entry_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+// Appends the host key to known_hosts. We only ever append a single line,
+// rather than rewriting the whole file, so entries libssh2 doesn't
+// understand are left untouched.
+fn add_host_key(session: &Session, path: &Path, key: &HostKey, host: &str, port: u16) -> Result<()> {
+    let mut known_hosts = session.known_hosts()?;
+    known_hosts.add(&host_entry_name(host, port), key.blob, "added by AetherSSH", key.key_type.into())?;
+    let entry = known_hosts.hosts()?
+        .into_iter()
+        .next()
+        .context("Failed to build known_hosts entry")?;
+    let line = known_hosts.write_string(&entry, KnownHostFileKind::OpenSSH)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Failed to create ~/.ssh")?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Failed to open known_hosts")?;
+    writeln!(file, "{}", line.trim_end()).context("Failed to write known_hosts")?;
+
+    Ok(())
+}
+
+fn check_host_key(session: &Session, path: &Path, key: &HostKey, host: &str, port: u16) -> Result<CheckResult> {
+    let mut known_hosts = session.known_hosts()?;
+    if path.exists() {
+        known_hosts.read_file(path, KnownHostFileKind::OpenSSH)
+            .context("Failed to read known_hosts")?;
+    }
+
+    match known_hosts.check_port(host, port, key.blob) {
+        CheckResult::Failure => Err(anyhow!("Failed to check the host key for {}", host_entry_name(host, port))),
+        result => Ok(result),
+    }
+}
+
+// Checks the server's host key right after the handshake. Unknown hosts are
+// rejected unless `accept_new` is set, in which case their key is recorded
+// and returned; a changed key is always rejected.
+pub fn verify_host_key(session: &Session, host: &str, port: u16, accept_new: bool) -> Result<Option<HostKeyInfo>> {
+    verify_key(session, &known_hosts_path()?, &session_host_key(session)?, host, port, accept_new)
+}
+
+fn verify_key(
+    session: &Session,
+    path: &Path,
+    key: &HostKey,
+    host: &str,
+    port: u16,
+    accept_new: bool,
+) -> Result<Option<HostKeyInfo>> {
+    match check_host_key(session, path, key, host, port)? {
+        CheckResult::Match => Ok(None),
+        CheckResult::NotFound if accept_new => {
+            add_host_key(session, path, key, host, port)?;
+            Ok(Some(key_info(key, host, port)))
+        }
+        CheckResult::NotFound => Err(HostKeyError::Unknown(key_info(key, host, port)).into()),
+        _ => Err(HostKeyError::Mismatch(key_info(key, host, port)).into()),
+    }
+}
+
+// Saves the key of a host the user has chosen to trust. The fingerprint must
+// be the one the user was shown, so a key that changed in between isn't saved
+// by mistake, and a host whose key conflicts with known_hosts is never updated.
+pub fn trust_host_key(session: &Session, host: &str, port: u16, fingerprint: &str) -> Result<HostKeyInfo> {
+    trust_key(session, &known_hosts_path()?, &session_host_key(session)?, host, port, fingerprint)
+}
+
+fn trust_key(
+    session: &Session,
+    path: &Path,
+    key: &HostKey,
+    host: &str,
+    port: u16,
+    fingerprint: &str,
+) -> Result<HostKeyInfo> {
+    let info = key_info(key, host, port);
+
+    match check_host_key(session, path, key, host, port)? {
+        CheckResult::Match => Ok(info),
+        CheckResult::NotFound => {
+            if info.fingerprint != fingerprint {
+                bail!(
+                    "The host key for {} changed since it was shown (now {})",
+                    host_entry_name(host, port), info.fingerprint
+                );
+            }
+            add_host_key(session, path, key, host, port)?;
+            Ok(info)
+        }
+        _ => Err(HostKeyError::Mismatch(info).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ed25519 public key blob as servers send it, made of `byte` repeated
+    fn ed25519_blob(byte: u8) -> Vec<u8> {
+        let mut blob = Vec::new();
+        for part in [b"ssh-ed25519".as_slice(), &[byte; 32]] {
+            blob.extend((part.len() as u32).to_be_bytes());
+            blob.extend(part);
+        }
+        blob
+    }
+
+    // A fresh path for a known_hosts file that doesn't exist yet
+    fn temp_known_hosts(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aetherssh-known-hosts-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(".ssh").join("known_hosts")
+    }
+
+    #[test]
+    fn unknown_host_is_rejected_without_accept_new() {
+        let session = Session::new().unwrap();
+        let path = temp_known_hosts("unknown");
+        let blob = ed25519_blob(1);
+        let key = HostKey { blob: &blob, key_type: HostKeyType::Ed25519 };
+
+        let e = verify_key(&session, &path, &key, "example.com", 22, false).unwrap_err();
+        match e.downcast_ref::<HostKeyError>() {
+            Some(HostKeyError::Unknown(info)) => {
+                assert_eq!(info.key_type, "ssh-ed25519");
+                assert!(info.fingerprint.starts_with("SHA256:"));
+            }
+            other => panic!("expected Unknown, got {:?}", other),
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn accepted_host_is_recorded_and_matches_after() {
+        let session = Session::new().unwrap();
+        let path = temp_known_hosts("accept");
+        let blob = ed25519_blob(2);
+        let key = HostKey { blob: &blob, key_type: HostKeyType::Ed25519 };
+
+        let added = verify_key(&session, &path, &key, "example.com", 2222, true).unwrap();
+        assert!(added.is_some());
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("[example.com]:2222 ssh-ed25519 "), "{}", contents);
+
+        assert!(verify_key(&session, &path, &key, "example.com", 2222, false).unwrap().is_none());
+        // The entry is for port 2222 only
+        let e = verify_key(&session, &path, &key, "example.com", 22, false).unwrap_err();
+        assert!(matches!(e.downcast_ref::<HostKeyError>(), Some(HostKeyError::Unknown(_))));
+    }
+
+    #[test]
+    fn changed_key_is_never_accepted() {
+        let session = Session::new().unwrap();
+        let path = temp_known_hosts("mismatch");
+        let (old_blob, new_blob) = (ed25519_blob(3), ed25519_blob(4));
+        let old = HostKey { blob: &old_blob, key_type: HostKeyType::Ed25519 };
+        let new = HostKey { blob: &new_blob, key_type: HostKeyType::Ed25519 };
+        verify_key(&session, &path, &old, "example.com", 22, true).unwrap();
+        let before = fs::read_to_string(&path).unwrap();
+
+        let e = verify_key(&session, &path, &new, "example.com", 22, true).unwrap_err();
+        assert!(matches!(e.downcast_ref::<HostKeyError>(), Some(HostKeyError::Mismatch(_))));
+
+        // Not even when the user trusts the exact key they were shown
+        let fingerprint = key_info(&new, "example.com", 22).fingerprint;
+        let e = trust_key(&session, &path, &new, "example.com", 22, &fingerprint).unwrap_err();
+        assert!(matches!(e.downcast_ref::<HostKeyError>(), Some(HostKeyError::Mismatch(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn trust_requires_the_fingerprint_shown() {
+        let session = Session::new().unwrap();
+        let path = temp_known_hosts("trust");
+        let (shown_blob, blob) = (ed25519_blob(5), ed25519_blob(6));
+        let shown = key_info(&HostKey { blob: &shown_blob, key_type: HostKeyType::Ed25519 }, "example.com", 22);
+        let key = HostKey { blob: &blob, key_type: HostKeyType::Ed25519 };
+
+        assert!(trust_key(&session, &path, &key, "example.com", 22, &shown.fingerprint).is_err());
+        assert!(!path.exists());
+
+        let fingerprint = key_info(&key, "example.com", 22).fingerprint;
+        trust_key(&session, &path, &key, "example.com", 22, &fingerprint).unwrap();
+        assert!(verify_key(&session, &path, &key, "example.com", 22, false).unwrap().is_none());
+    }
+}
@@ -0,0 +1,166 @@
+// Resource limits for runaway commands
+//
+// Like priority.rs, the command's shell applies the limits to itself with
+// ulimit before running anything, so everything the command starts inherits
+// them. Running out of CPU time or file size ends the process with SIGXCPU or
+// SIGXFSZ, which is how a hit limit is recognised afterwards. Going over the
+// memory limit only makes allocations fail, which each program handles its
+// own way, so that one can't be reported reliably.
+
+use crate::CommandResult;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+// SIGXCPU and SIGXFSZ are 24 and 25 on Linux, the BSDs and macOS, so a
+// shell reports a child they killed with these statuses
+const XCPU_EXIT_STATUS: i32 = 128 + 24;
+const XFSZ_EXIT_STATUS: i32 = 128 + 25;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ResourceLimits {
+    // Virtual memory, as `ulimit -v`
+    pub max_memory_kb: Option<u64>,
+    // CPU time, as `ulimit -t`
+    pub max_cpu_secs: Option<u64>,
+    // Largest file the command may write, as `ulimit -f`
+    pub max_file_size_kb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitExceeded {
+    CpuTime,
+    FileSize,
+}
+
+impl ResourceLimits {
+    pub fn validate(&self) -> Result<()> {
+        let limits = [
+            ("max_memory_kb", self.max_memory_kb),
+            ("max_cpu_secs", self.max_cpu_secs),
+            ("max_file_size_kb", self.max_file_size_kb),
+        ];
+        for (name, limit) in limits {
+            if limit == Some(0) {
+                bail!("{} must be greater than 0", name);
+            }
+        }
+        Ok(())
+    }
+
+    // ulimit calls for the current shell, each exiting it on failure, in the
+    // same form as CommandPriority::shell_prefix
+    pub fn shell_prefix(&self) -> String {
+        let mut steps = Vec::new();
+
+        if let Some(kb) = self.max_memory_kb {
+            steps.push(format!("ulimit -v {} || exit", kb));
+        }
+        if let Some(secs) = self.max_cpu_secs {
+            // At the soft limit the kernel sends SIGXCPU, at the hard one
+            // SIGKILL; a second's gap keeps the reason visible
+            steps.push(format!("ulimit -S -t {} || exit", secs));
+            steps.push(format!("ulimit -H -t {} 2>/dev/null", secs.saturating_add(1)));
+        }
+        if let Some(kb) = self.max_file_size_kb {
+            // bash counts -f in 1024-byte blocks, other shells in 512-byte ones
+            steps.push(format!(
+                "if [ -n \"$BASH_VERSION\" ]; then ulimit -f {} || exit; else ulimit -f {} || exit; fi",
+                kb,
+                kb.saturating_mul(2)
+            ));
+        }
+
+        steps.into_iter().map(|step| format!("{}; ", step)).collect()
+    }
+
+    // Which limit ended the command, if one did. The signal arrives either
+    // as the command's own exit signal (when the shell exec'd it) or
+    // through the shell's exit status.
+    pub fn exceeded(&self, result: &CommandResult) -> Option<LimitExceeded> {
+        let signal = result.exit_signal.as_deref();
+        if self.max_cpu_secs.is_some() && (signal == Some("XCPU") || result.exit_status == XCPU_EXIT_STATUS) {
+            Some(LimitExceeded::CpuTime)
+        } else if self.max_file_size_kb.is_some() && (signal == Some("XFSZ") || result.exit_status == XFSZ_EXIT_STATUS) {
+            Some(LimitExceeded::FileSize)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn limits(memory: Option<u64>, cpu: Option<u64>, file: Option<u64>) -> ResourceLimits {
+        ResourceLimits { max_memory_kb: memory, max_cpu_secs: cpu, max_file_size_kb: file }
+    }
+
+    // What `shell` reports for the limits after running the prefix
+    fn applied(shell: &str, limits: &ResourceLimits) -> String {
+        let script = format!("{}ulimit -v; ulimit -S -t; ulimit -H -t; ulimit -f", limits.shell_prefix());
+        let output = Command::new(shell).arg("-c").arg(&script).output().expect(shell);
+        assert!(output.status.success(), "{}: {}", shell, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn prefix_sets_each_limit() {
+        assert_eq!(limits(None, None, None).shell_prefix(), "");
+        assert_eq!(
+            limits(Some(1024), Some(5), None).shell_prefix(),
+            "ulimit -v 1024 || exit; ulimit -S -t 5 || exit; ulimit -H -t 6 2>/dev/null; "
+        );
+
+        let limits = limits(Some(4_000_000), Some(30), Some(2048));
+        for shell in ["sh", "bash"] {
+            // -f is in the shell's own blocks, so both should show the same
+            // size once converted back
+            let blocks = if shell == "bash" { 2048 } else { 4096 };
+            assert_eq!(applied(shell, &limits), format!("4000000\n30\n31\n{}\n", blocks), "{}", shell);
+        }
+    }
+
+    #[test]
+    fn zero_limits_are_rejected() {
+        assert!(limits(Some(1), Some(1), Some(1)).validate().is_ok());
+        assert!(limits(None, Some(0), None).validate().is_err());
+        assert!(limits(None, None, Some(0)).validate().is_err());
+    }
+
+    #[test]
+    fn exceeded_limits_are_recognised() {
+        let result = |status: i32, signal: Option<&str>| {
+            let mut result = CommandResult::from_output(Vec::new(), Vec::new(), status, String::new());
+            result.exit_signal = signal.map(str::to_string);
+            result
+        };
+        let both = limits(None, Some(1), Some(1));
+
+        assert_eq!(both.exceeded(&result(152, None)), Some(LimitExceeded::CpuTime));
+        assert_eq!(both.exceeded(&result(152, Some("XCPU"))), Some(LimitExceeded::CpuTime));
+        assert_eq!(both.exceeded(&result(153, None)), Some(LimitExceeded::FileSize));
+        assert_eq!(both.exceeded(&result(137, Some("KILL"))), None);
+        assert_eq!(both.exceeded(&result(0, None)), None);
+        // Only a limit that was set can have been hit
+        assert_eq!(limits(None, None, Some(1)).exceeded(&result(152, None)), None);
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn cpu_limit_stops_a_busy_loop() {
+        let config = crate::test_support::test_config();
+        let mut client = crate::test_support::connect(&config);
+        let limits = limits(None, Some(1), None);
+
+        let started = std::time::Instant::now();
+        let result = client
+            .execute_command_as("while :; do :; done", None, None, None, Some(&limits), None)
+            .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert!(!result.success);
+        assert_eq!(result.limit_exceeded, Some(LimitExceeded::CpuTime), "{} {:?}", result.exit_status, result.exit_signal);
+    }
+}
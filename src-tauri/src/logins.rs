@@ -0,0 +1,315 @@
+// Recent logins to the server, from `last` and `lastlog`
+//
+// Both commands print fixed-ish columns meant for people, not parsers, and
+// the terminal and host columns can be missing or contain spaces. The login
+// time is the one part that's easy to find (a weekday followed by a month),
+// so each line is split around that.
+
+use crate::{errors, with_client, ConnectionsStore, SSHClient};
+use anyhow::Result;
+use serde::Serialize;
+use tauri::State;
+
+const DEFAULT_LOGIN_COUNT: u32 = 20;
+const MAX_LOGIN_COUNT: u32 = 1000;
+
+const WEEKDAYS: &[&str] = &["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: &[&str] = &["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// Pseudo-users `last` uses for system events rather than logins
+const SYSTEM_ENTRIES: &[&str] = &["reboot", "shutdown", "runlevel"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginRecord {
+    pub user: String,
+    // e.g. "pts/0" or "tty1"
+    pub terminal: Option<String>,
+    // Host or address the login came from; None for local logins
+    pub from: Option<String>,
+    // As the server printed it, in the server's timezone
+    pub login_time: String,
+    // A time, or "crash"/"down" when the session ended with the system
+    pub logout_time: Option<String>,
+    // As `last` prints it, e.g. "01:50" or "2+03:10"
+    pub duration: Option<String>,
+    pub still_logged_in: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastLogins {
+    // Most recent first
+    pub logins: Vec<LoginRecord>,
+    // The connected user's previous login according to lastlog, which
+    // outlives wtmp's rotation
+    pub last_login: Option<LoginRecord>,
+    // Why part of this couldn't be collected, e.g. an unreadable wtmp
+    pub warnings: Vec<String>,
+}
+
+// Index of the token starting the login time, e.g. "Mon" in "Mon Oct 13"
+fn find_time_start(tokens: &[&str], from: usize) -> Option<usize> {
+    (from..tokens.len().saturating_sub(1))
+        .find(|&i| WEEKDAYS.contains(&tokens[i]) && MONTHS.contains(&tokens[i + 1]))
+}
+
+// How many tokens a time takes: "Mon Oct 13 09:12" normally, and
+// "Mon Oct 13 09:12:01 2026" with `last -F` or from lastlog, which may also
+// put a UTC offset before the year
+fn time_length(tokens: &[&str]) -> usize {
+    let Some(clock) = tokens.get(3) else { return tokens.len() };
+    if clock.matches(':').count() < 2 {
+        return 4.min(tokens.len());
+    }
+    let offset = tokens.get(4).is_some_and(|t| t.starts_with('+') || t.starts_with('-'));
+    (if offset { 6 } else { 5 }).min(tokens.len())
+}
+
+fn local_host(host: &str) -> Option<String> {
+    match host {
+        "" | "0.0.0.0" | ":0" | ":0.0" => None,
+        host => Some(host.to_string()),
+    }
+}
+
+// Parses one line of `last -i` output
+fn parse_last_line(line: &str) -> Option<LoginRecord> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let user = *tokens.first()?;
+    // The closing "wtmp begins Sun Oct  1 00:00:01 2026" line isn't a login either
+    if SYSTEM_ENTRIES.contains(&user) || tokens.get(1) == Some(&"begins") {
+        return None;
+    }
+
+    let start = find_time_start(&tokens, 2)?;
+    // With -i the host column is always there, so it's the last token before
+    // the time; anything between it and the user is the terminal
+    let (terminal, from) = match &tokens[1..start] {
+        [terminal] => (Some(terminal.to_string()), None),
+        [terminal @ .., host] => (Some(terminal.join(" ")), local_host(host)),
+        [] => (None, None),
+    };
+
+    let time = &tokens[start..];
+    let time_len = time_length(time);
+    let login_time = time[..time_len].join(" ");
+    let rest = time[time_len..].join(" ");
+
+    let still_logged_in = rest.starts_with("still logged in");
+    let (ended, duration) = match rest.rfind('(') {
+        Some(open) => (
+            rest[..open].trim(),
+            Some(rest[open + 1..].trim_end_matches(')').trim().to_string()),
+        ),
+        None => (rest.trim(), None),
+    };
+    let logout_time = ended.strip_prefix('-')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+
+    Some(LoginRecord {
+        user: user.to_string(),
+        terminal,
+        from,
+        login_time,
+        logout_time,
+        duration: if still_logged_in { None } else { duration },
+        still_logged_in,
+    })
+}
+
+// Parses lastlog's output for one user: a header line, then
+// `user port from time`, where port and from may be blank
+fn parse_lastlog(output: &str) -> Option<LoginRecord> {
+    let line = output.lines().skip(1).find(|l| !l.trim().is_empty())?;
+    if line.contains("**Never logged in**") {
+        return None;
+    }
+
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let start = find_time_start(&tokens, 1)?;
+    let (terminal, from) = match &tokens[1..start] {
+        [] => (None, None),
+        [terminal] => (Some(terminal.to_string()), None),
+        [terminal, from @ ..] => (Some(terminal.to_string()), local_host(&from.join(" "))),
+    };
+
+    let time = &tokens[start..];
+    Some(LoginRecord {
+        user: tokens[0].to_string(),
+        terminal,
+        from,
+        login_time: time[..time_length(time)].join(" "),
+        logout_time: None,
+        duration: None,
+        still_logged_in: false,
+    })
+}
+
+// Describes why a command failed: not installed, or its own complaint
+fn tool_failure(client: &SSHClient, tool: &str, stderr: &str) -> Result<String> {
+    let (status, _, _) = client.exec_capture(&format!("command -v {} >/dev/null 2>&1", tool))?;
+    if status != 0 {
+        return Ok(format!("{} is not installed on the server", tool));
+    }
+    match stderr.trim() {
+        "" => Ok(format!("{} failed", tool)),
+        message => Ok(format!("{} failed: {}", tool, message)),
+    }
+}
+
+fn collect_last_logins(client: &SSHClient, count: u32) -> Result<LastLogins> {
+    let mut warnings = Vec::new();
+
+    // English day and month names whatever the server's locale. -F gives
+    // full times with the year, -i addresses rather than resolved names, -w
+    // untruncated user names; BusyBox's last supports none of them.
+    let (mut status, mut stdout, mut stderr) = client.exec_capture(&format!("LC_ALL=C last -F -i -w -n {}", count))?;
+    if status != 0 {
+        (status, stdout, stderr) = client.exec_capture(&format!("LC_ALL=C last -i -n {}", count))?;
+    }
+
+    let logins = if status == 0 {
+        stdout.lines().filter_map(parse_last_line).take(count as usize).collect()
+    } else {
+        warnings.push(tool_failure(client, "last", &stderr)?);
+        Vec::new()
+    };
+
+    // lastlog is gone from recent util-linux releases, so its absence is
+    // only worth a mention when last didn't work either
+    let (status, stdout, stderr) = client.exec_capture("LC_ALL=C lastlog -u \"$(id -un)\"")?;
+    let last_login = if status == 0 {
+        parse_lastlog(&stdout)
+    } else {
+        if logins.is_empty() {
+            warnings.push(tool_failure(client, "lastlog", &stderr)?);
+        }
+        None
+    };
+
+    Ok(LastLogins { logins, last_login, warnings })
+}
+
+// Lists the server's most recent logins (`count` of them, 20 by default), so
+// unexpected users or source addresses stand out
+#[tauri::command]
+pub async fn get_last_logins(
+    connection_id: String,
+    count: Option<u32>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<LastLogins, errors::SSHError> {
+    let count = count.unwrap_or(DEFAULT_LOGIN_COUNT).clamp(1, MAX_LOGIN_COUNT);
+    with_client(&connections, &connection_id, move |client| {
+        collect_last_logins(client, count).map_err(|e| format!("Failed to read login history: {}", e))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // user, terminal, from, login and logout time, duration, still logged in
+    type Fields<'a> = (&'a str, Option<&'a str>, Option<&'a str>, &'a str, Option<&'a str>, Option<&'a str>, bool);
+
+    fn fields(record: &LoginRecord) -> Fields<'_> {
+        (
+            &record.user,
+            record.terminal.as_deref(),
+            record.from.as_deref(),
+            &record.login_time,
+            record.logout_time.as_deref(),
+            record.duration.as_deref(),
+            record.still_logged_in,
+        )
+    }
+
+    #[test]
+    fn parses_last_lines() {
+        let line = "alice    pts/0        192.168.1.10     Tue Oct 13 09:12:01 2026 - Tue Oct 13 11:02:45 2026  (01:50)";
+        assert_eq!(fields(&parse_last_line(line).unwrap()), (
+            "alice",
+            Some("pts/0"),
+            Some("192.168.1.10"),
+            "Tue Oct 13 09:12:01 2026",
+            Some("Tue Oct 13 11:02:45 2026"),
+            Some("01:50"),
+            false,
+        ));
+
+        let line = "bob      pts/1        0.0.0.0          Wed Oct 14 08:00:00 2026   still logged in";
+        assert_eq!(fields(&parse_last_line(line).unwrap()), (
+            "bob",
+            Some("pts/1"),
+            None,
+            "Wed Oct 14 08:00:00 2026",
+            None,
+            None,
+            true,
+        ));
+
+        let line = "carol    tty1         0.0.0.0          Mon Oct 12 07:00:00 2026 - crash                     (2+03:10)";
+        assert_eq!(fields(&parse_last_line(line).unwrap()), (
+            "carol",
+            Some("tty1"),
+            None,
+            "Mon Oct 12 07:00:00 2026",
+            Some("crash"),
+            Some("2+03:10"),
+            false,
+        ));
+    }
+
+    #[test]
+    fn parses_busybox_last_lines() {
+        // Without -F the times are short and the logout has no date
+        let line = "dave     pts/2        203.0.113.5      Mon Oct 12 10:00 - 10:30  (00:30)";
+        assert_eq!(fields(&parse_last_line(line).unwrap()), (
+            "dave",
+            Some("pts/2"),
+            Some("203.0.113.5"),
+            "Mon Oct 12 10:00",
+            Some("10:30"),
+            Some("00:30"),
+            false,
+        ));
+    }
+
+    #[test]
+    fn skips_what_is_not_a_login() {
+        for line in [
+            "",
+            "reboot   system boot  6.1.0-13-amd64   Mon Oct 12 06:59:00 2026   still running",
+            "shutdown system down  6.1.0-13-amd64   Mon Oct 12 06:58:00 2026 - Mon Oct 12 06:59:00 2026  (00:01)",
+            "wtmp begins Sun Oct  1 00:00:01 2026",
+            "eve      pts/3        10.0.0.1         garbled",
+        ] {
+            assert!(parse_last_line(line).is_none(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn parses_lastlog() {
+        let output = "Username         Port     From             Latest\n\
+                      alice            pts/0    192.168.1.10     Tue Oct 13 09:12:01 +0000 2026\n";
+        assert_eq!(fields(&parse_lastlog(output).unwrap()), (
+            "alice",
+            Some("pts/0"),
+            Some("192.168.1.10"),
+            "Tue Oct 13 09:12:01 +0000 2026",
+            None,
+            None,
+            false,
+        ));
+
+        let output = "Username         Port     From             Latest\n\
+                      root             tty1                      Mon Oct 12 07:00:00 +0000 2026\n";
+        let record = parse_lastlog(output).unwrap();
+        assert_eq!((record.terminal.as_deref(), record.from.as_deref()), (Some("tty1"), None));
+
+        let output = "Username         Port     From             Latest\n\
+                      newuser                                    **Never logged in**\n";
+        assert!(parse_lastlog(output).is_none());
+        assert!(parse_lastlog("Username         Port     From             Latest\n").is_none());
+    }
+}
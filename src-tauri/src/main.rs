@@ -1,25 +1,113 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use ssh2::Session;
+mod auth;
+mod diagnostics;
+mod dir_transfer;
+mod env;
+mod errors;
+mod files;
+mod health;
+mod jump;
+mod known_hosts;
+mod limits;
+mod ndjson;
+mod logins;
+mod multi;
+mod priority;
+mod profiles;
+mod repl;
+mod services;
+mod shell;
+mod stats;
+mod sudo;
+mod table;
+#[cfg(test)]
+mod test_support;
+mod transfer;
+mod tunnel;
+
+use ssh2::{Channel, KeyboardInteractivePrompt, Session};
 use std::io::prelude::*;
-use std::net::TcpStream;
+use std::net::{SocketAddr, TcpStream};
 use std::path::Path;
 use anyhow::{Result, Context};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
 use std::net::ToSocketAddrs;
 use serde::{Deserialize, Serialize};
-use tauri::State;
+use tauri::{AppHandle, Emitter, State};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Streaming output is flushed to the frontend at least this often
+const STREAM_FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+// ...or as soon as this many bytes are buffered for a stream
+const STREAM_FLUSH_THRESHOLD: usize = 16 * 1024;
+// How long the reader thread sleeps when neither stream had data
+const STREAM_POLL_INTERVAL: Duration = Duration::from_millis(20);
+// Used for the TCP connect and handshake when the config doesn't set one
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+// Keepalive interval when the config doesn't set one
+const DEFAULT_KEEPALIVE_INTERVAL_SECS: u32 = 30;
+// Most of each of stdout and stderr a command's result holds, when the config
+// doesn't set a limit
+const DEFAULT_MAX_OUTPUT_BYTES: u64 = 4 * 1024 * 1024;
+// How many commands each connection remembers for collect_diagnostics
+const MAX_RECENT_COMMANDS: usize = 20;
+// LIBSSH2_ERROR_CHANNEL_FAILURE, which is how a server at its MaxSessions
+// limit answers a request for another channel
+const LIBSSH2_ERROR_CHANNEL_FAILURE: i32 = -21;
+// How often a command waiting for a free channel tries again
+const MAX_SESSIONS_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+// Printed ahead of the final pwd of commands like `cd /tmp; ls`, so the new
+// directory can be told apart from the command's own output
+const CWD_MARKER: &str = "__AETHERSSH_CWD__";
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct SSHConnectionConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
     pub password: Option<String>,
     pub private_key_path: Option<String>,
+    // The private key itself, e.g. pasted in or taken from a password
+    // manager, so it never has to be written to disk. Preferred over the path.
+    pub private_key_contents: Option<String>,
     pub passphrase: Option<String>,
+    // Trust on first use: record the key of a host that isn't in known_hosts
+    // yet instead of refusing it (reported with first_seen and a
+    // `host-key-pinned` event). A key that differs from the recorded one is
+    // still refused. Also accepted as `tofu`.
+    #[serde(default, alias = "tofu")]
+    pub accept_new_host_key: bool,
+    // Forces a specific authentication method instead of picking one from
+    // the credentials provided
+    pub auth_method: Option<auth::AuthMethod>,
+    // Limit for establishing the TCP connection and SSH handshake
+    pub connect_timeout_ms: Option<u64>,
+    // Limit for each command run with execute_ssh_command; unlimited if unset
+    pub command_timeout_ms: Option<u64>,
+    // Seconds between keepalives on an idle connection; 0 turns them off
+    pub keepalive_interval_secs: Option<u32>,
+    // Bastion to connect through, with its own credentials. It may have a
+    // jump_host of its own.
+    pub jump_host: Option<Box<SSHConnectionConfig>>,
+    // Directory to start in instead of the home directory, e.g. a project folder
+    pub initial_directory: Option<String>,
+    // When the server is at its MaxSessions limit, wait up to this long for
+    // a channel to free up instead of failing straight away
+    pub max_sessions_wait_ms: Option<u64>,
+    // Log in again with these same credentials when the connection dies,
+    // instead of dropping it (default true)
+    pub auto_reconnect: Option<bool>,
+    // Most of each of stdout and stderr kept from a command run with
+    // execute_ssh_command (default 4 MiB; 0 for no limit). The rest is
+    // dropped and counted in the result's dropped_bytes.
+    pub max_output_bytes: Option<u64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -27,6 +115,35 @@ pub struct SSHConnectionResponse {
     pub success: bool,
     pub message: String,
     pub connection_id: Option<String>,
+    // For display, e.g. "alice@example.com:22"; unlike the ID, not unique
+    pub label: Option<String>,
+    // Machine-readable reason for failures the frontend handles specially,
+    // such as "host_key_unknown" or "host_key_mismatch"
+    pub error_code: Option<String>,
+    // The server's key, set when error_code is a host key problem or when
+    // first_seen is
+    pub host_key: Option<known_hosts::HostKeyInfo>,
+    // The server's key wasn't known and has just been recorded
+    pub first_seen: bool,
+    // What went wrong, when success is false; error_code is its code
+    pub error: Option<errors::SSHError>,
+}
+
+impl SSHConnectionResponse {
+    fn failed(e: &anyhow::Error, message: String) -> Self {
+        let error = errors::SSHError::from_anyhow(e, message.clone());
+        let host_key = e.downcast_ref::<known_hosts::HostKeyError>().map(|e| e.host_key().clone());
+        SSHConnectionResponse {
+            success: false,
+            message,
+            connection_id: None,
+            label: None,
+            error_code: Some(error.code().to_string()),
+            host_key,
+            first_seen: false,
+            error: Some(error),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -35,35 +152,547 @@ pub struct CommandResult {
     pub stderr: String,
     pub exit_status: i32,
     pub success: bool,
+    // Signal that killed the command, without the SIG prefix, e.g. "KILL".
+    // exit_status is then 128 plus its number, as a shell's $? would be.
+    pub exit_signal: Option<String>,
+    // The resource limit that ended the command, when run with limits
+    pub limit_exceeded: Option<limits::LimitExceeded>,
+    pub current_directory: String,
+    // Names of the session env variables the command ran with
+    pub env_names: Vec<String>,
+    // The command was stopped by cancel_ssh_command or cancel_command
+    pub cancelled: bool,
+    // The command was stopped because it ran past the command timeout
+    pub timed_out: bool,
+    // The connection turned out to be dead and has been removed
+    pub disconnected: bool,
+    // Output went past the connection's max_output_bytes and was cut short;
+    // dropped_bytes says how much of stdout and stderr together was lost
+    pub truncated: bool,
+    pub dropped_bytes: u64,
+    // Set when stdout or stderr wasn't valid UTF-8. The text fields then hold a
+    // lossy decoding and the matching *_base64 field holds the exact bytes.
+    pub binary: bool,
+    pub stdout_base64: Option<String>,
+    pub stderr_base64: Option<String>,
+    // Machine-readable reason for failures the frontend handles specially,
+    // such as "max_sessions_reached"; the code of `error`
+    pub error_code: Option<String>,
+    // Why the command couldn't run, or "command_not_found"
+    pub error: Option<errors::SSHError>,
+}
+
+impl CommandResult {
+    fn from_output(stdout: Vec<u8>, stderr: Vec<u8>, exit_status: i32, current_directory: String) -> Self {
+        let (stdout, stdout_base64) = decode_output(stdout);
+        let (stderr, stderr_base64) = decode_output(stderr);
+
+        CommandResult {
+            stdout,
+            stderr,
+            exit_status,
+            success: exit_status == 0,
+            exit_signal: None,
+            limit_exceeded: None,
+            current_directory,
+            env_names: Vec::new(),
+            cancelled: false,
+            timed_out: false,
+            disconnected: false,
+            truncated: false,
+            dropped_bytes: 0,
+            binary: stdout_base64.is_some() || stderr_base64.is_some(),
+            stdout_base64,
+            stderr_base64,
+            error_code: None,
+            error: None,
+        }
+    }
+
+    // A result for a command that didn't run to completion
+    fn failed(message: String, current_directory: String) -> Self {
+        CommandResult::from_output(Vec::new(), message.into_bytes(), -1, current_directory)
+    }
+
+    fn with_error(mut self, error: errors::SSHError) -> Self {
+        self.error_code = Some(error.code().to_string());
+        self.error = Some(error);
+        self
+    }
+}
+
+// The server refused to open another channel, which almost always means its
+// MaxSessions limit (10 by default) has been reached
+#[derive(Debug)]
+pub struct MaxSessionsReached;
+
+impl std::fmt::Display for MaxSessionsReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The server won't open another session on this connection (MaxSessions reached). \
+             Close a shell, REPL or running command and try again"
+        )
+    }
+}
+
+impl std::error::Error for MaxSessionsReached {}
+
+// A command found the connection dead and logging in again didn't work
+#[derive(Debug)]
+pub struct ReconnectFailed(String);
+
+impl std::fmt::Display for ReconnectFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The connection was lost and reconnecting failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for ReconnectFailed {}
+
+// Decodes command output as text, falling back to a lossy decoding plus the
+// raw bytes in base64 when it isn't valid UTF-8 (binary files, stray bytes)
+fn decode_output(bytes: Vec<u8>) -> (String, Option<String>) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, None),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            (String::from_utf8_lossy(&bytes).into_owned(), Some(BASE64_STANDARD.encode(&bytes)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+// Payload of the `ssh-output` event emitted while a streaming command runs
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandOutputEvent {
+    pub connection_id: String,
+    // Caller-chosen id telling concurrent streams on one connection apart
+    pub channel_id: String,
+    pub stream: OutputStream,
+    pub chunk: String,
+}
+
+// Payload of the `ssh-exit` event emitted once a streaming command finishes
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandExitEvent {
+    pub connection_id: String,
+    pub channel_id: String,
+    pub exit_status: i32,
+    pub success: bool,
+    pub exit_signal: Option<String>,
+    pub cancelled: bool,
     pub current_directory: String,
 }
 
+// Payload of the `host-key-pinned` event, sent for each host (the target
+// or a bastion) whose key was recorded on first use
+#[derive(Debug, Clone, Serialize)]
+pub struct HostKeyPinnedEvent {
+    pub connection_id: String,
+    pub host_key: known_hosts::HostKeyInfo,
+}
+
+// Payload of the `ssh-connection-warning` event, for problems that didn't
+// stop the connection, such as a missing initial_directory
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionWarningEvent {
+    pub connection_id: String,
+    pub message: String,
+}
+
+// Result of estimate_output_size
+#[derive(Debug, Serialize)]
+pub struct OutputSizeEstimate {
+    pub bytes: u64,
+    pub lines: u64,
+    pub exit_status: i32,
+    pub success: bool,
+    // Server-side file holding the output, when it was asked to be kept
+    pub output_file: Option<String>,
+}
+
+// Treats an empty string from the frontend the same as a missing one
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|v| !v.trim().is_empty())
+}
+
+// The config kept for reconnecting. Any host key accepted while connecting
+// is in known_hosts by now, so a reconnect must match it.
+fn reconnect_config(config: &SSHConnectionConfig) -> SSHConnectionConfig {
+    let mut stored = config.clone();
+    let mut hop = Some(&mut stored);
+    while let Some(config) = hop {
+        config.accept_new_host_key = false;
+        hop = config.jump_host.as_deref_mut();
+    }
+    stored
+}
+
+// Quotes a value for safe use as a single word in a POSIX shell command
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// The argument of a cd command as a single shell word. `cd` and `cd ~` go
+// home, `~/x` and `~user/x` stay relative to the home directory, and `cd -`
+// goes back to the previous directory. An argument the user quoted or
+// escaped is unquoted first; anything else, like `cd My Documents`, is taken
+// as one literal path. Arguments using variables are left to the shell.
+fn cd_target(argument: &str) -> String {
+    let argument = argument.trim();
+    let path = match env::split_words(argument) {
+        _ if argument.contains('$') => return argument.to_string(),
+        Some(mut words) if words.len() == 1 => words.remove(0),
+        _ => argument.to_string(),
+    };
+
+    match path.as_str() {
+        "" | "~" => return "\"$HOME\"".to_string(),
+        // in_current_directory sets OLDPWD to the tracked previous directory
+        "-" => return "\"$OLDPWD\"".to_string(),
+        _ => {}
+    }
+
+    // Only the ~ or ~user part may stay unquoted for the shell to expand
+    if let Some(rest) = path.strip_prefix('~') {
+        let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        if user.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+            let home = if user.is_empty() { "\"$HOME\"".to_string() } else { format!("~{}", user) };
+            return match rest {
+                "" => home,
+                rest => format!("{}/{}", home, shell_quote(rest)),
+            };
+        }
+    }
+    shell_quote(&path)
+}
+
+// Whether a command runs cd anywhere other than as the whole command, as in
+// `cd /tmp; ls` or `make && cd out`. May also match a quoted "; cd", which
+// only costs a pwd.
+fn has_embedded_cd(command: &str) -> bool {
+    command.split([';', '&', '|', '(', '\n']).any(|segment| {
+        let segment = segment.trim_start();
+        segment == "cd" || segment.strip_prefix("cd").is_some_and(|rest| rest.starts_with(char::is_whitespace))
+    })
+}
+
+// Splits the marker and final pwd off a command's output. Returns the pwd,
+// or None if the command exited before printing it.
+fn take_marked_directory(stdout: &mut Vec<u8>) -> Option<String> {
+    let marker = CWD_MARKER.as_bytes();
+    let start = stdout.windows(marker.len()).rposition(|w| w == marker)?;
+    let directory = String::from_utf8_lossy(&stdout[start + marker.len()..]).trim().to_string();
+
+    // Also drop the newline printed ahead of the marker
+    stdout.truncate(start);
+    if stdout.ends_with(b"\r\n") {
+        stdout.truncate(stdout.len() - 2);
+    } else if stdout.ends_with(b"\n") {
+        stdout.pop();
+    }
+
+    (!directory.is_empty()).then_some(directory)
+}
+
+// How much of a marked command's stdout can be shown while it runs: what
+// comes before the marker, less any tail that may yet turn out to be the
+// marker or the newline ahead of it
+fn shown_before_marker(stdout: &[u8]) -> usize {
+    let marker = CWD_MARKER.as_bytes();
+    let mut end = match stdout.windows(marker.len()).position(|w| w == marker) {
+        Some(start) => start,
+        // Room for the \r\n ahead of all but the marker's last byte
+        None => stdout.len().saturating_sub(marker.len() + 1),
+    };
+    if stdout[..end].ends_with(b"\n") {
+        end -= 1;
+        if stdout[..end].ends_with(b"\r") {
+            end -= 1;
+        }
+    }
+    end
+}
+
+// Strips the brackets from an IPv6 literal such as `[2001:db8::1]`
+fn normalize_host(host: &str) -> &str {
+    let host = host.trim();
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+// The port in a host written as `host:port` or `[::1]:port`, which belongs
+// in the config's port field instead. A bare IPv6 literal has more than one
+// colon, so it isn't mistaken for one.
+fn embedded_port(host: &str) -> Option<&str> {
+    let host = host.trim();
+    let port = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once("]:")?.1,
+        None => match host.split_once(':') {
+            Some((_, port)) if !port.contains(':') => port,
+            _ => return None,
+        },
+    };
+    (!port.is_empty() && port.chars().all(|c| c.is_ascii_digit())).then_some(port)
+}
+
+// Resolves the host to all of its addresses, IPv4 first and then IPv6, so
+// hosts that only have AAAA records (or are IPv6 literals) still work
+fn resolve_addresses(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    if let Some(embedded) = embedded_port(host) {
+        return Err(errors::Stage::new(
+            errors::SSHErrorKind::InvalidArgument,
+            format!("Host '{}' includes a port; set port to {} instead", host.trim(), embedded),
+        ).into());
+    }
+    let host = normalize_host(host);
+    let mut addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()
+        .with_context(|| errors::Stage::new(errors::SSHErrorKind::DnsResolution, format!("Failed to resolve host '{}'", host)))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(errors::Stage::new(errors::SSHErrorKind::DnsResolution, format!("No addresses found for host '{}'", host)).into());
+    }
+
+    // Stable sort, so the resolver's order is otherwise kept
+    addrs.sort_by_key(|a| a.is_ipv6());
+    Ok(addrs)
+}
+
+// Tries each address in turn, giving each one the full timeout
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let mut last_error = None;
+
+    for addr in resolve_addresses(host, port)? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last_error = Some(anyhow::Error::new(e).context(format!("Failed to connect to {}", addr))),
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| anyhow::anyhow!("No addresses to connect to"))
+        .context(errors::Stage::new(errors::SSHErrorKind::TcpConnect, "Failed to establish TCP connection")))
+}
+
+// Connects and completes the SSH handshake, without checking the host key.
+// With a jump host, the connection goes through it, and the returned tunnel
+// has to be kept for as long as the session is used.
+fn open_session(
+    host: &str,
+    port: u16,
+    jump_host: Option<&SSHConnectionConfig>,
+    timeout: Duration,
+    prompter: &mut impl KeyboardInteractivePrompt,
+) -> Result<(Session, Option<jump::JumpTunnel>)> {
+    let (tcp, jump) = match jump_host {
+        Some(jump_host) => {
+            let (tcp, tunnel) = jump::connect_through(jump_host, host, port, timeout, prompter)?;
+            (tcp, Some(tunnel))
+        }
+        None => (connect_tcp(host, port, timeout)?, None),
+    };
+
+    let mut session = Session::new()?;
+    session.set_tcp_stream(tcp);
+    // Bounds the handshake; SSHClient::new replaces it once connected
+    session.set_timeout(timeout.as_millis().min(u32::MAX as u128) as u32);
+    session.handshake()
+        .context(errors::Stage::new(errors::SSHErrorKind::Handshake, "SSH handshake failed"))?;
+
+    Ok((session, jump))
+}
+
+// How a command's effect on the working directory is picked up
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DirectoryTracking {
+    // Not a directory change
+    None,
+    // A plain cd, run as `cd … && pwd`, so its stdout is the new directory
+    Cd,
+    // A command that runs cd among other things, followed by CWD_MARKER and pwd
+    Marker,
+}
+
+// How a command's output read ended
+enum ReadOutcome {
+    Finished,
+    Cancelled,
+    TimedOut,
+    // sudo said the password was wrong before asking again
+    SudoRejected,
+}
+
+// A one-shot command whose channel is open. Its output is read a poll at a
+// time, so the connection's lock needn't be held for the whole command.
+struct RunningCommand {
+    channel: Channel,
+    cancel: CancelRegistration,
+    tracking: DirectoryTracking,
+    timeout: Option<Duration>,
+    started: Instant,
+    stdout: CappedOutput,
+    stderr: CappedOutput,
+    stdout_eof: bool,
+    stderr_eof: bool,
+    sudo: Option<sudo::PasswordResponder>,
+    limits: Option<limits::ResourceLimits>,
+    env_change: Option<env::EnvChange>,
+    // Set by sudo_execute, whose failures get explained
+    explain_sudo_failure: bool,
+}
+
+impl RunningCommand {
+    fn new(
+        client: &SSHClient,
+        channel: Channel,
+        cancel: CancelRegistration,
+        tracking: DirectoryTracking,
+        timeout: Option<Duration>,
+        sudo: Option<sudo::PasswordResponder>,
+    ) -> Self {
+        RunningCommand {
+            channel,
+            cancel,
+            tracking,
+            timeout,
+            started: Instant::now(),
+            stdout: CappedOutput::new(client.max_output_bytes),
+            stderr: CappedOutput::new(client.max_output_bytes),
+            stdout_eof: false,
+            stderr_eof: false,
+            sudo,
+            limits: None,
+            env_change: None,
+            explain_sudo_failure: false,
+        }
+    }
+
+    fn received(&self) -> u64 {
+        self.stdout.received() + self.stderr.received()
+    }
+}
+
+enum StartedCommand {
+    // Done without a channel of its own, like an export
+    Finished(CommandResult),
+    Running(RunningCommand),
+}
+
 struct SSHClient {
     session: Session,
     current_directory: String,
+    // Where the last directory change started from, for `cd -`
+    previous_directory: String,
+    // Cancellation requests from cancel_ssh_command and cancel_command
+    cancels: Arc<CommandCancels>,
+    // Open REPL channels, keyed by repl id
+    repl_sessions: HashMap<String, repl::ReplSession>,
+    // The interactive shell opened by start_shell, if any
+    shell: Option<shell::ShellSession>,
+    // Variables exported by earlier commands, re-exported ahead of each new one
+    session_env: HashMap<String, String>,
+    // Longest a command may run before execute_command gives up on it
+    command_timeout: Option<Duration>,
+    // Most of each output stream kept by execute_command; None for no limit
+    max_output_bytes: Option<usize>,
+    // How long commands wait for a free channel when the server is at MaxSessions
+    max_sessions_wait: Option<Duration>,
+    // What the connection was made with, for reconnect
+    config: SSHConnectionConfig,
+    host: String,
+    port: u16,
+    username: String,
+    // How the user logged in, once authenticated
+    auth_method: Option<auth::AuthMethod>,
+    // Methods tried while authenticating, for reporting a failed login
+    auth_attempts: Vec<auth::AuthMethod>,
+    connected_at: SystemTime,
+    // Time taken by the TCP connect, handshake and host key check
+    connect_duration: Duration,
+    auth_duration: Option<Duration>,
+    last_error: Option<String>,
+    // The last few commands run, with likely secrets masked
+    recent_commands: VecDeque<String>,
+    // Host keys recorded on first use while connecting, bastions' first
+    pinned_host_keys: Vec<known_hosts::HostKeyInfo>,
+    // Set when connected through a bastion. Declared last, so the session
+    // above is closed before the bastion's is.
+    _jump: Option<jump::JumpTunnel>,
 }
 
 impl SSHClient {
-    pub fn new(host: &str, port: u16) -> Result<Self> {
-        let addr = (host, port).to_socket_addrs()?.find(|a| a.is_ipv4())
-            .context("Failed to resolve IPv4 address")?;
+    // The prompter is only needed for a jump host using keyboard-interactive
+    // auth; the target itself authenticates afterwards, in authenticate
+    pub fn new(config: &SSHConnectionConfig, prompter: &mut impl KeyboardInteractivePrompt) -> Result<Self> {
+        let started = Instant::now();
+        let (host, port) = (config.host.as_str(), config.port);
+        let connect_timeout = config.connect_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        let command_timeout = config.command_timeout_ms.map(Duration::from_millis);
+
+        let (session, mut jump) = open_session(host, port, config.jump_host.as_deref(), connect_timeout, prompter)?;
 
-        let tcp = TcpStream::connect(addr)
-            .context("Failed to establish TCP connection")?;
+        let mut pinned_host_keys = jump.as_mut()
+            .map(|jump| std::mem::take(&mut jump.pinned_host_keys))
+            .unwrap_or_default();
+        pinned_host_keys.extend(known_hosts::verify_host_key(&session, normalize_host(host), port, config.accept_new_host_key)?);
 
-        let mut session = Session::new()?;
-        session.set_tcp_stream(tcp);
-        session.handshake()?;
+        // Blocking calls from here on (auth, opening channels, SFTP) give up
+        // after the command timeout instead of hanging on a dead server.
+        // Zero means no timeout.
+        let blocking_timeout = command_timeout.unwrap_or(Duration::ZERO);
+        session.set_timeout(blocking_timeout.as_millis().min(u32::MAX as u128) as u32);
+
+        // Ask for replies, so the server has to answer and a dead link shows
+        // up as a failed send. The keepalive thread does the sending.
+        let keepalive_interval = config.keepalive_interval_secs.unwrap_or(DEFAULT_KEEPALIVE_INTERVAL_SECS);
+        session.set_keepalive(true, keepalive_interval);
 
         Ok(SSHClient {
             session,
             current_directory: String::new(), // Will be set after authentication
+            previous_directory: String::new(),
+            cancels: Arc::default(),
+            repl_sessions: HashMap::new(),
+            shell: None,
+            session_env: HashMap::new(),
+            command_timeout,
+            max_output_bytes: match config.max_output_bytes.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES) {
+                0 => None,
+                limit => Some(limit.min(usize::MAX as u64) as usize),
+            },
+            max_sessions_wait: config.max_sessions_wait_ms.map(Duration::from_millis),
+            config: reconnect_config(config),
+            host: host.to_string(),
+            port,
+            username: config.username.clone(),
+            auth_method: None,
+            auth_attempts: Vec::new(),
+            connected_at: SystemTime::now(),
+            connect_duration: started.elapsed(),
+            auth_duration: None,
+            last_error: None,
+            recent_commands: VecDeque::new(),
+            pinned_host_keys,
+            _jump: jump,
         })
     }
 
     pub fn authenticate_with_password(&mut self, username: &str, password: &str) -> Result<()> {
+        self.auth_attempts.push(auth::AuthMethod::Password);
         self.session.userauth_password(username, password)
             .context("Password authentication failed")?;
+        self.auth_method = Some(auth::AuthMethod::Password);
 
         // Get initial working directory
         self.update_current_directory()?;
@@ -71,228 +700,2294 @@ impl SSHClient {
     }
 
     pub fn authenticate_with_key(&mut self, username: &str, private_key_path: &str, passphrase: Option<&str>) -> Result<()> {
+        self.auth_attempts.push(auth::AuthMethod::Key);
         self.session.userauth_pubkey_file(
             username,
             None,
             Path::new(private_key_path),
             passphrase,
         ).context("Key authentication failed")?;
+        self.auth_method = Some(auth::AuthMethod::Key);
 
         // Get initial working directory
         self.update_current_directory()?;
         Ok(())
     }
 
-    fn update_current_directory(&mut self) -> Result<()> {
-        let mut channel = self.session.channel_session()?;
-        channel.exec("pwd")?;
+    pub fn authenticate_with_key_contents(&mut self, username: &str, private_key: &str, passphrase: Option<&str>) -> Result<()> {
+        self.auth_attempts.push(auth::AuthMethod::Key);
+        self.session.userauth_pubkey_memory(username, None, private_key, passphrase)
+            .context("Key authentication failed")?;
+        self.auth_method = Some(auth::AuthMethod::Key);
 
-        let mut stdout = String::new();
-        channel.read_to_string(&mut stdout)?;
-        channel.wait_close()?;
+        // Get initial working directory
+        self.update_current_directory()?;
+        Ok(())
+    }
+
+    // Uses the in-memory key if there is one, otherwise the key file
+    fn authenticate_with_any_key(&mut self, config: &SSHConnectionConfig) -> Result<()> {
+        let username = config.username.as_str();
+        let passphrase = config.passphrase.as_deref();
+
+        match (non_empty(&config.private_key_contents), non_empty(&config.private_key_path)) {
+            (Some(contents), _) => self.authenticate_with_key_contents(username, contents, passphrase),
+            (None, Some(path)) => self.authenticate_with_key(username, path, passphrase),
+            (None, None) => Err(anyhow::anyhow!(
+                "Key authentication requires private_key_contents or private_key_path"
+            )),
+        }
+    }
 
-        self.current_directory = stdout.trim().to_string();
+    pub fn authenticate_with_agent(&mut self, username: &str) -> Result<()> {
+        self.auth_attempts.push(auth::AuthMethod::Agent);
+        self.session.userauth_agent(username)
+            .context("Agent authentication failed")?;
+        self.auth_method = Some(auth::AuthMethod::Agent);
+
+        // Get initial working directory
+        self.update_current_directory()?;
         Ok(())
     }
 
-    fn is_directory_change_command(&self, command: &str) -> bool {
-        let trimmed = command.trim();
-        trimmed.starts_with("cd ") || trimmed == "cd"
+    pub fn authenticate_keyboard_interactive(&mut self, username: &str, prompter: &mut impl KeyboardInteractivePrompt) -> Result<()> {
+        self.auth_attempts.push(auth::AuthMethod::KeyboardInteractive);
+        self.session.userauth_keyboard_interactive(username, prompter)
+            .context("Keyboard-interactive authentication failed")?;
+        self.auth_method = Some(auth::AuthMethod::KeyboardInteractive);
+
+        // Get initial working directory
+        self.update_current_directory()?;
+        Ok(())
     }
 
-    pub fn execute_command(&mut self, command: &str) -> Result<CommandResult> {
-        let is_cd_command = self.is_directory_change_command(command);
+    // Logs in with a password the way the server takes it: plain password
+    // auth if offered, otherwise (or if that's refused) keyboard-interactive
+    // with the password answering its password prompt, which is how PAM
+    // setups ask, often with an OTP prompt after it for the user
+    fn authenticate_with_password_prompts(
+        &mut self,
+        username: &str,
+        password: &str,
+        prompter: &mut impl KeyboardInteractivePrompt,
+    ) -> Result<()> {
+        let offered = self.session.auth_methods(username).unwrap_or_default().to_string();
+        let offers = |method: &str| offered.split(',').any(|m| m == method);
+        let keyboard_interactive = offers("keyboard-interactive");
 
-        // For cd commands, we need to handle them specially
-        let full_command = if is_cd_command {
-            // Execute cd command and then pwd to get new directory
-            format!("cd {} && pwd", &command[2..].trim()) // Remove "cd" and trim
-        } else {
-            // For other commands, execute them in the current directory context
-            if self.current_directory.is_empty() {
-                command.to_string()
-            } else {
-                format!("cd '{}' && {}", self.current_directory, command)
+        if offers("password") || !keyboard_interactive {
+            match self.authenticate_with_password(username, password) {
+                // Some servers only check passwords through PAM's prompts.
+                // A failure after the password was taken, such as reading
+                // the directory, is passed on as it is.
+                Err(_) if keyboard_interactive && !self.session.authenticated() => {}
+                result => return result,
+            }
+        }
+        self.authenticate_keyboard_interactive(username, &mut auth::PasswordPrompter::new(password, prompter))
+    }
+
+    // Authenticates with the method the config forces, or otherwise with the
+    // password or key it carries. Without either, the ssh-agent is tried,
+    // followed by keyboard-interactive if the server offers it.
+    pub fn authenticate(&mut self, config: &SSHConnectionConfig, prompter: &mut impl KeyboardInteractivePrompt) -> Result<()> {
+        let username = config.username.as_str();
+        let started = Instant::now();
+
+        let result = match config.auth_method {
+            Some(auth::AuthMethod::Password) => match &config.password {
+                Some(password) => self.authenticate_with_password(username, password),
+                None => Err(anyhow::anyhow!("Password authentication requires a password")),
+            },
+            Some(auth::AuthMethod::Key) => self.authenticate_with_any_key(config),
+            Some(auth::AuthMethod::Agent) => self.authenticate_with_agent(username),
+            Some(auth::AuthMethod::KeyboardInteractive) => self.authenticate_keyboard_interactive(username, prompter),
+            None => {
+                if let Some(password) = &config.password {
+                    self.authenticate_with_password_prompts(username, password, prompter)
+                } else if non_empty(&config.private_key_contents).is_some() || non_empty(&config.private_key_path).is_some() {
+                    self.authenticate_with_any_key(config)
+                } else {
+                    self.authenticate_with_agent(username).or_else(|e| {
+                        let offered = self.session.auth_methods(username).unwrap_or_default();
+                        if offered.split(',').any(|m| m == "keyboard-interactive") {
+                            self.authenticate_keyboard_interactive(username, prompter)
+                        } else {
+                            Err(e)
+                        }
+                    })
+                }
             }
         };
 
-        let mut channel = self.session.channel_session()?;
-        channel.request_pty("xterm", None, None)?;
-        channel.exec(&full_command)?;
+        self.auth_duration = Some(started.elapsed());
+
+        // Failing after logging in (reading the home directory) isn't an auth failure
+        if self.auth_method.is_some() {
+            return result;
+        }
+
+        // Say what the server would have accepted, so the user knows what to try
+        result.map_err(|e| {
+            let offered = self.session.auth_methods(username).map(str::to_string).ok();
+            let message = match &offered {
+                Some(methods) => format!("{} (server accepts: {})", e, methods),
+                None => e.to_string(),
+            };
+            let mut methods_tried = std::mem::take(&mut self.auth_attempts);
+            methods_tried.dedup();
+            let kind = errors::SSHErrorKind::AuthFailed {
+                methods_tried,
+                methods_offered: offered.iter().flat_map(|m| m.split(',')).map(str::to_string).collect(),
+            };
+            e.context(errors::Stage::new(kind, message))
+        })
+    }
 
-        let mut stdout = String::new();
-        channel.read_to_string(&mut stdout)?;
+    pub fn auto_reconnect(&self) -> bool {
+        self.config.auto_reconnect.unwrap_or(true)
+    }
 
-        let mut stderr = String::new();
-        channel.stderr().read_to_string(&mut stderr)?;
+    // Replaces a dead session with a new one made from the same config,
+    // keeping the working directory, session env and history. REPLs and the
+    // shell belonged to the old session, so they're closed. Returns a warning
+    // if the working directory couldn't be restored.
+    pub fn reconnect(&mut self, prompter: &mut impl KeyboardInteractivePrompt) -> Result<Option<String>> {
+        let mut fresh = SSHClient::new(&self.config, prompter)?;
+        fresh.authenticate(&self.config, prompter)?;
 
-        channel.wait_close()?;
-        let exit_status = channel.exit_status()?;
+        self.repl_sessions.clear();
+        self.shell = None;
+        // The old session goes before the old bastion, as when dropping a client
+        self.session = fresh.session;
+        self._jump = fresh._jump;
+        self.auth_method = fresh.auth_method;
+        self.connected_at = fresh.connected_at;
+        self.connect_duration = fresh.connect_duration;
+        self.auth_duration = fresh.auth_duration;
 
-        // If it was a successful cd command, update our current directory
-        if is_cd_command && exit_status == 0 {
+        // Not through execute_command, which could try to reconnect again
+        let directory = std::mem::replace(&mut self.current_directory, fresh.current_directory);
+        if directory.is_empty() || directory == self.current_directory {
+            return Ok(None);
+        }
+        let (status, stdout, stderr) = self.exec_capture(&format!("cd {} && pwd", shell_quote(&directory)))?;
+        if status == 0 {
             self.current_directory = stdout.trim().to_string();
-            // For cd commands, we don't want to show the pwd output
-            Ok(CommandResult {
-                stdout: String::new(),
-                stderr,
-                exit_status,
-                success: exit_status == 0,
-                current_directory: self.current_directory.clone(),
-            })
+            Ok(None)
         } else {
-            Ok(CommandResult {
-                stdout,
-                stderr,
-                exit_status,
-                success: exit_status == 0,
-                current_directory: self.current_directory.clone(),
-            })
+            Ok(Some(format!("Couldn't return to {} after reconnecting: {}", directory, stderr.trim())))
         }
     }
 
-    pub fn get_current_directory(&self) -> &str {
-        &self.current_directory
+    // Moves to the configured starting directory. If it can't, the home
+    // directory is kept and the reason returned.
+    fn enter_initial_directory(&mut self, directory: &str) -> Option<String> {
+        let command = format!("cd {}", shell_quote(directory));
+        match self.execute_command(&command, None) {
+            Ok(result) if result.success => None,
+            Ok(result) => Some(format!("Couldn't open initial directory {}: {}", directory, result.stderr.trim())),
+            Err(e) => Some(format!("Couldn't open initial directory {}: {}", directory, e)),
+        }
     }
-}
 
-// Type alias for the connections store
-type ConnectionsStore = Arc<Mutex<HashMap<String, SSHClient>>>;
+    fn update_current_directory(&mut self) -> Result<()> {
+        let mut channel = self.open_channel()?;
+        channel.exec("pwd")?;
 
-#[tauri::command]
-async fn connect_ssh(
-    config: SSHConnectionConfig,
-    connections: State<'_, ConnectionsStore>,
-) -> Result<SSHConnectionResponse, String> {
-    // Generate a unique connection ID
-    let connection_id = format!("{}@{}:{}", config.username, config.host, config.port);
+        // A directory name needn't be valid UTF-8
+        let mut stdout = Vec::new();
+        channel.read_to_end(&mut stdout)?;
+        channel.wait_close()?;
 
-    // Create SSH client
-    let mut client = match SSHClient::new(&config.host, config.port) {
-        Ok(client) => client,
-        Err(e) => {
-            return Ok(SSHConnectionResponse {
-                success: false,
-                message: format!("Failed to create SSH connection: {}", e),
-                connection_id: None,
-            });
-        }
-    };
+        self.current_directory = String::from_utf8_lossy(&stdout).trim().to_string();
+        Ok(())
+    }
 
-    // Authenticate based on provided credentials
-    let auth_result = if let Some(password) = &config.password {
-        // Password authentication
-        client.authenticate_with_password(&config.username, password)
-    } else if let Some(private_key_path) = &config.private_key_path {
-        // Key authentication
-        client.authenticate_with_key(
-            &config.username,
-            private_key_path,
-            config.passphrase.as_deref(),
-        )
-    } else {
-        return Ok(SSHConnectionResponse {
-            success: false,
-            message: "No authentication method provided (password or private_key_path required)".to_string(),
-            connection_id: None,
-        });
-    };
+    // Runs a helper command without a PTY, so stderr stays separate from
+    // stdout, and returns (exit_status, stdout, stderr)
+    fn exec_capture(&self, command: &str) -> Result<(i32, String, String)> {
+        let mut channel = self.open_channel()?;
+        channel.exec(command)?;
 
-    match auth_result {
-        Ok(_) => {
-            // Store the connection
-            let mut connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
-            connections.insert(connection_id.clone(), client);
+        let mut stdout = Vec::new();
+        channel.read_to_end(&mut stdout)?;
 
-            Ok(SSHConnectionResponse {
-                success: true,
-                message: "Successfully connected and authenticated".to_string(),
-                connection_id: Some(connection_id),
-            })
-        }
-        Err(e) => Ok(SSHConnectionResponse {
-            success: false,
-            message: format!("Authentication failed: {}", e),
-            connection_id: None,
-        }),
+        let mut stderr = Vec::new();
+        channel.stderr().read_to_end(&mut stderr)?;
+
+        channel.wait_close()?;
+        let exit_status = channel.exit_status()?;
+
+        Ok((
+            exit_status,
+            String::from_utf8_lossy(&stdout).into_owned(),
+            String::from_utf8_lossy(&stderr).into_owned(),
+        ))
     }
-}
 
-#[tauri::command]
-async fn execute_ssh_command(
-    connection_id: String,
-    command: String,
-    connections: State<'_, ConnectionsStore>,
-) -> Result<CommandResult, String> {
-    let mut connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
+    // A plain cd, without other commands alongside it
+    fn is_directory_change_command(&self, command: &str) -> bool {
+        let trimmed = command.trim();
+        (trimmed.starts_with("cd ") || trimmed == "cd") && env::split_words(trimmed).is_some()
+    }
 
-    let client = connections.get_mut(&connection_id)
-        .ok_or_else(|| "Connection not found. Please connect first.".to_string())?;
+    // Records a new working directory, remembering the old one for `cd -`
+    fn set_current_directory(&mut self, directory: String) {
+        if directory != self.current_directory {
+            self.previous_directory = std::mem::replace(&mut self.current_directory, directory);
+        }
+    }
 
-    match client.execute_command(&command) {
-        Ok(result) => Ok(result),
-        Err(e) => Ok(CommandResult {
-            stdout: String::new(),
-            stderr: format!("Command execution failed: {}", e),
-            exit_status: -1,
-            success: false,
-            current_directory: client.get_current_directory().to_string(),
-        }),
+    // Like prepare_command, except that commands running cd among other
+    // things also report the directory they finish in
+    fn prepare_tracked_command(&self, command: &str) -> (String, DirectoryTracking) {
+        if self.is_directory_change_command(command) {
+            return (self.prepare_command(command).0, DirectoryTracking::Cd);
+        }
+        if !has_embedded_cd(command) {
+            return (self.in_current_directory(command), DirectoryTracking::None);
+        }
+
+        // Grouped so the prefix's cd guards all of it, with the command's own
+        // exit status kept
+        let script = format!(
+            "{{\n{}\n__aetherssh_status=$?; printf '\\n%s' {}; pwd; exit $__aetherssh_status\n}}",
+            command, CWD_MARKER
+        );
+        (self.in_current_directory(&script), DirectoryTracking::Marker)
     }
-}
 
-// New command to get current directory
-#[tauri::command]
-async fn get_current_directory(
-    connection_id: String,
-    connections: State<'_, ConnectionsStore>,
-) -> Result<String, String> {
-    let connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
+    // Builds the command line actually sent to the server, returning it along
+    // with whether it is a cd command whose output is the new directory
+    fn prepare_command(&self, command: &str) -> (String, bool) {
+        let is_cd_command = self.is_directory_change_command(command);
 
-    let client = connections.get(&connection_id)
-        .ok_or_else(|| "Connection not found. Please connect first.".to_string())?;
+        // For cd commands, we need to handle them specially
+        let full_command = if is_cd_command {
+            // Execute cd command from the current directory, then pwd to get the new one
+            let argument = command.trim().strip_prefix("cd").unwrap_or_default();
+            self.in_current_directory(&format!("cd {} && pwd", cd_target(argument)))
+        } else {
+            self.in_current_directory(command)
+        };
 
-    Ok(client.get_current_directory().to_string())
-}
+        (full_command, is_cd_command)
+    }
 
-// Optional: Command to disconnect and cleanup
-#[tauri::command]
-async fn disconnect_ssh(
-    connection_id: String,
-    connections: State<'_, ConnectionsStore>,
-) -> Result<bool, String> {
-    let mut connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
+    // Wraps a command so it runs in the tracked working directory, with the
+    // session env exported
+    fn in_current_directory(&self, command: &str) -> String {
+        let exports = env::export_prefix(&self.session_env);
+        if self.current_directory.is_empty() {
+            format!("{}{}", exports, command)
+        } else {
+            let oldpwd = match self.previous_directory.as_str() {
+                "" => String::new(),
+                previous => format!("OLDPWD={} && ", shell_quote(previous)),
+            };
+            format!("{}cd {} && {}{}", exports, shell_quote(&self.current_directory), oldpwd, command)
+        }
+    }
 
-    match connections.remove(&connection_id) {
-        Some(_) => Ok(true),
-        None => Ok(false),
+    // Opens a session channel, telling a server at its MaxSessions limit apart
+    // from other failures
+    fn open_channel(&self) -> Result<Channel> {
+        self.session.channel_session().map_err(|e| match e.code() {
+            ssh2::ErrorCode::Session(LIBSSH2_ERROR_CHANNEL_FAILURE) => anyhow::Error::new(MaxSessionsReached),
+            _ => e.into(),
+        })
     }
-}
 
-// Optional: Command to list active connections
-#[tauri::command]
-async fn list_ssh_connections(
-    connections: State<'_, ConnectionsStore>,
-) -> Result<Vec<String>, String> {
-    let connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
-    Ok(connections.keys().cloned().collect())
-}
+    fn open_command_channel(&self, full_command: &str) -> Result<Channel> {
+        self.open_exec_channel(full_command, true)
+    }
 
-// Setup function for Tauri app
-fn setup_ssh_commands() -> ConnectionsStore {
-    Arc::new(Mutex::new(HashMap::new()))
-}
+    fn open_exec_channel(&self, full_command: &str, pty: bool) -> Result<Channel> {
+        let mut channel = self.open_channel()?;
+        if pty {
+            channel.request_pty("xterm", None, None)?;
+        }
+        channel.exec(full_command)?;
+        Ok(channel)
+    }
 
-fn main() {
-    tauri::Builder::default()
-        .manage(setup_ssh_commands())
+    // Reads whatever output the command has ready without waiting for more,
+    // answering sudo's prompts if there's a password for them. Returns how
+    // the read ended once it has.
+    fn poll_command(&self, running: &mut RunningCommand) -> Result<Option<ReadOutcome>> {
+        if running.cancel.is_cancelled() {
+            return Ok(Some(ReadOutcome::Cancelled));
+        }
+        if running.timeout.is_some_and(|timeout| running.started.elapsed() >= timeout) {
+            return Ok(Some(ReadOutcome::TimedOut));
+        }
+
+        self.session.set_blocking(false);
+        let result = (|| -> std::io::Result<Option<ReadOutcome>> {
+            let channel = &mut running.channel;
+            if !running.stdout_eof {
+                running.stdout_eof = running.stdout.drain(channel)?;
+            }
+            if !running.stderr_eof {
+                running.stderr_eof = running.stderr.drain(&mut channel.stderr())?;
+            }
+            if let Some(sudo) = &mut running.sudo {
+                match sudo.scan(&mut running.stdout.data) {
+                    sudo::PromptState::Waiting => {}
+                    sudo::PromptState::Answer => {
+                        self.session.set_blocking(true);
+                        let written = channel.write_all(&sudo.password_line()).and_then(|_| channel.flush());
+                        self.session.set_blocking(false);
+                        written?;
+                    }
+                    sudo::PromptState::Rejected => return Ok(Some(ReadOutcome::SudoRejected)),
+                }
+            }
+
+            Ok((running.stdout_eof && running.stderr_eof).then_some(ReadOutcome::Finished))
+        })();
+        self.session.set_blocking(true);
+
+        result.map_err(|e| health::with_session_code(&self.session, e))
+    }
+
+    // Reads a started command to the end while holding the connection's
+    // lock throughout, for callers that already have it
+    fn run_locked(&mut self, started: StartedCommand) -> Result<CommandResult> {
+        let mut running = match started {
+            StartedCommand::Finished(result) => return Ok(result),
+            StartedCommand::Running(running) => running,
+        };
+
+        let outcome = loop {
+            let received = running.received();
+            if let Some(outcome) = self.poll_command(&mut running)? {
+                break outcome;
+            }
+            if running.received() == received {
+                thread::sleep(STREAM_POLL_INTERVAL);
+            }
+        };
+        self.complete_command(running, outcome)
+    }
+
+    // Interrupts the remote process and closes its channel
+    fn abort_channel(channel: &mut Channel) -> Result<()> {
+        // Ctrl-C through the PTY first, so the process gets a chance to exit cleanly
+        let _ = channel.write_all(b"\x03");
+        channel.close()?;
+        Ok(())
+    }
+
+    fn cancelled_result(&self) -> CommandResult {
+        let mut result = CommandResult::failed("Command cancelled".to_string(), self.current_directory.clone());
+        result.cancelled = true;
+        result
+    }
+
+    // Runs a command in the tracked directory. `timeout` overrides the
+    // connection's command timeout for this one command.
+    pub fn execute_command(&mut self, command: &str, timeout: Option<Duration>) -> Result<CommandResult> {
+        self.execute_command_as(command, timeout, None, None, None, None)
+    }
+
+    // Like execute_command, with the command registered under `channel_id`
+    // so cancel_command can stop it, run at `priority` and under `limits`
+    // if given, and with any sudo in it answered with `sudo_password`
+    pub fn execute_command_as(
+        &mut self,
+        command: &str,
+        timeout: Option<Duration>,
+        channel_id: Option<&str>,
+        priority: Option<&priority::CommandPriority>,
+        limits: Option<&limits::ResourceLimits>,
+        sudo_password: Option<&str>,
+    ) -> Result<CommandResult> {
+        let started = self.start_command(command, timeout, channel_id, priority, limits, sudo_password)?;
+        self.run_locked(started)
+    }
+
+    fn env_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.session_env.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn with_env_names(&self, mut result: CommandResult) -> CommandResult {
+        result.env_names = self.env_names();
+        result
+    }
+
+    // Sends a command to the server, leaving its output to be read by
+    // run_locked or run_unlocked. The arguments are as for execute_command_as.
+    fn start_command(
+        &mut self,
+        command: &str,
+        timeout: Option<Duration>,
+        channel_id: Option<&str>,
+        priority: Option<&priority::CommandPriority>,
+        limits: Option<&limits::ResourceLimits>,
+        sudo_password: Option<&str>,
+    ) -> Result<StartedCommand> {
+        let (mut full_command, tracking) = self.prepare_tracked_command(command);
+        if let Some(limits) = limits {
+            full_command.insert_str(0, &limits.shell_prefix());
+        }
+        let sudo = sudo_password.map(sudo::PasswordResponder::new);
+        if let Some(sudo) = &sudo {
+            full_command.insert_str(0, &sudo.shell_prefix());
+        }
+        if let Some(priority) = priority {
+            full_command.insert_str(0, &priority.shell_prefix());
+        }
+
+        self.record_command(command);
+        let env_change = env::parse_env_command(command);
+        if let Some(env::EnvChange::Export(names)) = &env_change {
+            let result = self.execute_export(command, names)?;
+            return Ok(StartedCommand::Finished(self.with_env_names(result)));
+        }
+
+        let cancel = self.cancels.register(channel_id);
+        let channel = match self.open_command_channel(&full_command) {
+            // Nothing reached the server yet, so the command can safely be
+            // sent again over a new connection
+            Err(e) if self.auto_reconnect() && health::is_transport_error(&e) => {
+                self.reconnect(&mut auth::NoPrompter)
+                    .map_err(|reconnect_error| e.context(ReconnectFailed(reconnect_error.to_string())))?;
+                self.open_command_channel(&full_command)?
+            }
+            result => result?,
+        };
+
+        let mut running = RunningCommand::new(self, channel, cancel, tracking, timeout.or(self.command_timeout), sudo);
+        running.limits = limits.cloned();
+        running.env_change = env_change;
+        Ok(StartedCommand::Running(running))
+    }
+
+    // Runs an export and records the values it left the variables with
+    fn execute_export(&mut self, command: &str, names: &[String]) -> Result<CommandResult> {
+        let script = self.in_current_directory(&env::export_capture_script(command, names));
+        let (exit_status, stdout, stderr) = self.exec_capture(&script)?;
+
+        if exit_status == 0 {
+            for (name, value) in names.iter().zip(stdout.split('\0')) {
+                self.session_env.insert(name.clone(), value.to_string());
+            }
+        }
+
+        Ok(CommandResult::from_output(Vec::new(), stderr.into_bytes(), exit_status, self.current_directory.clone()))
+    }
+
+    // Runs a command as root through sudo.
+    //
+    // sudo always gets a PTY here, whatever other commands use, because servers
+    // with `Defaults requiretty` in sudoers refuse to run it without one.
+    // Without a password, `-n` makes sudo fail straight away if it wants one,
+    // rather than waiting on a prompt nothing can answer.
+    pub fn sudo_execute(&mut self, command: &str, channel_id: Option<&str>, password: Option<&str>) -> Result<CommandResult> {
+        let started = self.start_sudo(command, channel_id, password)?;
+        self.run_locked(started)
+    }
+
+    // Sends a command for sudo_execute, leaving its output to be read by
+    // run_locked or run_unlocked
+    fn start_sudo(&mut self, command: &str, channel_id: Option<&str>, password: Option<&str>) -> Result<StartedCommand> {
+        let sudo = password.map(sudo::PasswordResponder::new);
+        let full_command = match &sudo {
+            Some(sudo) => format!("{}sudo -- sh -c {}", sudo.shell_prefix(), shell_quote(command)),
+            None => format!("sudo -n -- sh -c {}", shell_quote(command)),
+        };
+        let full_command = self.in_current_directory(&full_command);
+
+        self.record_command(&format!("sudo {}", command));
+        let cancel = self.cancels.register(channel_id);
+        let channel = self.open_exec_channel(&full_command, true)?;
+
+        let mut running = RunningCommand::new(self, channel, cancel, DirectoryTracking::None, self.command_timeout, sudo);
+        running.explain_sudo_failure = true;
+        Ok(StartedCommand::Running(running))
+    }
+
+    // Turns a command whose output has been read into its CommandResult,
+    // updating the working directory after a successful cd
+    fn complete_command(&mut self, mut running: RunningCommand, outcome: ReadOutcome) -> Result<CommandResult> {
+        let mut result = self.command_result(&mut running, outcome)?;
+        if result.cancelled || result.timed_out {
+            return Ok(self.with_env_names(result));
+        }
+
+        result.limit_exceeded = running.limits.as_ref().and_then(|limits| limits.exceeded(&result));
+        if let Some(message) = command_not_found_message(&result) {
+            result = result.with_error(errors::SSHError::new(errors::SSHErrorKind::CommandNotFound, message));
+        }
+        if let Some(env::EnvChange::Unset(names)) = running.env_change.take() {
+            if result.success {
+                for name in names {
+                    self.session_env.remove(&name);
+                }
+            }
+        }
+        if running.explain_sudo_failure && !result.success {
+            if let Some(message) = sudo_failure_message(&result) {
+                if !result.stderr.is_empty() && !result.stderr.ends_with('\n') {
+                    result.stderr.push('\n');
+                }
+                result.stderr.push_str(message);
+            }
+        }
+        Ok(self.with_env_names(result))
+    }
+
+    fn command_result(&mut self, running: &mut RunningCommand, outcome: ReadOutcome) -> Result<CommandResult> {
+        if let Some(sudo) = &mut running.sudo {
+            sudo.finish(&mut running.stdout.data);
+        }
+        let (tracking, timeout, channel) = (running.tracking, running.timeout, &mut running.channel);
+        let dropped_bytes = running.stdout.dropped + running.stderr.dropped;
+        let mut stdout = std::mem::take(&mut running.stdout.data);
+        let stderr = std::mem::take(&mut running.stderr.data);
+        match outcome {
+            ReadOutcome::Finished => {}
+            // The directory is left alone, so a cancelled cd changes nothing
+            ReadOutcome::Cancelled => {
+                Self::abort_channel(channel)?;
+                return Ok(self.cancelled_result());
+            }
+            ReadOutcome::TimedOut => {
+                Self::abort_channel(channel)?;
+                let timeout = timeout.unwrap_or_default();
+                let mut result = CommandResult::from_output(
+                    if tracking == DirectoryTracking::Cd { Vec::new() } else { stdout },
+                    stderr,
+                    -1,
+                    self.current_directory.clone(),
+                );
+                result.stderr.push_str(&format!("Command timed out after {} ms", timeout.as_millis()));
+                result.success = false;
+                result.timed_out = true;
+                result.truncated = dropped_bytes > 0;
+                result.dropped_bytes = dropped_bytes;
+                return Ok(result);
+            }
+            ReadOutcome::SudoRejected => {
+                Self::abort_channel(channel)?;
+                let message = "sudo: incorrect password";
+                let mut result = CommandResult::from_output(stdout, stderr, -1, self.current_directory.clone());
+                if !result.stderr.is_empty() && !result.stderr.ends_with('\n') {
+                    result.stderr.push('\n');
+                }
+                result.stderr.push_str(message);
+                return Ok(result.with_error(errors::SSHError::new(errors::SSHErrorKind::SudoPasswordRejected, message)));
+            }
+        }
+
+        channel.wait_close()?;
+        let (exit_status, exit_signal) = read_exit(channel)?;
+
+        match tracking {
+            // If it was a successful cd command, update our current directory
+            DirectoryTracking::Cd if exit_status == 0 => {
+                self.set_current_directory(String::from_utf8_lossy(&stdout).trim().to_string());
+                // For cd commands, we don't want to show the pwd output
+                stdout.clear();
+            }
+            // Whatever the exit status, the directory is wherever the command ended up
+            DirectoryTracking::Marker => {
+                if let Some(directory) = take_marked_directory(&mut stdout) {
+                    self.set_current_directory(directory);
+                }
+            }
+            _ => {}
+        }
+        let mut result = CommandResult::from_output(stdout, stderr, exit_status, self.current_directory.clone());
+        result.exit_signal = exit_signal;
+        result.truncated = dropped_bytes > 0;
+        result.dropped_bytes = dropped_bytes;
+        Ok(result)
+    }
+
+    // Counts the bytes and lines a command prints (stdout and stderr together,
+    // as execute_command reports them) without transferring the output itself.
+    //
+    // Fetching the output after this means running the command a second time,
+    // which doubles its cost and side effects and may not even produce the same
+    // output. With `keep_output` the output is instead written to a temp file on
+    // the server, whose path is returned so the caller can fetch it (e.g. with
+    // `cat` or SFTP) without re-running anything, and must remove it afterwards.
+    pub fn estimate_output_size(&self, command: &str, keep_output: bool) -> Result<OutputSizeEstimate> {
+        let command = self.in_current_directory(command);
+
+        let script = if keep_output {
+            format!(
+                "tmp=$(mktemp) || exit 1; {{ {}\n}} >\"$tmp\" 2>&1; printf 'rc:%s\\n' \"$?\"; \
+                 printf 'wc:%s\\n' \"$(wc -lc < \"$tmp\")\"; printf 'file:%s\\n' \"$tmp\"",
+                command
+            )
+        } else {
+            // The command's exit status goes out on fd 3 so the pipe into wc doesn't hide it
+            format!(
+                "{{ {{ {{ {}\n}} 2>&1; printf 'rc:%s\\n' \"$?\" >&3; }} | wc -lc | sed 's/^/wc:/'; }} 3>&1",
+                command
+            )
+        };
+
+        let (status, stdout, stderr) = self.exec_capture(&script)?;
+
+        let mut estimate = OutputSizeEstimate {
+            bytes: 0,
+            lines: 0,
+            exit_status: -1,
+            success: false,
+            output_file: None,
+        };
+        let mut counted = false;
+
+        for line in stdout.lines() {
+            if let Some(rc) = line.strip_prefix("rc:") {
+                estimate.exit_status = rc.trim().parse().unwrap_or(-1);
+                estimate.success = estimate.exit_status == 0;
+            } else if let Some(counts) = line.strip_prefix("wc:") {
+                // wc -lc prints the line count followed by the byte count
+                let mut counts = counts.split_whitespace().map(|n| n.parse::<u64>());
+                if let (Some(Ok(lines)), Some(Ok(bytes))) = (counts.next(), counts.next()) {
+                    estimate.lines = lines;
+                    estimate.bytes = bytes;
+                    counted = true;
+                }
+            } else if let Some(path) = line.strip_prefix("file:") {
+                estimate.output_file = Some(path.to_string());
+            }
+        }
+
+        if !counted {
+            anyhow::bail!("Failed to measure command output (exit status {}): {}", status, stderr.trim());
+        }
+
+        Ok(estimate)
+    }
+
+    pub fn get_current_directory(&self) -> &str {
+        &self.current_directory
+    }
+
+    fn record_command(&mut self, command: &str) {
+        if self.recent_commands.len() == MAX_RECENT_COMMANDS {
+            self.recent_commands.pop_front();
+        }
+        self.recent_commands.push_back(diagnostics::redact_command(command));
+    }
+
+    fn record_error(&mut self, message: &str) {
+        self.last_error = Some(message.to_string());
+    }
+}
+
+// Explains the sudo errors that otherwise leave users guessing. With a PTY,
+// sudo's messages arrive on stdout, so both streams are checked.
+fn sudo_failure_message(result: &CommandResult) -> Option<&'static str> {
+    let output = format!("{}\n{}", result.stdout, result.stderr);
+
+    if output.contains("must have a tty") || output.contains("a terminal is required") {
+        Some("sudo refused to run without a terminal (requiretty is set in sudoers), \
+              even though a PTY was allocated. Check the Defaults for this user in sudoers.")
+    } else if output.contains("a password is required") {
+        Some("sudo needs a password for this user. Pass it as sudo_password, or configure \
+              NOPASSWD in sudoers for the commands you want to run from here.")
+    } else {
+        None
+    }
+}
+
+// The shell's complaint when a command exited 127 because it couldn't be
+// found. A command can exit 127 itself, so the status alone isn't enough.
+// With a PTY the complaint arrives on stdout, so both streams are checked.
+fn command_not_found_message(result: &CommandResult) -> Option<String> {
+    if result.exit_status != 127 || result.exit_signal.is_some() {
+        return None;
+    }
+    result.stderr.lines()
+        .chain(result.stdout.lines())
+        .map(str::trim)
+        .find(|line| line.contains("command not found") || line.contains(": not found"))
+        .map(str::to_string)
+}
+
+// Each connection has its own lock, so a slow command on one connection
+// doesn't hold up the others. The store's lock is only held to look up, add
+// or remove a connection.
+type SharedClient = Arc<Mutex<SSHClient>>;
+type ConnectionsStore = Arc<Mutex<HashMap<String, SharedClient>>>;
+
+// Looks up a connection, releasing the store lock before returning
+fn get_client(connections: &ConnectionsStore, connection_id: &str) -> Result<SharedClient> {
+    let connections = connections.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+    connections.get(connection_id)
+        .cloned()
+        .context(errors::Stage::new(errors::SSHErrorKind::NotConnected, "Connection not found. Please connect first."))
+}
+
+// Looks up a connection for with_client and friends
+pub(crate) fn find_client(connections: &ConnectionsStore, connection_id: &str) -> Result<SharedClient, errors::SSHError> {
+    get_client(connections, connection_id).map_err(|e| errors::SSHError::from_anyhow(&e, e.to_string()))
+}
+
+fn task_failed(e: tauri::Error) -> errors::SSHError {
+    errors::SSHError::new(errors::SSHErrorKind::Internal, format!("Connection task failed: {}", e))
+}
+
+// Runs work on one connection from a blocking thread, so that waiting on
+// its lock or on the network never ties up the async runtime's threads.
+// Other connections and the store stay usable the whole time.
+//
+// A missing connection fails as not_connected; `f` failing with a message
+// is reported as internal.
+async fn with_client<T, F>(connections: &ConnectionsStore, connection_id: &str, f: F) -> Result<T, errors::SSHError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut SSHClient) -> Result<T, String> + Send + 'static,
+{
+    let client = find_client(connections, connection_id)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut client = client.lock().map_err(|e| format!("Lock error: {}", e))?;
+        Ok(f(&mut client)?)
+    })
+    .await
+    .map_err(task_failed)?
+}
+
+// Like with_client, for work that takes the connection's lock itself, a
+// step at a time
+async fn with_shared_client<T, F>(connections: &ConnectionsStore, connection_id: &str, f: F) -> Result<T, errors::SSHError>
+where
+    T: Send + 'static,
+    F: FnOnce(&SharedClient) -> Result<T, String> + Send + 'static,
+{
+    let client = find_client(connections, connection_id)?;
+    tauri::async_runtime::spawn_blocking(move || Ok(f(&client)?))
+        .await
+        .map_err(task_failed)?
+}
+
+// Like with_client, for a one-shot command. `start` sends it under the lock,
+// then its output is read taking the lock once per poll, like the streaming
+// and shell threads do, so tunnels, shells and transfers on the connection
+// keep moving while it runs. Failures are handed to `on_error`.
+async fn execute_queued<S, E>(connections: &ConnectionsStore, connection_id: &str, start: S, on_error: E) -> Result<CommandResult, errors::SSHError>
+where
+    S: FnMut(&mut SSHClient) -> Result<StartedCommand> + Send + 'static,
+    E: FnOnce(&mut SSHClient, anyhow::Error) -> Result<CommandResult, String> + Send + 'static,
+{
+    let client = find_client(connections, connection_id)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        call_queued(&client, start)
+            .and_then(|started| run_unlocked(&client, started))
+            .or_else(|e| {
+                let mut client = client.lock().map_err(|e| format!("Lock error: {}", e))?;
+                Ok(on_error(&mut client, e)?)
+            })
+    })
+    .await
+    .map_err(task_failed)?
+}
+
+// Runs `f` under the client's lock. While the server is at its MaxSessions
+// limit, `f` is retried for as long as the connection's max_sessions_wait
+// allows, with the lock released in between so whatever holds the other
+// channels can finish.
+fn call_queued<T>(client: &SharedClient, mut f: impl FnMut(&mut SSHClient) -> Result<T>) -> Result<T> {
+    let started = Instant::now();
+    loop {
+        let mut client = client.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        let e = match f(&mut client) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+
+        let can_wait = client.max_sessions_wait.is_some_and(|wait| started.elapsed() < wait);
+        if !(can_wait && e.downcast_ref::<MaxSessionsReached>().is_some()) {
+            return Err(e);
+        }
+        drop(client);
+        thread::sleep(MAX_SESSIONS_RETRY_INTERVAL);
+    }
+}
+
+// Reads a started command to the end, holding the lock only while polling.
+// The channel is freed under the lock too, since that talks to the server.
+fn run_unlocked(client: &SharedClient, started: StartedCommand) -> Result<CommandResult> {
+    let mut running = match started {
+        StartedCommand::Finished(result) => return Ok(result),
+        StartedCommand::Running(running) => running,
+    };
+
+    loop {
+        let received = running.received();
+        let mut client = client.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        match client.poll_command(&mut running) {
+            Ok(Some(outcome)) => return client.complete_command(running, outcome),
+            Ok(None) => {}
+            Err(e) => {
+                drop(running);
+                return Err(e);
+            }
+        }
+        drop(client);
+
+        if running.received() == received {
+            thread::sleep(STREAM_POLL_INTERVAL);
+        }
+    }
+}
+
+// Cancellation requests for one connection's commands. Every running command
+// has its own flag, so a cancel only reaches the commands running when it's
+// made, never one that starts just after.
+#[derive(Default)]
+struct CommandCancels {
+    // Every running command's flag, for cancel_ssh_command
+    live: Mutex<Vec<Arc<AtomicBool>>>,
+    // Running commands the caller gave a channel id, for cancel_command
+    channels: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl CommandCancels {
+    // Called as a command starts. Returns the flag that cancels just this
+    // command, registered under its channel id if it has one.
+    fn begin(&self, channel_id: Option<&str>) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        if let Ok(mut live) = self.live.lock() {
+            live.push(flag.clone());
+        }
+        if let (Some(channel_id), Ok(mut channels)) = (channel_id, self.channels.lock()) {
+            channels.insert(channel_id.to_string(), flag.clone());
+        }
+        flag
+    }
+
+    // Like begin, for a command that's over once the registration is dropped
+    fn register(self: &Arc<Self>, channel_id: Option<&str>) -> CancelRegistration {
+        CancelRegistration {
+            cancels: self.clone(),
+            flag: self.begin(channel_id),
+            channel_id: channel_id.map(str::to_string),
+        }
+    }
+
+    // Called with what begin returned once the command is over
+    fn finish(&self, flag: &Arc<AtomicBool>, channel_id: Option<&str>) {
+        if let Ok(mut live) = self.live.lock() {
+            live.retain(|live| !Arc::ptr_eq(live, flag));
+        }
+        if let (Some(channel_id), Ok(mut channels)) = (channel_id, self.channels.lock()) {
+            // A later command may have taken the same channel id
+            if channels.get(channel_id).is_some_and(|registered| Arc::ptr_eq(registered, flag)) {
+                channels.remove(channel_id);
+            }
+        }
+    }
+
+    fn cancel_all(&self) {
+        if let Ok(live) = self.live.lock() {
+            for flag in live.iter() {
+                flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Returns false if no running command has that channel id
+    fn cancel(&self, channel_id: &str) -> bool {
+        match self.channels.lock().ok().and_then(|channels| channels.get(channel_id).cloned()) {
+            Some(flag) => {
+                flag.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// A running command's cancel flag, unregistered when dropped
+struct CancelRegistration {
+    cancels: Arc<CommandCancels>,
+    flag: Arc<AtomicBool>,
+    channel_id: Option<String>,
+}
+
+impl CancelRegistration {
+    fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for CancelRegistration {
+    fn drop(&mut self) {
+        self.cancels.finish(&self.flag, self.channel_id.as_deref());
+    }
+}
+
+// Each connection's cancellation requests, kept outside the connections store
+// so that a command holding the connection's lock can still be cancelled
+type CancelFlags = Arc<Mutex<HashMap<String, Arc<CommandCancels>>>>;
+
+// Each connection's display label, e.g. "alice@example.com:22", kept outside
+// the connections store so listing connections never waits on a busy one
+type ConnectionLabels = Arc<Mutex<HashMap<String, String>>>;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+// Unique even for several connections to the same user and host
+fn new_connection_id() -> String {
+    format!("conn-{}", NEXT_CONNECTION_ID.fetch_add(1, Ordering::SeqCst))
+}
+
+fn connection_label(config: &SSHConnectionConfig) -> String {
+    format!("{}@{}:{}", config.username, config.host, config.port)
+}
+
+// `label`, or with " (2)", " (3)" and so on after it when another connection
+// already has it, so connections to the same target can be told apart
+fn unique_label(labels: &HashMap<String, String>, label: String) -> String {
+    let taken = |candidate: &String| labels.values().any(|l| l == candidate);
+    if !taken(&label) {
+        return label;
+    }
+    (2..).map(|n| format!("{} ({})", label, n)).find(|candidate| !taken(candidate)).unwrap()
+}
+
+// Puts a connected client in the stores under `connection_id`, returning the
+// label it was given
+fn register_connection(
+    connections: &ConnectionsStore,
+    cancel_flags: &CancelFlags,
+    labels: &ConnectionLabels,
+    connection_id: &str,
+    client: SSHClient,
+    label: String,
+) -> Result<String, String> {
+    let mut cancel_flags = cancel_flags.lock().map_err(|e| format!("Lock error: {}", e))?;
+    cancel_flags.insert(connection_id.to_string(), client.cancels.clone());
+
+    let mut labels = labels.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let label = unique_label(&labels, label);
+    labels.insert(connection_id.to_string(), label.clone());
+
+    let mut connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
+    connections.insert(connection_id.to_string(), Arc::new(Mutex::new(client)));
+    Ok(label)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionSummary {
+    pub connection_id: String,
+    pub label: String,
+}
+
+// Reads whatever is currently available from a non-blocking stream into `buf`.
+// Returns true once the stream has hit EOF.
+fn drain_available(stream: &mut impl Read, buf: &mut Vec<u8>) -> std::io::Result<bool> {
+    let mut chunk = [0u8; 8192];
+    let start = buf.len();
+    // Cap each pass so a chatty command can't starve the other stream or the flush
+    while buf.len() - start < STREAM_FLUSH_THRESHOLD {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(true),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(false)
+}
+
+// A closed channel's exit status, and the signal that killed the command if
+// one did. OpenSSH sends only the signal then, which libssh2 reports as exit
+// status 0, so the status is made up the way shells do it instead.
+fn read_exit(channel: &Channel) -> Result<(i32, Option<String>)> {
+    let exit_status = channel.exit_status()?;
+    match channel.exit_signal()?.exit_signal {
+        Some(signal) => Ok((signal_exit_status(&signal), Some(signal))),
+        None => Ok((exit_status, None)),
+    }
+}
+
+// 128 plus the signal's number, for the signals whose numbers are the same
+// everywhere; other signals only get a non-zero status
+fn signal_exit_status(signal: &str) -> i32 {
+    let number = match signal {
+        "HUP" => 1,
+        "INT" => 2,
+        "QUIT" => 3,
+        "ILL" => 4,
+        "TRAP" => 5,
+        "ABRT" => 6,
+        "FPE" => 8,
+        "KILL" => 9,
+        "SEGV" => 11,
+        "PIPE" => 13,
+        "ALRM" => 14,
+        "TERM" => 15,
+        // Not in POSIX, but the same on Linux, the BSDs and macOS
+        "XCPU" => 24,
+        "XFSZ" => 25,
+        _ => return -1,
+    };
+    128 + number
+}
+
+// A command's buffered output, keeping at most `limit` bytes and only
+// counting the rest, so a command that prints gigabytes can't exhaust memory
+struct CappedOutput {
+    data: Vec<u8>,
+    limit: Option<usize>,
+    dropped: u64,
+}
+
+impl CappedOutput {
+    fn new(limit: Option<usize>) -> Self {
+        CappedOutput { data: Vec::new(), limit, dropped: 0 }
+    }
+
+    // Like drain_available. Everything after the limit is read and thrown
+    // away, so the command can still run to completion.
+    fn drain(&mut self, stream: &mut impl Read) -> std::io::Result<bool> {
+        if self.dropped > 0 {
+            let mut discarded = Vec::new();
+            let eof = drain_available(stream, &mut discarded)?;
+            self.dropped += discarded.len() as u64;
+            return Ok(eof);
+        }
+
+        let eof = drain_available(stream, &mut self.data)?;
+        if let Some(limit) = self.limit.filter(|&limit| self.data.len() > limit) {
+            // Cut at the start of a character, so the kept text doesn't end
+            // in a broken one and look binary
+            let mut end = limit;
+            while end > 0 && limit - end < 3 && self.data[end] & 0xC0 == 0x80 {
+                end -= 1;
+            }
+            self.dropped += (self.data.len() - end) as u64;
+            self.data.truncate(end);
+        }
+        Ok(eof)
+    }
+
+    fn received(&self) -> u64 {
+        self.data.len() as u64 + self.dropped
+    }
+}
+
+// Takes the longest valid UTF-8 prefix out of `buf`, leaving a trailing
+// partial character behind for the next read to complete
+fn take_utf8_prefix(buf: &mut Vec<u8>) -> String {
+    let valid_len = match std::str::from_utf8(buf) {
+        Ok(_) => buf.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => buf.len(),
+    };
+    let rest = buf.split_off(valid_len);
+    let text = String::from_utf8_lossy(buf).into_owned();
+    *buf = rest;
+    text
+}
+
+// Where a streaming command's events are sent
+struct StreamTarget<'a> {
+    app: &'a AppHandle,
+    connection_id: &'a str,
+    channel_id: &'a str,
+    // Set for execute_ndjson_streaming, which parses stdout instead of
+    // passing it on
+    ndjson: Option<RefCell<ndjson::NdjsonParser>>,
+}
+
+impl StreamTarget<'_> {
+    fn emit_text(&self, stream: OutputStream, chunk: String) {
+        if !chunk.is_empty() {
+            let _ = self.app.emit("ssh-output", CommandOutputEvent {
+                connection_id: self.connection_id.to_string(),
+                channel_id: self.channel_id.to_string(),
+                stream,
+                chunk,
+            });
+        }
+    }
+
+    // Emits the buffered output, holding back a trailing partial UTF-8
+    // character unless the stream has finished
+    fn emit_output(&self, stream: OutputStream, buf: &mut Vec<u8>, finished: bool) {
+        if let (OutputStream::Stdout, Some(parser)) = (stream, &self.ndjson) {
+            for parsed in parser.borrow_mut().feed(buf, finished) {
+                self.emit_ndjson(parsed);
+            }
+            return;
+        }
+        let chunk = if finished {
+            String::from_utf8_lossy(&std::mem::take(buf)).into_owned()
+        } else {
+            take_utf8_prefix(buf)
+        };
+        self.emit_text(stream, chunk);
+    }
+
+    fn emit_ndjson(&self, parsed: ndjson::ParsedLine) {
+        let (connection_id, channel_id) = (self.connection_id.to_string(), self.channel_id.to_string());
+        let _ = match parsed {
+            ndjson::ParsedLine::Record { line, record } => {
+                self.app.emit("ndjson-record", ndjson::NdjsonRecordEvent { connection_id, channel_id, line, record })
+            }
+            ndjson::ParsedLine::Error { line, error, text } => {
+                self.app.emit("ndjson-error", ndjson::NdjsonErrorEvent { connection_id, channel_id, line, error, text })
+            }
+        };
+    }
+
+    fn exit_event(&self, exit_status: i32, current_directory: String) -> CommandExitEvent {
+        CommandExitEvent {
+            connection_id: self.connection_id.to_string(),
+            channel_id: self.channel_id.to_string(),
+            exit_status,
+            success: exit_status == 0,
+            exit_signal: None,
+            cancelled: false,
+            current_directory,
+        }
+    }
+}
+
+// Pumps a running command's output to the frontend until the channel closes.
+//
+// The session is only switched to non-blocking mode while the connection's lock
+// is held, so other commands on the same connection can interleave with the
+// stream between polls and always see a blocking session.
+fn stream_command_output(
+    target: &StreamTarget,
+    connections: &ConnectionsStore,
+    mut channel: Channel,
+    tracking: DirectoryTracking,
+    cancel: &AtomicBool,
+) -> Result<CommandExitEvent> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut stdout_eof = false;
+    let mut stderr_eof = false;
+    let mut last_flush = Instant::now();
+    let mut cancelled = false;
+
+    while !(stdout_eof && stderr_eof) {
+        let buffered = stdout.len() + stderr.len();
+        {
+            let client = get_client(connections, target.connection_id)
+                .context("Connection was closed while the command was running")?;
+            let client = client.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+            if cancel.load(Ordering::SeqCst) {
+                cancelled = true;
+                break;
+            }
+
+            client.session.set_blocking(false);
+            let result = (|| -> std::io::Result<()> {
+                if !stdout_eof {
+                    stdout_eof = drain_available(&mut channel, &mut stdout)?;
+                }
+                if !stderr_eof {
+                    stderr_eof = drain_available(&mut channel.stderr(), &mut stderr)?;
+                }
+                Ok(())
+            })();
+            client.session.set_blocking(true);
+            result?;
+        }
+
+        let finished = stdout_eof && stderr_eof;
+        let got_data = stdout.len() + stderr.len() > buffered;
+        let buffer_full = stdout.len() >= STREAM_FLUSH_THRESHOLD || stderr.len() >= STREAM_FLUSH_THRESHOLD;
+
+        if finished || buffer_full || last_flush.elapsed() >= STREAM_FLUSH_INTERVAL {
+            match tracking {
+                DirectoryTracking::None => target.emit_output(OutputStream::Stdout, &mut stdout, finished),
+                // Held back until the end, when the marker and pwd are cut
+                DirectoryTracking::Marker if !finished => {
+                    let mut held = stdout.split_off(shown_before_marker(&stdout));
+                    target.emit_output(OutputStream::Stdout, &mut stdout, false);
+                    stdout.append(&mut held);
+                }
+                // A cd command's stdout is the pwd output, which we keep to update the directory
+                _ => {}
+            }
+            target.emit_output(OutputStream::Stderr, &mut stderr, finished);
+            last_flush = Instant::now();
+        }
+
+        if !got_data && !finished {
+            thread::sleep(STREAM_POLL_INTERVAL);
+        }
+    }
+
+    let client = get_client(connections, target.connection_id)
+        .context("Connection was closed while the command was running")?;
+    let mut client = client.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+    if cancelled {
+        SSHClient::abort_channel(&mut channel)?;
+        if tracking != DirectoryTracking::Cd {
+            take_marked_directory(&mut stdout);
+            target.emit_output(OutputStream::Stdout, &mut stdout, true);
+        }
+        stderr.extend_from_slice(b"Command cancelled");
+        target.emit_output(OutputStream::Stderr, &mut stderr, true);
+
+        let mut exit = target.exit_event(-1, client.current_directory.clone());
+        exit.cancelled = true;
+        return Ok(exit);
+    }
+
+    channel.wait_close()?;
+    let (exit_status, exit_signal) = read_exit(&channel)?;
+
+    match tracking {
+        DirectoryTracking::Cd if exit_status == 0 => {
+            client.set_current_directory(String::from_utf8_lossy(&stdout).trim().to_string());
+        }
+        DirectoryTracking::Cd => target.emit_output(OutputStream::Stdout, &mut stdout, true),
+        // Whatever the exit status, the directory is wherever the command ended up
+        DirectoryTracking::Marker => {
+            if let Some(directory) = take_marked_directory(&mut stdout) {
+                client.set_current_directory(directory);
+            }
+            target.emit_output(OutputStream::Stdout, &mut stdout, true);
+        }
+        DirectoryTracking::None => {}
+    }
+
+    let mut exit = target.exit_event(exit_status, client.current_directory.clone());
+    exit.exit_signal = exit_signal;
+    Ok(exit)
+}
+
+#[tauri::command]
+async fn connect_ssh(
+    app: AppHandle,
+    config: SSHConnectionConfig,
+    connections: State<'_, ConnectionsStore>,
+    cancel_flags: State<'_, CancelFlags>,
+    labels: State<'_, ConnectionLabels>,
+    pending_prompts: State<'_, auth::PendingPrompts>,
+) -> Result<SSHConnectionResponse, errors::SSHError> {
+    let connection_id = new_connection_id();
+    let label = connection_label(&config);
+
+    // Connecting and authenticating block on the network (and possibly on the
+    // user answering prompts), so keep them off the async runtime's threads
+    let pending_prompts = pending_prompts.inner().clone();
+    let id = connection_id.clone();
+    let auth_result = tauri::async_runtime::spawn_blocking(move || {
+        let mut prompter = auth::FrontendPrompter::new(&app, &pending_prompts);
+
+        // Create SSH client
+        let mut client = match SSHClient::new(&config, &mut prompter) {
+            Ok(client) => client,
+            Err(e) => {
+                let message = format!("Failed to create SSH connection: {}", e);
+                return Err(Box::new(SSHConnectionResponse::failed(&e, message)));
+            }
+        };
+
+        // Authenticate based on provided credentials
+        if let Err(e) = client.authenticate(&config, &mut prompter) {
+            let message = format!("Authentication failed: {}", e);
+            return Err(Box::new(SSHConnectionResponse::failed(&e, message)));
+        }
+
+        for host_key in &client.pinned_host_keys {
+            let _ = app.emit("host-key-pinned", HostKeyPinnedEvent {
+                connection_id: id.clone(),
+                host_key: host_key.clone(),
+            });
+        }
+
+        if let Some(directory) = non_empty(&config.initial_directory) {
+            if let Some(message) = client.enter_initial_directory(directory) {
+                let _ = app.emit("ssh-connection-warning", ConnectionWarningEvent { connection_id: id, message });
+            }
+        }
+        Ok(client)
+    })
+    .await
+    .map_err(|e| format!("Connection task failed: {}", e))?;
+
+    match auth_result {
+        Ok(mut client) => {
+            // The last one is the target's own, unless only bastions were new
+            let target_key = std::mem::take(&mut client.pinned_host_keys)
+                .pop()
+                .filter(|key| key.host == normalize_host(&client.host) && key.port == client.port);
+
+            let label = register_connection(&connections, &cancel_flags, &labels, &connection_id, client, label)?;
+
+            Ok(SSHConnectionResponse {
+                success: true,
+                message: "Successfully connected and authenticated".to_string(),
+                connection_id: Some(connection_id),
+                label: Some(label),
+                error_code: None,
+                first_seen: target_key.is_some(),
+                host_key: target_key,
+                error: None,
+            })
+        }
+        Err(response) => Ok(*response),
+    }
+}
+
+// Runs a command and returns its output once it finishes. `timeout_ms` stops
+// the command after that long (falling back to the connection's
+// command_timeout_ms), returning the output so far with `timed_out` set.
+// Given a `channel_id`, the command can be stopped with cancel_command.
+// `priority` runs it under nice/ionice, for heavy commands on busy servers,
+// and `limits` under ulimit, for commands that might run away.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)] // Tauri passes each argument separately
+async fn execute_ssh_command(
+    app: AppHandle,
+    connection_id: String,
+    command: String,
+    timeout_ms: Option<u64>,
+    channel_id: Option<String>,
+    priority: Option<priority::CommandPriority>,
+    limits: Option<limits::ResourceLimits>,
+    // Answers sudo's password prompts, if the command runs sudo
+    sudo_password: Option<String>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<CommandResult, errors::SSHError> {
+    let invalid = |e: anyhow::Error, what: &str| errors::SSHError::new(errors::SSHErrorKind::InvalidArgument, format!("Invalid {}: {}", what, e));
+    if let Some(priority) = &priority {
+        priority.validate().map_err(|e| invalid(e, "priority"))?;
+    }
+    if let Some(limits) = &limits {
+        limits.validate().map_err(|e| invalid(e, "limits"))?;
+    }
+    let id = connection_id.clone();
+    execute_queued(
+        &connections,
+        &connection_id,
+        move |client| {
+            let timeout = timeout_ms.map(Duration::from_millis);
+            client.start_command(&command, timeout, channel_id.as_deref(), priority.as_ref(), limits.as_ref(), sudo_password.as_deref())
+        },
+        move |client, e| Ok(command_error(&app, &id, client, e)),
+    )
+    .await
+}
+
+// Turns a failed command into its result, dropping the connection if the
+// failure shows the transport is dead
+fn command_error(app: &AppHandle, connection_id: &str, client: &mut SSHClient, e: anyhow::Error) -> CommandResult {
+    let message = format!("Command execution failed: {}", e);
+    client.record_error(&message);
+
+    let mut result = client.with_env_names(CommandResult::failed(message.clone(), client.get_current_directory().to_string()))
+        .with_error(errors::SSHError::from_anyhow(&e, message.clone()));
+    if health::is_transport_error(&e) {
+        // The command may have started, so it isn't run again
+        if e.downcast_ref::<ReconnectFailed>().is_none() && health::try_reconnect(app, connection_id, client) {
+            result.stderr.push_str("\nReconnected; the command was not run again since it may already have started");
+        } else {
+            health::remove_dead_connection(app, connection_id, message);
+            result.disconnected = true;
+        }
+    }
+    result
+}
+
+// Runs a command with sudo, always on a PTY so `requiretty` servers accept it
+#[tauri::command]
+async fn sudo_execute(
+    app: AppHandle,
+    connection_id: String,
+    command: String,
+    channel_id: Option<String>,
+    sudo_password: Option<String>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<CommandResult, errors::SSHError> {
+    let id = connection_id.clone();
+    execute_queued(
+        &connections,
+        &connection_id,
+        move |client| client.start_sudo(&command, channel_id.as_deref(), sudo_password.as_deref()),
+        move |client, e| Ok(command_error(&app, &id, client, e)),
+    )
+    .await
+}
+
+// Adds a host's key to known_hosts after the user has accepted the fingerprint
+// returned by connect_ssh; the frontend then retries connect_ssh. A host
+// behind a bastion needs that bastion's config as jump_host to be reached.
+#[tauri::command]
+async fn trust_host_key(
+    app: AppHandle,
+    host: String,
+    port: u16,
+    fingerprint: String,
+    jump_host: Option<SSHConnectionConfig>,
+    pending_prompts: State<'_, auth::PendingPrompts>,
+) -> Result<known_hosts::HostKeyInfo, errors::SSHError> {
+    let pending_prompts = pending_prompts.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut prompter = auth::FrontendPrompter::new(&app, &pending_prompts);
+        let (session, _jump) = open_session(&host, port, jump_host.as_ref(), DEFAULT_CONNECT_TIMEOUT, &mut prompter)
+            .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to create SSH connection: {}", e)))?;
+
+        known_hosts::trust_host_key(&session, normalize_host(&host), port, &fingerprint)
+            .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to trust host key: {}", e)))
+    })
+    .await
+    .map_err(|e| format!("Connection task failed: {}", e))?
+}
+
+// Streaming variant of execute_ssh_command: returns as soon as the command has
+// started and reports its output through `ssh-output` events, followed by a
+// single `ssh-exit` event once it finishes. Every event carries `channel_id`,
+// so the caller can run several streams at once and tell them apart.
+#[tauri::command]
+async fn execute_ssh_command_streaming(
+    app: AppHandle,
+    connection_id: String,
+    command: String,
+    channel_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<(), errors::SSHError> {
+    start_streaming(app, connections.inner(), connection_id, command, channel_id, false).await
+}
+
+// Starts a streaming command and pumps its output from a thread of its own.
+// With `ndjson`, stdout is parsed as NDJSON and an `ndjson-summary` event
+// follows `ssh-exit`.
+async fn start_streaming(
+    app: AppHandle,
+    connections: &ConnectionsStore,
+    connection_id: String,
+    command: String,
+    channel_id: String,
+    ndjson: bool,
+) -> Result<(), errors::SSHError> {
+    let stream_id = channel_id.clone();
+    let (channel, tracking, cancel) = with_client(connections, &connection_id, move |client| {
+        let (full_command, tracking) = client.prepare_tracked_command(&command);
+        client.record_command(&command);
+        let cancel = client.cancels.begin(Some(&stream_id));
+        let channel = client.open_command_channel(&full_command).map_err(|e| {
+            client.cancels.finish(&cancel, Some(&stream_id));
+            format!("Command execution failed: {}", e)
+        })?;
+
+        Ok((channel, tracking, cancel))
+    })
+    .await?;
+
+    let connections = connections.clone();
+    thread::spawn(move || {
+        let target = StreamTarget {
+            app: &app,
+            connection_id: &connection_id,
+            channel_id: &channel_id,
+            ndjson: ndjson.then(RefCell::default),
+        };
+
+        let exit = match stream_command_output(&target, &connections, channel, tracking, &cancel) {
+            Ok(exit) => exit,
+            Err(e) => {
+                let message = format!("Command execution failed: {}", e);
+                target.emit_text(OutputStream::Stderr, message.clone());
+
+                let current_directory = get_client(&connections, &connection_id).ok()
+                    .and_then(|client| client.lock().ok().map(|mut c| {
+                        c.record_error(&message);
+                        c.get_current_directory().to_string()
+                    }))
+                    .unwrap_or_default();
+
+                target.exit_event(-1, current_directory)
+            }
+        };
+
+        if let Ok(client) = get_client(&connections, &connection_id) {
+            if let Ok(client) = client.lock() {
+                client.cancels.finish(&cancel, Some(&channel_id));
+            }
+        }
+        let _ = app.emit("ssh-exit", exit);
+        if let Some(parser) = &target.ndjson {
+            let _ = app.emit("ndjson-summary", parser.borrow().summary(&connection_id, &channel_id));
+        }
+    });
+
+    Ok(())
+}
+
+// Measures how much output a command produces, so the frontend can decide
+// whether to stream it, page through it or just fetch it
+#[tauri::command]
+async fn estimate_output_size(
+    connection_id: String,
+    command: String,
+    keep_output: Option<bool>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<OutputSizeEstimate, errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        client.estimate_output_size(&command, keep_output.unwrap_or(false))
+            .map_err(|e| format!("Failed to estimate output size: {}", e))
+    })
+    .await
+}
+
+// New command to get current directory
+#[tauri::command]
+async fn get_current_directory(
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<String, errors::SSHError> {
+    // Waits behind a running command, so this goes off the runtime too
+    with_client(&connections, &connection_id, |client| Ok(client.get_current_directory().to_string())).await
+}
+
+// Optional: Command to disconnect and cleanup
+#[tauri::command]
+async fn disconnect_ssh(
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+    cancel_flags: State<'_, CancelFlags>,
+    labels: State<'_, ConnectionLabels>,
+    tunnels: State<'_, tunnel::TunnelsStore>,
+) -> Result<bool, errors::SSHError> {
+    Ok(unregister_connection(&connections, &cancel_flags, &labels, &tunnels, &connection_id)?)
+}
+
+// Takes a connection out of the stores, the reverse of register_connection.
+// Returns false if there was no such connection.
+fn unregister_connection(
+    connections: &ConnectionsStore,
+    cancel_flags: &CancelFlags,
+    labels: &ConnectionLabels,
+    tunnels: &tunnel::TunnelsStore,
+    connection_id: &str,
+) -> Result<bool, String> {
+    // Stop anything still running first, so its thread lets go of the session
+    if let Some(cancels) = cancel_flags.lock().map_err(|e| format!("Lock error: {}", e))?.remove(connection_id) {
+        cancels.cancel_all();
+    }
+    tunnel::close_connection_tunnels(tunnels, connection_id)?;
+    labels.lock().map_err(|e| format!("Lock error: {}", e))?.remove(connection_id);
+
+    let mut connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    match connections.remove(connection_id) {
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+// Cancels the command currently running on a connection, if any.
+// Returns false if the connection doesn't exist.
+#[tauri::command]
+async fn cancel_ssh_command(
+    connection_id: String,
+    cancel_flags: State<'_, CancelFlags>,
+) -> Result<bool, errors::SSHError> {
+    let cancel_flags = cancel_flags.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    match cancel_flags.get(&connection_id) {
+        Some(cancels) => {
+            cancels.cancel_all();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Cancels one running command: a streaming command by its channel_id, or a
+// one-shot command started with that channel_id. Returns false if nothing
+// with that id is running on the connection.
+#[tauri::command]
+async fn cancel_command(
+    connection_id: String,
+    channel_id: String,
+    cancel_flags: State<'_, CancelFlags>,
+) -> Result<bool, errors::SSHError> {
+    let cancel_flags = cancel_flags.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    Ok(cancel_flags.get(&connection_id).is_some_and(|cancels| cancels.cancel(&channel_id)))
+}
+
+// Lists the open connections with their labels, oldest first
+#[tauri::command]
+async fn list_ssh_connections(
+    connections: State<'_, ConnectionsStore>,
+    labels: State<'_, ConnectionLabels>,
+) -> Result<Vec<ConnectionSummary>, errors::SSHError> {
+    let connections = connections.lock().map_err(|e| format!("Lock error: {}", e))?;
+    let labels = labels.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    let mut summaries: Vec<ConnectionSummary> = connections.keys()
+        .map(|id| ConnectionSummary {
+            connection_id: id.clone(),
+            label: labels.get(id).cloned().unwrap_or_else(|| id.clone()),
+        })
+        .collect();
+    // IDs count up, so their number orders them
+    summaries.sort_by_key(|s| s.connection_id.trim_start_matches("conn-").parse::<u64>().unwrap_or(u64::MAX));
+    Ok(summaries)
+}
+
+// Setup function for Tauri app
+fn setup_ssh_commands() -> ConnectionsStore {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+fn main() {
+    tauri::Builder::default()
+        .manage(setup_ssh_commands())
+        .manage(CancelFlags::default())
+        .manage(ConnectionLabels::default())
+        .manage(auth::PendingPrompts::default())
+        .manage(tunnel::TunnelsStore::default())
+        .manage(dir_transfer::TransfersStore::default())
+        .manage(stats::StatsPollersStore::default())
+        .setup(|app| {
+            health::spawn_keepalive_thread(app.handle().clone());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             connect_ssh,
+            trust_host_key,
+            profiles::save_connection_profile,
+            profiles::list_connection_profiles,
+            profiles::delete_connection_profile,
+            profiles::connect_with_profile,
+            profiles::export_connection_profiles,
+            profiles::import_connection_profiles,
+            auth::submit_keyboard_interactive,
             execute_ssh_command,
+            execute_ssh_command_streaming,
+            ndjson::execute_ndjson_streaming,
+            sudo_execute,
+            multi::execute_ssh_command_multi,
+            multi::execute_on_multiple,
+            cancel_ssh_command,
+            cancel_command,
+            health::check_connection_health,
+            health::send_keepalive,
+            health::reconnect,
+            env::set_remote_env,
+            env::unset_remote_env,
+            env::get_remote_env,
+            env::clear_remote_env,
+            estimate_output_size,
             disconnect_ssh,
             list_ssh_connections,
-            get_current_directory
+            get_current_directory,
+            diagnostics::get_sshd_info,
+            diagnostics::collect_diagnostics,
+            logins::get_last_logins,
+            services::get_service_status,
+            services::start_service,
+            services::stop_service,
+            services::restart_service,
+            stats::get_system_stats,
+            stats::start_stats_polling,
+            stats::stop_stats_polling,
+            repl::start_repl,
+            repl::repl_send_input,
+            repl::close_repl,
+            shell::start_shell,
+            shell::write_to_shell,
+            shell::resize_shell,
+            shell::close_shell,
+            files::list_remote_directory,
+            files::sftp_list_dir,
+            table::execute_table,
+            transfer::upload_files_batch,
+            transfer::execute_to_resumable_file,
+            transfer::download_resumable_output,
+            dir_transfer::upload_directory,
+            dir_transfer::download_directory,
+            dir_transfer::cancel_transfer,
+            tunnel::open_local_tunnel,
+            tunnel::open_remote_tunnel,
+            tunnel::start_dynamic_forward,
+            tunnel::close_tunnel,
+            tunnel::start_local_forward,
+            tunnel::stop_forward,
+            tunnel::list_tunnels,
+            tunnel::list_forwards,
+            tunnel::forward_stats
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Output of a script run by the local sh, standing in for the server's
+    fn sh(script: &str) -> String {
+        let output = std::process::Command::new("sh").arg("-c").arg(script).output().expect("run sh");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn shell_quote_makes_one_literal_word() {
+        for (value, quoted) in [
+            ("a b", "'a b'"),
+            ("it's", r"'it'\''s'"),
+            ("$HOME", "'$HOME'"),
+            ("", "''"),
+            ("one\ntwo", "'one\ntwo'"),
+            ("Répertoire ünï", "'Répertoire ünï'"),
+        ] {
+            assert_eq!(shell_quote(value), quoted);
+            assert_eq!(sh(&format!("printf '%s|' {}", quoted)), format!("{}|", value), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn cd_target_quotes_one_path() {
+        for (argument, target) in [
+            ("", "\"$HOME\""),
+            (" ~ ", "\"$HOME\""),
+            ("-", "\"$OLDPWD\""),
+            ("/tmp", "'/tmp'"),
+            ("My Documents", "'My Documents'"),
+            ("'My Documents'", "'My Documents'"),
+            ("\"it's here\"", r"'it'\''s here'"),
+            (r"My\ Documents", "'My Documents'"),
+            ("~/x y", "\"$HOME\"/'x y'"),
+            ("~root/etc", "~root/'etc'"),
+            ("~root", "~root"),
+            ("Données/été", "'Données/été'"),
+            ("$HOME/src", "$HOME/src"),
+        ] {
+            assert_eq!(cd_target(argument), target, "{:?}", argument);
+        }
+    }
+
+    #[test]
+    fn has_embedded_cd_finds_cd_among_other_commands() {
+        for command in ["cd /tmp; ls", "make && cd out", "ls || cd", "(cd sub && ls)", "true\ncd /", "ls | cd x"] {
+            assert!(has_embedded_cd(command), "{:?}", command);
+        }
+        for command in ["ls", "cdrecord x", "echo cd", "abcd /tmp", "ls; cdx"] {
+            assert!(!has_embedded_cd(command), "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn take_marked_directory_splits_off_the_pwd() {
+        for (output, rest, directory) in [
+            ("a\nb\n\n__AETHERSSH_CWD__/tmp\n", "a\nb\n", Some("/tmp")),
+            ("a\r\n\r\n__AETHERSSH_CWD__/home/me/My Documents\r\n", "a\r\n", Some("/home/me/My Documents")),
+            ("\n__AETHERSSH_CWD__/srv/données", "", Some("/srv/données")),
+            ("__AETHERSSH_CWD__ __AETHERSSH_CWD__\n__AETHERSSH_CWD__/x", "__AETHERSSH_CWD__ __AETHERSSH_CWD__", Some("/x")),
+            ("exited early\n", "exited early\n", None),
+            ("\n__AETHERSSH_CWD__", "", None),
+        ] {
+            let mut stdout = output.as_bytes().to_vec();
+            assert_eq!(take_marked_directory(&mut stdout).as_deref(), directory, "{:?}", output);
+            assert_eq!(String::from_utf8(stdout).unwrap(), rest, "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn shown_before_marker_holds_back_what_may_be_the_marker() {
+        let tail = CWD_MARKER.len() + 1;
+        let long = "x".repeat(40);
+        for (output, shown) in [
+            (format!("{}\n__AETHERSSH_CWD__/tmp", long), long.len()),
+            (format!("{}\r\n__AETHER", long), long.len() + 2 + 8 - tail),
+            (format!("{}\n", long), long.len() + 1 - tail),
+            ("short".to_string(), 0),
+            (long.clone(), long.len() - tail),
+        ] {
+            assert_eq!(shown_before_marker(output.as_bytes()), shown, "{:?}", output);
+        }
+
+        // However the output arrives, what's shown early and what's left
+        // once the marker is cut add up to the command's own output
+        let output = format!("{}\r\n\r\n{}/home/été\r\n", long, CWD_MARKER);
+        for received in 0..=output.len() {
+            let shown = shown_before_marker(&output.as_bytes()[..received]);
+            let mut rest = output.as_bytes()[shown..].to_vec();
+            assert_eq!(take_marked_directory(&mut rest).as_deref(), Some("/home/été"));
+            let mut seen = output.as_bytes()[..shown].to_vec();
+            seen.extend(rest);
+            assert_eq!(seen, format!("{}\r\n", long).as_bytes(), "after {} bytes", received);
+        }
+    }
+
+    #[test]
+    fn capped_output_cuts_at_a_character_boundary() {
+        for (limit, input, kept) in [
+            (5, "abcdé", "abcd"),
+            (4, "ab€", "ab"),
+            (3, "😀", ""),
+            (6, "abcdé", "abcdé"),
+            (3, "abc", "abc"),
+        ] {
+            let mut output = CappedOutput::new(Some(limit));
+            output.drain(&mut input.as_bytes()).unwrap();
+            assert_eq!(String::from_utf8(output.data.clone()).unwrap(), kept, "{:?} at {}", input, limit);
+            assert_eq!(output.dropped, (input.len() - kept.len()) as u64, "{:?} at {}", input, limit);
+        }
+    }
+
+    #[test]
+    fn capped_output_counts_what_it_drops() {
+        let mut output = CappedOutput::new(Some(4));
+        output.drain(&mut &b"ab"[..]).unwrap();
+        assert_eq!(output.dropped, 0);
+        output.drain(&mut &b"cdef"[..]).unwrap();
+        // Once over the limit, later reads are only counted
+        output.drain(&mut "ghé".as_bytes()).unwrap();
+        assert_eq!(output.data, b"abcd");
+        assert_eq!(output.dropped, 6);
+        assert_eq!(output.received(), 10);
+
+        // Each drain reads a bounded amount, so big output takes several
+        let mut unlimited = CappedOutput::new(None);
+        let big = "x".repeat(200_000);
+        let mut input = big.as_bytes();
+        while !unlimited.drain(&mut input).unwrap() {}
+        assert_eq!((unlimited.data.len(), unlimited.dropped), (200_000, 0));
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn reports_truncated_output() {
+        let mut config = test_support::test_config();
+        config.max_output_bytes = Some(11);
+        let mut client = test_support::connect(&config);
+
+        // Twenty two-byte characters, cut to five whole ones
+        let result = client.execute_command("for i in $(seq 20); do printf 'é'; done", None).unwrap();
+        assert!(result.success);
+        assert_eq!(result.stdout, "ééééé");
+        assert!(result.truncated);
+        assert_eq!(result.dropped_bytes, 30);
+    }
+
+    #[test]
+    fn invalid_utf8_output_keeps_its_exact_bytes() {
+        let result = CommandResult::from_output(b"ok\xff\xfe".to_vec(), b"fine".to_vec(), 0, String::new());
+        assert_eq!(result.stdout, "ok\u{FFFD}\u{FFFD}");
+        assert!(result.binary);
+        let exact = BASE64_STANDARD.decode(result.stdout_base64.unwrap()).unwrap();
+        assert_eq!(exact, b"ok\xff\xfe");
+        assert_eq!((result.stderr.as_str(), result.stderr_base64), ("fine", None));
+
+        let text = CommandResult::from_output("héllo".as_bytes().to_vec(), Vec::new(), 0, String::new());
+        assert!(!text.binary);
+        assert_eq!((text.stdout_base64, text.stderr_base64), (None, None));
+    }
+
+    #[test]
+    fn take_utf8_prefix_keeps_a_split_character_for_later() {
+        let mut buf = "aé".as_bytes()[..2].to_vec();
+        assert_eq!(take_utf8_prefix(&mut buf), "a");
+        assert_eq!(buf, [0xC3]);
+        buf.extend_from_slice(&"é".as_bytes()[1..]);
+        assert_eq!(take_utf8_prefix(&mut buf), "é");
+        assert!(buf.is_empty());
+
+        // Bytes that can never become valid are passed on replaced
+        let mut buf = b"a\xFFb".to_vec();
+        assert_eq!(take_utf8_prefix(&mut buf), "a\u{FFFD}b");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn signal_exit_status_follows_the_shell() {
+        for (signal, status) in [("HUP", 129), ("INT", 130), ("KILL", 137), ("SEGV", 139), ("TERM", 143), ("XCPU", 152), ("XFSZ", 153)] {
+            assert_eq!(signal_exit_status(signal), status, "{}", signal);
+        }
+        // Numbered differently from one system to the next
+        assert_eq!(signal_exit_status("USR1"), -1);
+        assert_eq!(signal_exit_status(""), -1);
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn reports_a_command_killed_by_a_signal() {
+        let config = test_support::test_config();
+        let mut client = test_support::connect(&config);
+        let result = client.execute_command("echo before; kill -9 $$; echo after", None).unwrap();
+        assert_eq!(result.stdout.trim(), "before");
+        assert_eq!(result.exit_signal.as_deref(), Some("KILL"));
+        assert_eq!(result.exit_status, 137);
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn connections_to_the_same_target_are_told_apart() {
+        let config = test_support::config("example.com", 22, "alice");
+        let (first, second) = (new_connection_id(), new_connection_id());
+        assert_ne!(first, second);
+        assert!(first.starts_with("conn-") && second.starts_with("conn-"));
+
+        let mut labels = HashMap::new();
+        for id in ["conn-1", "conn-2", "conn-3"] {
+            let label = unique_label(&labels, connection_label(&config));
+            labels.insert(id.to_string(), label);
+        }
+        assert_eq!(labels["conn-1"], "alice@example.com:22");
+        assert_eq!(labels["conn-2"], "alice@example.com:22 (2)");
+        assert_eq!(labels["conn-3"], "alice@example.com:22 (3)");
+
+        // A freed label is handed out again
+        labels.remove("conn-1");
+        assert_eq!(unique_label(&labels, connection_label(&config)), "alice@example.com:22");
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn two_connections_to_the_same_server_stay_separate() {
+        let config = test_support::test_config();
+        let connections = test_support::store(Vec::new());
+        let cancel_flags = CancelFlags::default();
+        let labels = ConnectionLabels::default();
+
+        let mut registered = Vec::new();
+        for _ in 0..2 {
+            let id = new_connection_id();
+            let label = register_connection(
+                &connections,
+                &cancel_flags,
+                &labels,
+                &id,
+                test_support::connect(&config),
+                connection_label(&config),
+            )
+            .unwrap();
+            registered.push((id, label));
+        }
+        assert_ne!(registered[0].0, registered[1].0);
+        assert_ne!(registered[0].1, registered[1].1);
+
+        // Each ID reaches its own client, with its own working directory
+        let run = |id: &str, command: &str| {
+            test_support::run(&mut get_client(&connections, id).unwrap().lock().unwrap(), command)
+        };
+        run(&registered[0].0, "cd /");
+        run(&registered[1].0, "cd /tmp");
+        assert_eq!(run(&registered[0].0, "pwd"), "/");
+        assert_eq!(run(&registered[1].0, "pwd"), "/tmp");
+
+        // Disconnecting one leaves the other usable
+        let tunnels = tunnel::TunnelsStore::default();
+        assert!(unregister_connection(&connections, &cancel_flags, &labels, &tunnels, &registered[0].0).unwrap());
+        assert!(get_client(&connections, &registered[0].0).is_err());
+        assert_eq!(run(&registered[1].0, "pwd"), "/tmp");
+        let labels = labels.lock().unwrap();
+        assert_eq!(labels.keys().collect::<Vec<_>>(), [&registered[1].0]);
+        assert_eq!(labels[&registered[1].0], registered[1].1);
+    }
+
+    #[test]
+    fn normalize_host_strips_ipv6_brackets() {
+        assert_eq!(normalize_host("[::1]"), "::1");
+        assert_eq!(normalize_host(" [2001:db8::1] "), "2001:db8::1");
+        assert_eq!(normalize_host("::1"), "::1");
+        assert_eq!(normalize_host("example.com"), "example.com");
+    }
+
+    #[test]
+    fn resolve_addresses_accepts_ipv6_literals() {
+        let expected: SocketAddr = "[::1]:22".parse().unwrap();
+        assert_eq!(resolve_addresses("::1", 22).unwrap(), vec![expected]);
+        assert_eq!(resolve_addresses("[::1]", 22).unwrap(), vec![expected]);
+    }
+
+    #[test]
+    fn resolve_addresses_puts_ipv4_first() {
+        let addrs = resolve_addresses("127.0.0.1", 2222).unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:2222".parse::<SocketAddr>().unwrap()]);
+        assert!(addrs.windows(2).all(|pair| !pair[0].is_ipv6() || pair[1].is_ipv6()));
+    }
+
+    #[test]
+    fn resolve_addresses_rejects_host_with_port() {
+        for host in ["127.0.0.1:2222", "example.com:22", "[::1]:2222"] {
+            let e = resolve_addresses(host, 22).unwrap_err();
+            assert_eq!(errors::classify(&e), errors::SSHErrorKind::InvalidArgument, "{}", host);
+        }
+        assert_eq!(embedded_port("::1"), None);
+        assert_eq!(embedded_port("2001:db8::1"), None);
+        assert_eq!(embedded_port("example.com"), None);
+    }
+
+    #[test]
+    fn cancel_all_reaches_only_running_commands() {
+        let cancels = CommandCancels::default();
+        let first = cancels.begin(None);
+        cancels.cancel_all();
+        // A command starting after the cancel must not clear it, as the
+        // shared flag used to
+        let second = cancels.begin(Some("ch-2"));
+        assert!(first.load(Ordering::SeqCst));
+        assert!(!second.load(Ordering::SeqCst));
+
+        cancels.finish(&first, None);
+        cancels.cancel_all();
+        assert!(second.load(Ordering::SeqCst));
+        cancels.finish(&second, Some("ch-2"));
+        assert!(cancels.live.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cancel_by_channel_id() {
+        let cancels = CommandCancels::default();
+        let old = cancels.begin(Some("ch"));
+        let other = cancels.begin(Some("other"));
+        // The id is reused before the old command has finished
+        let new = cancels.begin(Some("ch"));
+        cancels.finish(&old, Some("ch"));
+
+        assert!(cancels.cancel("ch"));
+        assert!(new.load(Ordering::SeqCst));
+        assert!(!other.load(Ordering::SeqCst));
+        assert!(!cancels.cancel("missing"));
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn slow_command_does_not_hold_up_other_connections() {
+        let config = test_support::test_config();
+        let connections = test_support::store(vec![
+            ("slow", test_support::connect(&config)),
+            ("fast", test_support::connect(&config)),
+        ]);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for (id, command) in [("slow", "sleep 3"), ("fast", "echo fast")] {
+            let (connections, sender) = (connections.clone(), sender.clone());
+            tauri::async_runtime::spawn(async move {
+                let result = with_client(&connections, id, move |client| {
+                    client.execute_command(command, None).map_err(|e| e.to_string())
+                })
+                .await;
+                let _ = sender.send((id, result.map(|result| result.success).map_err(|e| e.message)));
+            });
+            // Lets the slow command take its connection's lock first
+            thread::sleep(Duration::from_millis(200));
+        }
+
+        let started = Instant::now();
+        assert_eq!(receiver.recv().unwrap(), ("fast", Ok(true)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(receiver.recv().unwrap(), ("slow", Ok(true)));
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn running_command_does_not_hold_the_connection() {
+        let config = test_support::test_config();
+        let connections = test_support::store(vec![("conn", test_support::connect(&config))]);
+
+        let slow = {
+            let connections = connections.clone();
+            tauri::async_runtime::spawn(async move {
+                execute_queued(
+                    &connections,
+                    "conn",
+                    |client| client.start_command("sleep 3; echo slow", None, None, None, None, None),
+                    |_, e| Err(e.to_string()),
+                )
+                .await
+            })
+        };
+        thread::sleep(Duration::from_millis(500));
+
+        // Something else on the same connection, as a tunnel or transfer would be
+        let started = Instant::now();
+        let client = get_client(&connections, "conn").unwrap();
+        let (status, stdout, _) = client.lock().unwrap().exec_capture("echo fast").unwrap();
+        assert_eq!((status, stdout.trim()), (0, "fast"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let result = tauri::async_runtime::block_on(slow).unwrap().unwrap();
+        assert_eq!(result.stdout.trim(), "slow");
+    }
+
+    // Generates a key with ssh-keygen, returning (private key, public key)
+    fn generate_key(passphrase: &str) -> (String, String) {
+        let path = std::env::temp_dir().join(format!("aetherssh-key-{}-{}", std::process::id(), passphrase.len()));
+        let _ = std::fs::remove_file(&path);
+        let status = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "rsa", "-b", "2048", "-m", "PEM", "-C", "aetherssh-test", "-N", passphrase, "-f"])
+            .arg(&path)
+            .status()
+            .expect("run ssh-keygen");
+        assert!(status.success());
+
+        let private_key = std::fs::read_to_string(&path).unwrap();
+        let public_key = std::fs::read_to_string(path.with_extension("pub")).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("pub"));
+        (private_key, public_key.trim().to_string())
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd, and ssh-keygen"]
+    fn authenticates_with_private_key_contents() {
+        let config = test_support::test_config();
+        let mut admin = test_support::connect(&config);
+
+        for passphrase in ["", "correct horse"] {
+            let (private_key, public_key) = generate_key(passphrase);
+            test_support::run(&mut admin, &format!(
+                "mkdir -p ~/.ssh && chmod 700 ~/.ssh && printf '%s\\n' {} >> ~/.ssh/authorized_keys",
+                shell_quote(&public_key)
+            ));
+
+            let mut key_config = test_support::config(&config.host, config.port, &config.username);
+            key_config.auth_method = Some(auth::AuthMethod::Key);
+            key_config.private_key_contents = Some(private_key);
+            key_config.passphrase = (!passphrase.is_empty()).then(|| passphrase.to_string());
+            // Checked after the key is removed again
+            let result = SSHClient::new(&key_config, &mut auth::NoPrompter).and_then(|mut client| {
+                client.authenticate(&key_config, &mut auth::NoPrompter)?;
+                client.execute_command("echo ok", None)
+            });
+
+            let wrong_passphrase = if passphrase.is_empty() {
+                None
+            } else {
+                key_config.passphrase = Some("wrong".to_string());
+                let mut client = SSHClient::new(&key_config, &mut auth::NoPrompter).unwrap();
+                Some(client.authenticate(&key_config, &mut auth::NoPrompter))
+            };
+
+            test_support::run(&mut admin, &format!(
+                "grep -vxF {} ~/.ssh/authorized_keys > ~/.ssh/authorized_keys.tmp; mv ~/.ssh/authorized_keys.tmp ~/.ssh/authorized_keys",
+                shell_quote(&public_key)
+            ));
+            assert_eq!(result.unwrap().stdout.trim(), "ok", "passphrase {:?}", passphrase);
+            if let Some(result) = wrong_passphrase {
+                assert!(result.is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn sudo_failure_message_explains_requiretty() {
+        let failed = |stderr: &str| CommandResult::from_output(Vec::new(), stderr.as_bytes().to_vec(), 1, String::new());
+
+        for stderr in [
+            "sudo: sorry, you must have a tty to run sudo\n",
+            "sudo: a terminal is required to read the password; either use the -S option to read from standard input or configure an askpass helper\n",
+        ] {
+            assert!(sudo_failure_message(&failed(stderr)).unwrap().contains("requiretty"), "{}", stderr);
+        }
+        assert!(sudo_failure_message(&failed("sudo: a password is required\n")).unwrap().contains("sudo_password"));
+        assert_eq!(sudo_failure_message(&failed("ls: cannot access 'x': No such file or directory\n")), None);
+
+        // On a PTY sudo's complaints arrive on stdout
+        let on_pty = CommandResult::from_output(b"sudo: sorry, you must have a tty to run sudo\r\n".to_vec(), Vec::new(), 1, String::new());
+        assert!(sudo_failure_message(&on_pty).is_some());
+    }
+
+    #[test]
+    fn command_not_found_needs_the_shells_message() {
+        let exited = |stdout: &str, stderr: &str, status| {
+            CommandResult::from_output(stdout.as_bytes().to_vec(), stderr.as_bytes().to_vec(), status, String::new())
+        };
+
+        assert_eq!(
+            command_not_found_message(&exited("", "bash: line 1: nosuchcmd: command not found\n", 127)).as_deref(),
+            Some("bash: line 1: nosuchcmd: command not found"),
+        );
+        assert_eq!(
+            command_not_found_message(&exited("", "sh: 1: nosuchcmd: not found\n", 127)).as_deref(),
+            Some("sh: 1: nosuchcmd: not found"),
+        );
+        // On a PTY the shell's complaint arrives on stdout
+        assert_eq!(
+            command_not_found_message(&exited("bash: nosuchcmd: command not found\r\n", "", 127)).as_deref(),
+            Some("bash: nosuchcmd: command not found"),
+        );
+
+        // A command that exits 127 itself
+        assert_eq!(command_not_found_message(&exited("done\n", "", 127)), None);
+        assert_eq!(command_not_found_message(&exited("", "", 127)), None);
+        assert_eq!(command_not_found_message(&exited("", "x: command not found\n", 1)), None);
+    }
+
+    // Passes whether or not requiretty is set in sudoers, since sudo_execute
+    // always asks for a PTY. Needs sudo without a password for the test user,
+    // or AETHERSSH_TEST_SUDO_PASSWORD.
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd where the user can sudo"]
+    fn sudo_execute_runs_on_a_tty() {
+        let config = test_support::test_config();
+        let mut client = test_support::connect(&config);
+        let password = std::env::var("AETHERSSH_TEST_SUDO_PASSWORD").ok();
+
+        let result = client.sudo_execute("id -u; test -t 0 && echo tty", None, password.as_deref()).unwrap();
+        assert!(result.success, "{} {}", result.stdout, result.stderr);
+        let lines: Vec<&str> = result.stdout.lines().map(str::trim).collect();
+        assert_eq!(lines, ["0", "tty"]);
+    }
+
+    #[test]
+    #[ignore = "needs an sshd listening on ::1"]
+    fn connects_over_ipv6_loopback() {
+        let mut config = test_support::test_config();
+        config.host = "::1".to_string();
+        let mut client = test_support::connect(&config);
+        assert_eq!(test_support::run(&mut client, "echo ok"), "ok");
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn reconnects_after_the_connection_is_killed() {
+        let config = test_support::test_config();
+        let mut client = test_support::connect(&config);
+        test_support::run(&mut client, "mkdir -p '/tmp/aetherssh reconnect'");
+        test_support::run(&mut client, "cd '/tmp/aetherssh reconnect'");
+        test_support::run(&mut client, "export AETHERSSH_MARK=still-here");
+
+        // Kills the server's process for this connection mid-command, so the
+        // command itself fails and isn't run again
+        let killed = client.execute_command("kill -9 $PPID; sleep 5", None);
+        assert!(killed.as_ref().map_or(true, |result| !result.success), "{:?}", killed.map(|r| r.stdout));
+
+        // The next command finds the transport dead when opening its
+        // channel, logs in again and runs where the old session left off
+        assert_eq!(test_support::run(&mut client, "pwd"), "/tmp/aetherssh reconnect");
+        assert_eq!(test_support::run(&mut client, "echo $AETHERSSH_MARK"), "still-here");
+    }
+
+    // The first bastion is AETHERSSH_TEST_*, the second AETHERSSH_TEST_HOP_*
+    // as reached from the first (the first one again by default), and the
+    // target AETHERSSH_TEST_TARGET_* as reached from the second
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* and AETHERSSH_TEST_TARGET_* pointing at two sshds"]
+    fn connects_through_chained_jump_hosts() {
+        let first = test_support::test_config();
+        let mut second = test_support::config_from_env("AETHERSSH_TEST_HOP").unwrap_or_else(|| first.clone());
+        let mut target = test_support::config_from_env("AETHERSSH_TEST_TARGET")
+            .expect("set AETHERSSH_TEST_TARGET_HOST and friends to run this test");
+        second.jump_host = Some(Box::new(first));
+        target.jump_host = Some(Box::new(second));
+
+        let mut client = test_support::connect(&target);
+        assert_eq!(test_support::run(&mut client, "echo through"), "through");
+        // The session really is on the target: it sees the connection
+        // coming in on the target's port
+        let connection = test_support::run(&mut client, "echo $SSH_CONNECTION");
+        assert_eq!(connection.split_whitespace().nth(3), Some(target.port.to_string().as_str()), "{}", connection);
+    }
+}
@@ -0,0 +1,216 @@
+// Running one command on several connections at once
+//
+// Each connection has its own lock, so the commands run side by side, each
+// on its own blocking task. A host that's gone or fails only fails its own
+// entry, and every connection keeps its own tracked directory.
+//
+// execute_on_multiple also takes an overall timeout and sends a
+// `multi-command-result` event as each host finishes, so the UI can show
+// results before the slowest server is done.
+
+use crate::{command_error, errors, execute_queued, CommandResult, ConnectionsStore};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, State};
+
+// Payload of the `multi-command-result` event
+#[derive(Debug, Clone, Serialize)]
+pub struct HostResultEvent<'a> {
+    // As passed to execute_on_multiple, to tell batches apart
+    pub batch_id: Option<&'a str>,
+    pub connection_id: &'a str,
+    pub result: &'a CommandResult,
+}
+
+fn failed(message: String, kind: errors::SSHErrorKind) -> CommandResult {
+    CommandResult::failed(message.clone(), String::new()).with_error(errors::SSHError::new(kind, message))
+}
+
+// Runs `command` on one connection, turning every failure into its result
+async fn run_on(
+    app: AppHandle,
+    connections: ConnectionsStore,
+    connection_id: String,
+    command: String,
+    timeout: Option<Duration>,
+) -> CommandResult {
+    let id = connection_id.clone();
+    execute_queued(
+        &connections,
+        &connection_id,
+        move |client| client.start_command(&command, timeout, None, None, None, None),
+        move |client, e| Ok(command_error(&app, &id, client, e)),
+    )
+    .await
+    .unwrap_or_else(|e| CommandResult::failed(e.message.clone(), String::new()).with_error(e))
+}
+
+// Starts `run` for every connection at once and gathers the results in the
+// order the connections were listed. A task that dies fails only its entry.
+async fn in_order<F, Fut>(connection_ids: Vec<String>, run: F) -> Vec<(String, CommandResult)>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = CommandResult> + Send + 'static,
+{
+    let tasks: Vec<_> = connection_ids
+        .into_iter()
+        .map(|connection_id| {
+            let task = tauri::async_runtime::spawn(run(connection_id.clone()));
+            (connection_id, task)
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for (connection_id, task) in tasks {
+        let result = task
+            .await
+            .unwrap_or_else(|e| failed(format!("Connection task failed: {}", e), errors::SSHErrorKind::Internal));
+        results.push((connection_id, result));
+    }
+    results
+}
+
+// Runs a command on every listed connection concurrently. Results come back
+// in the order the connections were listed, as [connection_id, result].
+#[tauri::command]
+pub async fn execute_ssh_command_multi(
+    app: AppHandle,
+    connection_ids: Vec<String>,
+    command: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<Vec<(String, CommandResult)>, errors::SSHError> {
+    let connections = connections.inner().clone();
+    Ok(in_order(connection_ids, |connection_id| {
+        run_on(app.clone(), connections.clone(), connection_id, command.clone(), None)
+    })
+    .await)
+}
+
+// Runs a command on every listed connection concurrently and returns the
+// results by connection ID. With `timeout_ms`, each command is stopped when
+// it runs out, and a host that hasn't answered by then (say it's stuck
+// connecting) is reported as timed out rather than waited for.
+#[tauri::command]
+pub async fn execute_on_multiple(
+    app: AppHandle,
+    connection_ids: Vec<String>,
+    command: String,
+    timeout_ms: Option<u64>,
+    batch_id: Option<String>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<HashMap<String, CommandResult>, errors::SSHError> {
+    let timeout = timeout_ms.map(Duration::from_millis);
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    let mut seen = HashSet::new();
+    let connection_ids: Vec<String> = connection_ids.into_iter().filter(|id| seen.insert(id.clone())).collect();
+
+    let (sender, receiver) = mpsc::channel();
+    for connection_id in &connection_ids {
+        let task = run_on(app.clone(), connections.inner().clone(), connection_id.clone(), command.clone(), timeout);
+        let sender = sender.clone();
+        let connection_id = connection_id.clone();
+        tauri::async_runtime::spawn(async move {
+            let result = task.await;
+            let _ = sender.send((connection_id, result));
+        });
+    }
+    drop(sender);
+
+    // Collected from a blocking thread, so waiting out the deadline doesn't
+    // hold up the async runtime
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut results = HashMap::new();
+        // Set when every task has ended, so whoever is missing crashed
+        let mut all_ended = false;
+        while results.len() < connection_ids.len() {
+            let received = match deadline {
+                Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok((connection_id, result)) => {
+                    let _ = app.emit("multi-command-result", HostResultEvent {
+                        batch_id: batch_id.as_deref(),
+                        connection_id: &connection_id,
+                        result: &result,
+                    });
+                    results.insert(connection_id, result);
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    all_ended = true;
+                    break;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+            }
+        }
+
+        for connection_id in connection_ids {
+            if results.contains_key(&connection_id) {
+                continue;
+            }
+            let result = if all_ended {
+                failed("Connection task failed".to_string(), errors::SSHErrorKind::Internal)
+            } else {
+                let mut result = failed(
+                    format!("No result within {} ms", timeout_ms.unwrap_or_default()),
+                    errors::SSHErrorKind::Timeout,
+                );
+                result.timed_out = true;
+                result
+            };
+            let _ = app.emit("multi-command-result", HostResultEvent {
+                batch_id: batch_id.as_deref(),
+                connection_id: &connection_id,
+                result: &result,
+            });
+            results.insert(connection_id, result);
+        }
+        results
+    })
+    .await
+    .map_err(|e| errors::SSHError::new(errors::SSHErrorKind::Internal, format!("Connection task failed: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn results_keep_the_listed_order_and_failures_stay_apart() {
+        let ids = ["slow", "down", "crashed", "fast"].map(String::from).to_vec();
+
+        let results = tauri::async_runtime::block_on(in_order(ids, |connection_id| async move {
+            match connection_id.as_str() {
+                "down" => failed("Connection not found".to_string(), errors::SSHErrorKind::NotConnected),
+                "crashed" => panic!("task for {} died", connection_id),
+                _ => {
+                    // The first listed finishes last
+                    let delay = if connection_id == "slow" { 200 } else { 0 };
+                    tauri::async_runtime::spawn_blocking(move || thread::sleep(Duration::from_millis(delay)))
+                        .await
+                        .unwrap();
+                    CommandResult::from_output(connection_id.into_bytes(), Vec::new(), 0, String::new())
+                }
+            }
+        }));
+
+        let summary: Vec<_> = results
+            .iter()
+            .map(|(id, result)| (id.as_str(), result.success, result.error_code.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("slow", true, None),
+            ("down", false, Some("not_connected")),
+            ("crashed", false, Some("internal")),
+            ("fast", true, None),
+        ]);
+        assert_eq!((results[0].1.stdout.as_str(), results[3].1.stdout.as_str()), ("slow", "fast"));
+        assert_eq!(results[1].1.stderr, "Connection not found");
+        assert!(results[2].1.stderr.starts_with("Connection task failed"));
+    }
+}
@@ -0,0 +1,174 @@
+// Newline-delimited JSON from streaming commands
+//
+// execute_ndjson_streaming runs like execute_ssh_command_streaming, except
+// that stdout is split into lines and each one parsed as JSON: objects go to
+// the frontend as `ndjson-record` events and anything else as `ndjson-error`.
+// stderr still arrives as `ssh-output`. After `ssh-exit`, an `ndjson-summary`
+// event gives the counts.
+
+use crate::{errors, start_streaming, ConnectionsStore};
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, State};
+
+// Longest piece of a malformed line repeated in its ndjson-error event
+const MAX_ERROR_TEXT: usize = 200;
+
+// Payload of the `ndjson-record` event
+#[derive(Debug, Clone, Serialize)]
+pub struct NdjsonRecordEvent {
+    pub connection_id: String,
+    pub channel_id: String,
+    // 1-based line of stdout the record was on
+    pub line: u64,
+    pub record: Value,
+}
+
+// Payload of the `ndjson-error` event, for a line that was skipped
+#[derive(Debug, Clone, Serialize)]
+pub struct NdjsonErrorEvent {
+    pub connection_id: String,
+    pub channel_id: String,
+    pub line: u64,
+    pub error: String,
+    // The start of the line
+    pub text: String,
+}
+
+// Payload of the `ndjson-summary` event, sent last
+#[derive(Debug, Clone, Serialize)]
+pub struct NdjsonSummaryEvent {
+    pub connection_id: String,
+    pub channel_id: String,
+    pub records: u64,
+    pub errors: u64,
+}
+
+// One non-blank line of stdout, parsed
+#[derive(Debug, PartialEq)]
+pub enum ParsedLine {
+    Record { line: u64, record: Value },
+    // A line that was skipped, with the start of its text
+    Error { line: u64, error: String, text: String },
+}
+
+// Splits stdout into lines as it arrives, keeping a partial last line until
+// the rest of it does
+#[derive(Debug, Default)]
+pub struct NdjsonParser {
+    pending: Vec<u8>,
+    line: u64,
+    records: u64,
+    errors: u64,
+}
+
+impl NdjsonParser {
+    // Parses every complete line in `buf`, and on `finished` whatever is
+    // left too, which the command may not have ended with a newline
+    pub fn feed(&mut self, buf: &mut Vec<u8>, finished: bool) -> Vec<ParsedLine> {
+        self.pending.append(buf);
+
+        let mut parsed = Vec::new();
+        let mut start = 0;
+        while let Some(end) = self.pending[start..].iter().position(|&b| b == b'\n') {
+            let line = self.pending[start..start + end].to_vec();
+            parsed.extend(self.parse_line(&line));
+            start += end + 1;
+        }
+        self.pending.drain(..start);
+
+        if finished && !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            parsed.extend(self.parse_line(&line));
+        }
+        parsed
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> Option<ParsedLine> {
+        self.line += 1;
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim();
+        // Blank lines separate nothing in NDJSON
+        if text.is_empty() {
+            return None;
+        }
+
+        let error = match serde_json::from_str::<Value>(text) {
+            Ok(record @ Value::Object(_)) => {
+                self.records += 1;
+                return Some(ParsedLine::Record { line: self.line, record });
+            }
+            Ok(_) => "Not a JSON object".to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        self.errors += 1;
+        Some(ParsedLine::Error { line: self.line, error, text: text.chars().take(MAX_ERROR_TEXT).collect() })
+    }
+
+    pub fn summary(&self, connection_id: &str, channel_id: &str) -> NdjsonSummaryEvent {
+        NdjsonSummaryEvent {
+            connection_id: connection_id.to_string(),
+            channel_id: channel_id.to_string(),
+            records: self.records,
+            errors: self.errors,
+        }
+    }
+}
+
+// Runs a command that prints one JSON object per line (structured logs,
+// `kubectl get -w -o json`, `jq -c`) and streams the parsed objects. Returns
+// once the command has started; cancel it with cancel_command.
+#[tauri::command]
+pub async fn execute_ndjson_streaming(
+    app: AppHandle,
+    connection_id: String,
+    command: String,
+    channel_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<(), errors::SSHError> {
+    start_streaming(app, connections.inner(), connection_id, command, channel_id, true).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn feed(parser: &mut NdjsonParser, chunk: &str, finished: bool) -> Vec<ParsedLine> {
+        parser.feed(&mut chunk.as_bytes().to_vec(), finished)
+    }
+
+    #[test]
+    fn objects_split_across_chunks_are_kept() {
+        let mut parser = NdjsonParser::default();
+        assert_eq!(feed(&mut parser, "{\"a\": 1}\n{\"b\"", false), [ParsedLine::Record { line: 1, record: json!({"a": 1}) }]);
+        assert_eq!(feed(&mut parser, ": 2}\n", false), [ParsedLine::Record { line: 2, record: json!({"b": 2}) }]);
+        assert_eq!(feed(&mut parser, "", true), []);
+    }
+
+    #[test]
+    fn a_last_line_without_a_newline_is_parsed_when_finished() {
+        let mut parser = NdjsonParser::default();
+        assert_eq!(feed(&mut parser, "{\"done\": true}", false), []);
+        assert_eq!(feed(&mut parser, "", true), [ParsedLine::Record { line: 1, record: json!({"done": true}) }]);
+    }
+
+    #[test]
+    fn blank_malformed_and_non_object_lines() {
+        let mut parser = NdjsonParser::default();
+        let parsed = feed(&mut parser, "\n  \r\nnot json\n[1, 2]\n{\"ok\": 1}\n", true);
+
+        assert_eq!(parsed.len(), 3);
+        assert!(matches!(&parsed[0], ParsedLine::Error { line: 3, text, .. } if text == "not json"));
+        assert_eq!(parsed[1], ParsedLine::Error {
+            line: 4,
+            error: "Not a JSON object".to_string(),
+            text: "[1, 2]".to_string(),
+        });
+        assert_eq!(parsed[2], ParsedLine::Record { line: 5, record: json!({"ok": 1}) });
+
+        let summary = parser.summary("conn-1", "ch");
+        assert_eq!((summary.records, summary.errors), (1, 2));
+    }
+}
@@ -0,0 +1,130 @@
+// Running commands at a lower CPU and I/O priority
+//
+// Rather than wrapping the command in `nice`/`ionice` (which would hide cd
+// and export from the shell that tracks them), the command's shell lowers its
+// own priority with renice and ionice first, and everything it runs inherits
+// that. A missing tool stops the command instead of running it at full
+// priority.
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoClass {
+    // Only gets disk time when nothing else wants it
+    Idle,
+    BestEffort,
+    // Needs root
+    Realtime,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CommandPriority {
+    // Niceness from -20 (highest) to 19 (lowest); below 0 needs root
+    pub nice: Option<i32>,
+    pub io_class: Option<IoClass>,
+    // 0 (highest) to 7 (lowest), for the best_effort and realtime classes
+    pub io_level: Option<u8>,
+}
+
+impl CommandPriority {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("nice must be between -20 and 19, got {}", nice);
+            }
+        }
+        if let Some(level) = self.io_level {
+            match self.io_class {
+                None => bail!("io_level needs an io_class"),
+                Some(IoClass::Idle) => bail!("The idle I/O class doesn't take a level"),
+                Some(_) if level > 7 => bail!("io_level must be between 0 and 7, got {}", level),
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Shell commands that apply the priority to the current shell, each
+    // exiting it on failure, so that anything can follow. Empty when nothing
+    // was asked for.
+    pub fn shell_prefix(&self) -> String {
+        let mut steps = Vec::new();
+
+        if let Some(nice) = self.nice {
+            steps.push(require_tool("renice"));
+            steps.push(format!("renice -n {} -p $$ >/dev/null || exit", nice));
+        }
+        if let Some(class) = self.io_class {
+            steps.push(require_tool("ionice"));
+            let class = match class {
+                IoClass::Realtime => 1,
+                IoClass::BestEffort => 2,
+                IoClass::Idle => 3,
+            };
+            match self.io_level {
+                Some(level) => steps.push(format!("ionice -c {} -n {} -p $$ || exit", class, level)),
+                None => steps.push(format!("ionice -c {} -p $$ || exit", class)),
+            }
+        }
+
+        steps.into_iter().map(|step| format!("{}; ", step)).collect()
+    }
+}
+
+// Fails with "command not found"'s status if the server doesn't have `tool`
+fn require_tool(tool: &str) -> String {
+    format!(
+        "command -v {0} >/dev/null 2>&1 || {{ echo '{0} is not installed on the server' >&2; exit 127; }}",
+        tool
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn priority(nice: Option<i32>, io_class: Option<IoClass>, io_level: Option<u8>) -> CommandPriority {
+        CommandPriority { nice, io_class, io_level }
+    }
+
+    #[test]
+    fn out_of_range_priorities_are_rejected() {
+        assert!(priority(Some(-20), Some(IoClass::BestEffort), Some(7)).validate().is_ok());
+        assert!(priority(Some(19), Some(IoClass::Idle), None).validate().is_ok());
+
+        for (invalid, message) in [
+            (priority(Some(20), None, None), "nice must be between -20 and 19"),
+            (priority(Some(-21), None, None), "nice must be between -20 and 19"),
+            (priority(None, None, Some(3)), "io_level needs an io_class"),
+            (priority(None, Some(IoClass::Idle), Some(0)), "doesn't take a level"),
+            (priority(None, Some(IoClass::Realtime), Some(8)), "io_level must be between 0 and 7"),
+        ] {
+            let e = invalid.validate().unwrap_err();
+            assert!(e.to_string().contains(message), "{:?}: {}", invalid, e);
+        }
+    }
+
+    #[test]
+    fn prefix_applies_each_setting() {
+        assert_eq!(priority(None, None, None).shell_prefix(), "");
+        let prefix = priority(Some(10), Some(IoClass::BestEffort), Some(4)).shell_prefix();
+        assert!(prefix.contains("renice -n 10 -p $$ >/dev/null || exit; "), "{}", prefix);
+        assert!(prefix.ends_with("ionice -c 2 -n 4 -p $$ || exit; "), "{}", prefix);
+    }
+
+    #[test]
+    fn missing_ionice_stops_the_command() {
+        // A PATH with nothing on it, so ionice can't be found
+        let empty = std::env::temp_dir().join(format!("aetherssh-no-tools-{}", std::process::id()));
+        std::fs::create_dir_all(&empty).unwrap();
+
+        let script = format!("{}echo ran", priority(None, Some(IoClass::Idle), None).shell_prefix());
+        let output = Command::new("/bin/sh").arg("-c").arg(&script).env("PATH", &empty).output().unwrap();
+        assert_eq!(output.status.code(), Some(127));
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "ionice is not installed on the server\n");
+        assert!(output.stdout.is_empty());
+    }
+}
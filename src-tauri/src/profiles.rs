@@ -0,0 +1,431 @@
+// Saved connection profiles
+//
+// Profiles are kept as JSON in the app data directory. Passwords and key
+// passphrases never go in that file: they're stored in the OS keyring
+// (Keychain, Credential Manager or the Secret Service), keyed by profile ID,
+// and only fetched again when connecting.
+
+use crate::{auth, connect_ssh, errors, CancelFlags, ConnectionLabels, ConnectionsStore, SSHConnectionConfig, SSHConnectionResponse, unique_label};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Manager, State};
+
+const PROFILES_FILE: &str = "profiles.json";
+// Service name the secrets are filed under in the keyring
+const KEYRING_SERVICE: &str = "aetherssh";
+// Bounds a chain of jump profiles, which can't nest any other way
+const MAX_JUMP_DEPTH: usize = 8;
+
+static NEXT_PROFILE_ID: AtomicU64 = AtomicU64::new(1);
+// Serializes the read-modify-write of the profiles file
+static PROFILES_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionProfile {
+    // Left empty when saving a new profile; one is assigned
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub private_key_path: Option<String>,
+    pub auth_method: Option<auth::AuthMethod>,
+    // Another profile to use as the jump host
+    pub jump_profile_id: Option<String>,
+    pub initial_directory: Option<String>,
+    pub connect_timeout_ms: Option<u64>,
+    pub command_timeout_ms: Option<u64>,
+    pub keepalive_interval_secs: Option<u32>,
+    pub max_sessions_wait_ms: Option<u64>,
+    pub auto_reconnect: Option<bool>,
+    pub max_output_bytes: Option<u64>,
+    // For the frontend to tell profiles apart, e.g. "#e5534b"
+    pub color: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    // Whether the keyring holds a password or passphrase for this profile.
+    // Maintained by save_connection_profile; ignored when passed in.
+    #[serde(default)]
+    pub has_password: bool,
+    #[serde(default)]
+    pub has_passphrase: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Secret {
+    Password,
+    Passphrase,
+}
+
+fn keyring_entry(profile_id: &str, secret: Secret) -> Result<keyring::Entry> {
+    let kind = match secret {
+        Secret::Password => "password",
+        Secret::Passphrase => "passphrase",
+    };
+    keyring::Entry::new(KEYRING_SERVICE, &format!("profile:{}:{}", profile_id, kind))
+        .context("Failed to open the keyring")
+}
+
+fn read_secret(profile_id: &str, secret: Secret) -> Result<Option<String>> {
+    match keyring_entry(profile_id, secret)?.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(anyhow!("Failed to read from the keyring: {}", e)),
+    }
+}
+
+fn delete_secret(profile_id: &str, secret: Secret) -> Result<()> {
+    match keyring_entry(profile_id, secret)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(anyhow!("Failed to remove from the keyring: {}", e)),
+    }
+}
+
+// Some(value) stores the secret, Some("") removes it and None leaves it alone
+fn update_secret(profile_id: &str, secret: Secret, value: Option<&str>) -> Result<()> {
+    match value {
+        None => Ok(()),
+        Some("") => delete_secret(profile_id, secret),
+        Some(value) => keyring_entry(profile_id, secret)?
+            .set_password(value)
+            .map_err(|e| anyhow!("Failed to save to the keyring: {}", e)),
+    }
+}
+
+// Whether the keyring will hold a secret once update_secret has stored
+// `value` over what it `had`
+fn keeps_secret(value: Option<&str>, had: bool) -> bool {
+    value.map_or(had, |value| !value.is_empty())
+}
+
+fn profiles_path(app: &AppHandle) -> Result<PathBuf> {
+    let dir = app.path().app_data_dir().context("Could not determine the app data directory")?;
+    Ok(dir.join(PROFILES_FILE))
+}
+
+fn read_profiles_file(path: &Path) -> Result<Vec<ConnectionProfile>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(path).context("Failed to read the profiles file")?;
+    serde_json::from_str(&contents).context("The profiles file is not valid")
+}
+
+// Writes through a temp file, so a crash can't leave half a file behind
+fn write_profiles_file(path: &Path, profiles: &[ConnectionProfile]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Failed to create the app data directory")?;
+    }
+    let contents = serde_json::to_string_pretty(profiles)?;
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, contents).context("Failed to write the profiles file")?;
+    fs::rename(&temp, path).context("Failed to write the profiles file")?;
+    Ok(())
+}
+
+fn load_profiles(app: &AppHandle) -> Result<Vec<ConnectionProfile>> {
+    read_profiles_file(&profiles_path(app)?)
+}
+
+// Runs `f` on the stored profiles and saves the result
+fn update_profiles<T>(app: &AppHandle, f: impl FnOnce(&mut Vec<ConnectionProfile>) -> Result<T>) -> Result<T> {
+    let _guard = PROFILES_LOCK.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    let path = profiles_path(app)?;
+    let mut profiles = read_profiles_file(&path)?;
+    let result = f(&mut profiles)?;
+    write_profiles_file(&path, &profiles)?;
+    Ok(result)
+}
+
+// Unique across restarts without keeping a counter on disk
+fn new_profile_id() -> String {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    format!("profile-{}-{}", millis, NEXT_PROFILE_ID.fetch_add(1, Ordering::SeqCst))
+}
+
+// Builds the connection config for a profile, with secrets from the keyring
+// and its jump profiles resolved into a jump_host chain
+fn profile_config(
+    profiles: &[ConnectionProfile],
+    profile: &ConnectionProfile,
+    accept_new_host_key: bool,
+    visited: &mut HashSet<String>,
+) -> Result<SSHConnectionConfig> {
+    if !visited.insert(profile.id.clone()) {
+        bail!("Profile {} is its own jump host", profile.name);
+    }
+    if visited.len() > MAX_JUMP_DEPTH {
+        bail!("Too many jump hosts in a row");
+    }
+
+    let jump_host = match &profile.jump_profile_id {
+        Some(jump_id) => {
+            let jump = profiles.iter()
+                .find(|p| &p.id == jump_id)
+                .with_context(|| format!("The jump host profile of {} no longer exists", profile.name))?;
+            Some(Box::new(profile_config(profiles, jump, accept_new_host_key, visited)?))
+        }
+        None => None,
+    };
+
+    let password = if profile.has_password { read_secret(&profile.id, Secret::Password)? } else { None };
+    let passphrase = if profile.has_passphrase { read_secret(&profile.id, Secret::Passphrase)? } else { None };
+
+    Ok(SSHConnectionConfig {
+        host: profile.host.clone(),
+        port: profile.port,
+        username: profile.username.clone(),
+        password,
+        private_key_path: profile.private_key_path.clone(),
+        private_key_contents: None,
+        passphrase,
+        accept_new_host_key,
+        auth_method: profile.auth_method,
+        connect_timeout_ms: profile.connect_timeout_ms,
+        command_timeout_ms: profile.command_timeout_ms,
+        keepalive_interval_secs: profile.keepalive_interval_secs,
+        jump_host,
+        initial_directory: profile.initial_directory.clone(),
+        max_sessions_wait_ms: profile.max_sessions_wait_ms,
+        auto_reconnect: profile.auto_reconnect,
+        max_output_bytes: profile.max_output_bytes,
+    })
+}
+
+// Creates or updates a profile, returning it as stored. `password` and
+// `passphrase` go to the keyring: leave them out to keep what's there, or
+// pass "" to remove them.
+#[tauri::command]
+pub async fn save_connection_profile(
+    app: AppHandle,
+    mut profile: ConnectionProfile,
+    password: Option<String>,
+    passphrase: Option<String>,
+) -> Result<ConnectionProfile, errors::SSHError> {
+    update_profiles(&app, |profiles| {
+        if profile.id.is_empty() {
+            profile.id = new_profile_id();
+        }
+        if profile.jump_profile_id.as_deref() == Some(profile.id.as_str()) {
+            bail!("A profile can't be its own jump host");
+        }
+
+        let existing = profiles.iter().position(|p| p.id == profile.id);
+        let (had_password, had_passphrase) = existing
+            .map(|i| (profiles[i].has_password, profiles[i].has_passphrase))
+            .unwrap_or((false, false));
+        profile.has_password = keeps_secret(password.as_deref(), had_password);
+        profile.has_passphrase = keeps_secret(passphrase.as_deref(), had_passphrase);
+
+        match existing {
+            Some(i) => profiles[i] = profile.clone(),
+            None => profiles.push(profile.clone()),
+        }
+        Ok(profile)
+    })
+    .and_then(|profile| {
+        // Only once the file is saved, so a failed save can't leave a
+        // secret in the keyring that no profile refers to
+        update_secret(&profile.id, Secret::Password, password.as_deref())?;
+        update_secret(&profile.id, Secret::Passphrase, passphrase.as_deref())?;
+        Ok(profile)
+    })
+    .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to save profile: {}", e)))
+}
+
+#[tauri::command]
+pub async fn list_connection_profiles(app: AppHandle) -> Result<Vec<ConnectionProfile>, errors::SSHError> {
+    load_profiles(&app).map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to load profiles: {}", e)))
+}
+
+// Removes a profile and its keyring entries. Profiles that used it as their
+// jump host go back to connecting directly.
+#[tauri::command]
+pub async fn delete_connection_profile(app: AppHandle, profile_id: String) -> Result<bool, errors::SSHError> {
+    update_profiles(&app, |profiles| {
+        let before = profiles.len();
+        profiles.retain(|p| p.id != profile_id);
+        if profiles.len() == before {
+            return Ok(false);
+        }
+
+        for profile in profiles.iter_mut() {
+            if profile.jump_profile_id.as_deref() == Some(profile_id.as_str()) {
+                profile.jump_profile_id = None;
+            }
+        }
+        delete_secret(&profile_id, Secret::Password)?;
+        delete_secret(&profile_id, Secret::Passphrase)?;
+        Ok(true)
+    })
+    .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to delete profile: {}", e)))
+}
+
+// Connects with a saved profile, the same way connect_ssh does with a
+// config. The connection is labelled with the profile's name.
+#[tauri::command]
+pub async fn connect_with_profile(
+    app: AppHandle,
+    profile_id: String,
+    accept_new_host_key: Option<bool>,
+    connections: State<'_, ConnectionsStore>,
+    cancel_flags: State<'_, CancelFlags>,
+    labels: State<'_, ConnectionLabels>,
+    pending_prompts: State<'_, auth::PendingPrompts>,
+) -> Result<SSHConnectionResponse, errors::SSHError> {
+    let (config, name) = (|| -> Result<(SSHConnectionConfig, String)> {
+        let profiles = load_profiles(&app)?;
+        let profile = profiles.iter()
+            .find(|p| p.id == profile_id)
+            .context("No profile with that ID")?;
+        let config = profile_config(&profiles, profile, accept_new_host_key.unwrap_or(false), &mut HashSet::new())?;
+        Ok((config, profile.name.clone()))
+    })()
+    .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to load profile: {}", e)))?;
+
+    let mut response = connect_ssh(app, config, connections, cancel_flags, labels.clone(), pending_prompts).await?;
+    if let Some(connection_id) = &response.connection_id {
+        let mut labels = labels.lock().map_err(|e| format!("Lock error: {}", e))?;
+        labels.remove(connection_id);
+        let label = unique_label(&labels, name);
+        labels.insert(connection_id.clone(), label.clone());
+        response.label = Some(label);
+    }
+    Ok(response)
+}
+
+// Writes every profile to `path` for moving to another machine. Secrets stay
+// in this machine's keyring.
+#[tauri::command]
+pub async fn export_connection_profiles(app: AppHandle, path: String) -> Result<usize, errors::SSHError> {
+    (|| -> Result<usize> {
+        let mut profiles = load_profiles(&app)?;
+        for profile in profiles.iter_mut() {
+            profile.has_password = false;
+            profile.has_passphrase = false;
+        }
+        let contents = serde_json::to_string_pretty(&profiles)?;
+        fs::write(&path, contents).context("Failed to write the export file")?;
+        Ok(profiles.len())
+    })()
+    .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to export profiles: {}", e)))
+}
+
+// Adds the profiles from a file written by export_connection_profiles,
+// replacing any with the same ID. Returns how many were imported.
+#[tauri::command]
+pub async fn import_connection_profiles(app: AppHandle, path: String) -> Result<usize, errors::SSHError> {
+    update_profiles(&app, |profiles| {
+        let imported = read_profiles_file(Path::new(&path))?;
+        let count = imported.len();
+
+        for mut profile in imported {
+            if profile.id.is_empty() {
+                profile.id = new_profile_id();
+            }
+            // Whatever the file says, there's nothing in this keyring for it
+            // unless it replaces a profile that already had secrets
+            let existing = profiles.iter().position(|p| p.id == profile.id);
+            match existing {
+                Some(i) => {
+                    profile.has_password = profiles[i].has_password;
+                    profile.has_passphrase = profiles[i].has_passphrase;
+                    profiles[i] = profile;
+                }
+                None => {
+                    profile.has_password = false;
+                    profile.has_passphrase = false;
+                    profiles.push(profile);
+                }
+            }
+        }
+        Ok(count)
+    })
+    .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to import profiles: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(id: &str, jump_profile_id: Option<&str>) -> ConnectionProfile {
+        ConnectionProfile {
+            id: id.to_string(),
+            name: format!("{} server", id),
+            host: format!("{}.example.com", id),
+            port: 22,
+            username: "alice".to_string(),
+            private_key_path: None,
+            auth_method: None,
+            jump_profile_id: jump_profile_id.map(str::to_string),
+            initial_directory: None,
+            connect_timeout_ms: None,
+            command_timeout_ms: None,
+            keepalive_interval_secs: None,
+            max_sessions_wait_ms: None,
+            auto_reconnect: None,
+            max_output_bytes: None,
+            color: None,
+            labels: Vec::new(),
+            has_password: false,
+            has_passphrase: false,
+        }
+    }
+
+    fn temp_profiles(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aetherssh-profiles-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(PROFILES_FILE)
+    }
+
+    #[test]
+    fn profiles_round_trip_with_every_optional_field() {
+        let path = temp_profiles("round-trip");
+        let full = ConnectionProfile {
+            private_key_path: Some("~/.ssh/id_ed25519".to_string()),
+            auth_method: Some(auth::AuthMethod::KeyboardInteractive),
+            initial_directory: Some("/srv/app".to_string()),
+            connect_timeout_ms: Some(5_000),
+            command_timeout_ms: Some(60_000),
+            keepalive_interval_secs: Some(30),
+            max_sessions_wait_ms: Some(2_000),
+            auto_reconnect: Some(false),
+            max_output_bytes: Some(1 << 20),
+            color: Some("#e5534b".to_string()),
+            labels: vec!["prod".to_string(), "eu".to_string()],
+            has_password: true,
+            has_passphrase: true,
+            ..profile("web", Some("bastion"))
+        };
+        let profiles = vec![full, profile("bastion", None)];
+
+        assert!(read_profiles_file(&path).unwrap().is_empty());
+        write_profiles_file(&path, &profiles).unwrap();
+        let read = read_profiles_file(&path).unwrap();
+        assert_eq!(serde_json::to_value(&read).unwrap(), serde_json::to_value(&profiles).unwrap());
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn jump_profile_cycles_are_refused() {
+        let profiles = vec![profile("a", Some("b")), profile("b", Some("a")), profile("c", Some("c"))];
+        for profile in &profiles {
+            let e = profile_config(&profiles, profile, false, &mut HashSet::new()).unwrap_err();
+            assert!(e.to_string().contains("its own jump host"), "{}", e);
+        }
+
+        let chain = vec![profile("web", Some("bastion")), profile("bastion", None)];
+        let config = profile_config(&chain, &chain[0], true, &mut HashSet::new()).unwrap();
+        assert_eq!(config.jump_host.unwrap().host, "bastion.example.com");
+
+        let missing = vec![profile("web", Some("gone"))];
+        assert!(profile_config(&missing, &missing[0], false, &mut HashSet::new()).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+// Request/response sessions for remote REPLs (python, node, psql, ...)
+//
+// A REPL runs on its own PTY channel that stays open between calls. Each
+// call writes one input and reads until the REPL prints its prompt again,
+// so the output of every evaluation comes back on its own.
+
+use crate::{drain_available, errors, with_client, with_shared_client, ConnectionsStore, STREAM_POLL_INTERVAL, SharedClient};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use ssh2::Channel;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::State;
+
+// Prompt endings of the common REPLs: python (">>> ", "... "), node ("> "),
+// psql ("db=> " and "db=# "), irb ("irb(main):001> ") and friends
+const DEFAULT_PROMPTS: &[&str] = &["> ", "... ", "# "];
+const DEFAULT_REPL_TIMEOUT: Duration = Duration::from_secs(30);
+
+static NEXT_REPL_ID: AtomicU64 = AtomicU64::new(1);
+
+pub struct ReplSession {
+    channel: Channel,
+    prompts: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplOutput {
+    pub repl_id: String,
+    // Everything printed before the prompt, without the echoed input
+    pub output: String,
+    // The prompt line that ended the output, if one was seen
+    pub prompt: Option<String>,
+    pub timed_out: bool,
+    // The REPL process exited (e.g. after `exit()`), closing the session
+    pub exited: bool,
+}
+
+// Removes ANSI escape sequences, which REPLs like node wrap their prompt in
+fn strip_ansi(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            result.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'[') {
+            chars.next();
+            // CSI sequences end with a byte in the range @ to ~
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        } else {
+            chars.next();
+        }
+    }
+
+    result
+}
+
+// Returns the prompt if the output currently ends with one
+fn find_prompt(output: &str, prompts: &[String]) -> Option<String> {
+    let cleaned = strip_ansi(output).replace('\r', "");
+    let last_line = cleaned.rsplit('\n').next().unwrap_or("");
+
+    prompts.iter()
+        .any(|p| last_line.ends_with(p.as_str()))
+        .then(|| last_line.to_string())
+}
+
+// What a REPL printed for `input`, without the PTY's echo of it or the
+// prompt that followed
+fn evaluation_output(buf: &[u8], input: Option<&str>, ends_in_prompt: bool) -> String {
+    let mut output = strip_ansi(&String::from_utf8_lossy(buf)).replace("\r\n", "\n");
+    if ends_in_prompt {
+        // Drop the prompt line itself
+        let end = output.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        output.truncate(end);
+    }
+    if let Some(input) = input {
+        // The PTY echoes what we typed back as the first line
+        if let Some(rest) = output.strip_prefix(input.trim_end()) {
+            output = rest.strip_prefix('\n').unwrap_or(rest).to_string();
+        }
+    }
+    output
+}
+
+// Reads until the output ends with a prompt, the REPL exits, or the timeout
+// passes. The connection's lock is only taken for one poll at a time, as for
+// one-shot commands, so a slow evaluation doesn't hold up the other users of
+// the connection.
+fn read_until_prompt(client: &SharedClient, repl_id: &str, input: Option<&str>, timeout: Duration) -> Result<ReplOutput> {
+    let started = Instant::now();
+    let mut buf = Vec::new();
+    let mut exited = false;
+    let mut prompt = None;
+    let mut prompts = Vec::new();
+
+    while started.elapsed() < timeout {
+        let before = buf.len();
+        {
+            let mut client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+            let client = &mut *client;
+            let repl = client.repl_sessions.get_mut(repl_id).context("REPL session was closed")?;
+            if prompts.is_empty() {
+                prompts = repl.prompts.clone();
+            }
+
+            client.session.set_blocking(false);
+            let drained = drain_available(&mut repl.channel, &mut buf);
+            client.session.set_blocking(true);
+            exited = drained?;
+
+            if exited {
+                client.repl_sessions.remove(repl_id);
+                break;
+            }
+        }
+
+        if buf.len() > before {
+            prompt = find_prompt(&String::from_utf8_lossy(&buf), &prompts);
+            if prompt.is_some() {
+                break;
+            }
+        } else {
+            thread::sleep(STREAM_POLL_INTERVAL);
+        }
+    }
+
+    Ok(ReplOutput {
+        repl_id: repl_id.to_string(),
+        output: evaluation_output(&buf, input, prompt.is_some()),
+        timed_out: prompt.is_none() && !exited,
+        prompt,
+        exited,
+    })
+}
+
+// Starts a REPL and waits for its first prompt. `prompt` can override the
+// built-in prompt detection for REPLs with unusual prompts.
+#[tauri::command]
+pub async fn start_repl(
+    connection_id: String,
+    command: String,
+    prompt: Option<String>,
+    timeout_ms: Option<u64>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ReplOutput, errors::SSHError> {
+    with_shared_client(&connections, &connection_id, move |shared| {
+        let repl_id = format!("repl-{}", NEXT_REPL_ID.fetch_add(1, Ordering::SeqCst));
+        {
+            let mut client = shared.lock().map_err(|e| format!("Lock error: {}", e))?;
+            let (full_command, _) = client.prepare_command(&command);
+            let channel = client.open_command_channel(&full_command)
+                .map_err(|e| format!("Failed to start REPL: {}", e))?;
+
+            let prompts = match prompt {
+                Some(prompt) => vec![prompt],
+                None => DEFAULT_PROMPTS.iter().map(|p| p.to_string()).collect(),
+            };
+            client.repl_sessions.insert(repl_id.clone(), ReplSession { channel, prompts });
+        }
+
+        let timeout = timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_REPL_TIMEOUT);
+        read_until_prompt(shared, &repl_id, None, timeout).map_err(|e| format!("Failed to start REPL: {}", e))
+    })
+    .await
+}
+
+// Sends one input line to a REPL and returns the output it produced before
+// showing its prompt again
+#[tauri::command]
+pub async fn repl_send_input(
+    connection_id: String,
+    repl_id: String,
+    input: String,
+    timeout_ms: Option<u64>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ReplOutput, errors::SSHError> {
+    with_shared_client(&connections, &connection_id, move |shared| {
+        {
+            let mut client = shared.lock().map_err(|e| format!("Lock error: {}", e))?;
+            let repl = client.repl_sessions.get_mut(&repl_id)
+                .ok_or_else(|| "REPL session not found".to_string())?;
+            let line = format!("{}\n", input.trim_end_matches('\n'));
+            repl.channel.write_all(line.as_bytes()).map_err(|e| format!("REPL error: Failed to send input: {}", e))?;
+        }
+
+        let timeout = timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_REPL_TIMEOUT);
+        read_until_prompt(shared, &repl_id, Some(&input), timeout).map_err(|e| format!("REPL error: {}", e))
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn close_repl(
+    connection_id: String,
+    repl_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<bool, errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        match client.repl_sessions.remove(&repl_id) {
+            Some(mut repl) => {
+                let _ = repl.channel.close();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompts(prompts: &[&str]) -> Vec<String> {
+        prompts.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn strips_ansi_sequences() {
+        assert_eq!(strip_ansi("\x1b[1G\x1b[0J> \x1b[3G"), "> ");
+        assert_eq!(strip_ansi("\x1b[32mgreen\x1b[39m text"), "green text");
+        // Two-character escapes, like the keypad mode psql sets
+        assert_eq!(strip_ansi("\x1b=ready\x1b>"), "ready");
+        assert_eq!(strip_ansi("héllo ✓"), "héllo ✓");
+        // A sequence cut off at the end of what's arrived so far
+        assert_eq!(strip_ansi("out\x1b[3"), "out");
+    }
+
+    #[test]
+    fn finds_the_prompts_of_common_repls() {
+        let defaults = prompts(DEFAULT_PROMPTS);
+        for (output, prompt) in [
+            (">>> ", Some(">>> ")),
+            ("2\r\n>>> ", Some(">>> ")),
+            ("... ", Some("... ")),
+            ("\x1b[1G\x1b[0J> \x1b[3G", Some("> ")),
+            ("postgres=# ", Some("postgres=# ")),
+            ("irb(main):001> ", Some("irb(main):001> ")),
+            ("2\r\n", None),
+            ("loading>\r\nstill going", None),
+        ] {
+            assert_eq!(find_prompt(output, &defaults).as_deref(), prompt, "{:?}", output);
+        }
+
+        let custom = prompts(&["sqlite> "]);
+        assert_eq!(find_prompt("sqlite> ", &custom).as_deref(), Some("sqlite> "));
+        assert_eq!(find_prompt(">>> ", &custom), None);
+    }
+
+    #[test]
+    fn finds_a_prompt_split_across_reads() {
+        let defaults = prompts(DEFAULT_PROMPTS);
+        let reads: &[&[u8]] = &[b"print(1)\r\n1\r\n", b">>", b"> "];
+        let mut buf = Vec::new();
+        let mut found = Vec::new();
+        for read in reads {
+            buf.extend_from_slice(read);
+            found.push(find_prompt(&String::from_utf8_lossy(&buf), &defaults));
+        }
+        assert_eq!(found, [None, None, Some(">>> ".to_string())]);
+        assert_eq!(evaluation_output(&buf, Some("print(1)\n"), true), "1\n");
+
+        // Also when the split falls inside an escape sequence
+        let reads: &[&[u8]] = &[b"3\r\n\x1b[1G\x1b", b"[0J> ", b"\x1b[3G"];
+        let mut buf = Vec::new();
+        let mut found = Vec::new();
+        for read in reads {
+            buf.extend_from_slice(read);
+            found.push(find_prompt(&String::from_utf8_lossy(&buf), &defaults));
+        }
+        assert_eq!(found.last().unwrap().as_deref(), Some("> "));
+        assert_eq!(evaluation_output(&buf, Some("1 + 2"), true), "3\n");
+    }
+
+    #[test]
+    fn evaluation_output_without_a_prompt() {
+        // A timed-out or exited REPL keeps everything it printed
+        assert_eq!(evaluation_output(b"exit()\r\nbye\r\n", Some("exit()"), false), "bye\n");
+        assert_eq!(evaluation_output(b"partial", None, false), "partial");
+    }
+}
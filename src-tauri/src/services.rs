@@ -0,0 +1,392 @@
+// Checking and controlling services on the server
+//
+// systemd is asked through `systemctl show`, whose key=value output is meant
+// for scripts. Servers without it fall back to OpenRC's rc-service or the
+// SysV `service` wrapper, which only report running or not through their
+// exit status.
+
+use crate::{errors, shell_quote, with_client, CommandResult, ConnectionsStore, SSHClient};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use tauri::State;
+
+// Journal lines included with a status when the caller doesn't choose
+const DEFAULT_LOG_LINES: u32 = 20;
+const MAX_LOG_LINES: u32 = 500;
+
+const SYSTEMD_PROPERTIES: &str =
+    "Description,LoadState,ActiveState,SubState,UnitFileState,MainPID,ActiveEnterTimestampMonotonic";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitSystem {
+    Systemd,
+    Openrc,
+    Sysvinit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    Active,
+    Inactive,
+    Failed,
+    Activating,
+    Deactivating,
+    // The init system doesn't know the service
+    NotFound,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl ServiceAction {
+    fn verb(self) -> &'static str {
+        match self {
+            ServiceAction::Start => "start",
+            ServiceAction::Stop => "stop",
+            ServiceAction::Restart => "restart",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceStatus {
+    pub unit: String,
+    pub init_system: InitSystem,
+    pub state: ServiceState,
+    // systemd's finer-grained state, e.g. "running", "exited" or "dead"
+    pub sub_state: Option<String>,
+    pub description: Option<String>,
+    // systemd's unit file state, e.g. "enabled" or "disabled"
+    pub enabled: Option<String>,
+    pub pid: Option<u32>,
+    // How long the service has been in its current active state
+    pub uptime_secs: Option<u64>,
+    // The service's latest journal lines (systemd), or what the status
+    // command printed (other init systems)
+    pub recent_logs: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceActionResult {
+    // What the start, stop or restart command printed
+    pub result: CommandResult,
+    // The status afterwards, if it could be read
+    pub status: Option<ServiceStatus>,
+}
+
+// Unit names are passed to the shell quoted, but also have to be something
+// the init system would accept, and not look like an option
+fn validate_unit(unit: &str) -> Result<()> {
+    let valid = !unit.is_empty()
+        && !unit.starts_with('-')
+        && unit.chars().all(|c| c.is_ascii_alphanumeric() || "@._:-\\".contains(c));
+    if !valid {
+        bail!("Invalid service name: {}", unit);
+    }
+    Ok(())
+}
+
+fn detect_init_system(client: &SSHClient) -> Result<InitSystem> {
+    // The directory only exists when systemd is the running init, not
+    // merely installed
+    let (status, stdout, _) = client.exec_capture(
+        "if [ -d /run/systemd/system ] && command -v systemctl >/dev/null 2>&1; then echo systemd; \
+         elif command -v rc-service >/dev/null 2>&1; then echo openrc; \
+         elif command -v service >/dev/null 2>&1 || [ -d /etc/init.d ]; then echo sysvinit; fi",
+    )?;
+    match (status, stdout.trim()) {
+        (0, "systemd") => Ok(InitSystem::Systemd),
+        (0, "openrc") => Ok(InitSystem::Openrc),
+        (0, "sysvinit") => Ok(InitSystem::Sysvinit),
+        _ => bail!("Couldn't find systemd, OpenRC or a SysV service script on the server"),
+    }
+}
+
+fn systemd_state(active_state: &str) -> ServiceState {
+    match active_state {
+        "active" | "reloading" => ServiceState::Active,
+        "inactive" => ServiceState::Inactive,
+        "failed" => ServiceState::Failed,
+        "activating" => ServiceState::Activating,
+        "deactivating" => ServiceState::Deactivating,
+        _ => ServiceState::Unknown,
+    }
+}
+
+fn systemd_status(client: &SSHClient, unit: &str, log_lines: u32) -> Result<ServiceStatus> {
+    let quoted = shell_quote(unit);
+    let (status, stdout, stderr) = client.exec_capture(&format!(
+        "LC_ALL=C systemctl show {} --no-pager --property={} && echo \"Uptime=$(cut -d' ' -f1 /proc/uptime)\"",
+        quoted, SYSTEMD_PROPERTIES
+    ))?;
+    if status != 0 {
+        bail!("systemctl failed: {}", stderr.trim());
+    }
+
+    let mut service = parse_systemd_show(unit, &stdout);
+    if log_lines > 0 && service.state != ServiceState::NotFound {
+        let (status, stdout, stderr) = client.exec_capture(&format!(
+            "journalctl -u {} -n {} --no-pager -o short-iso",
+            quoted, log_lines
+        ))?;
+        if status == 0 {
+            service.recent_logs = journal_lines(&stdout);
+            if !stderr.trim().is_empty() {
+                service.warnings.push(stderr.trim().to_string());
+            }
+        } else {
+            service.warnings.push(format!("Couldn't read the service's journal: {}", stderr.trim()));
+        }
+    }
+    Ok(service)
+}
+
+// Reads `systemctl show` output, followed by an `Uptime=` line with the
+// seconds since boot
+fn parse_systemd_show(unit: &str, stdout: &str) -> ServiceStatus {
+    let properties: HashMap<&str, &str> = stdout.lines().filter_map(|line| line.split_once('=')).collect();
+    let property = |name: &str| properties.get(name).map(|v| v.to_string()).filter(|v| !v.is_empty());
+
+    let state = match properties.get("LoadState") {
+        Some(&"not-found") => ServiceState::NotFound,
+        _ => systemd_state(properties.get("ActiveState").copied().unwrap_or_default()),
+    };
+    let pid = properties.get("MainPID").and_then(|p| p.parse().ok()).filter(|&p: &u32| p != 0);
+
+    // Both are measured from boot, which sidesteps the server's timezone
+    let uptime_secs = match (state, properties.get("ActiveEnterTimestampMonotonic"), properties.get("Uptime")) {
+        (ServiceState::Active, Some(entered), Some(uptime)) => {
+            let entered = entered.parse::<u64>().ok().filter(|&e| e != 0).map(|e| e / 1_000_000);
+            let uptime = uptime.parse::<f64>().ok().map(|u| u as u64);
+            entered.zip(uptime).map(|(entered, uptime)| uptime.saturating_sub(entered))
+        }
+        _ => None,
+    };
+
+    ServiceStatus {
+        unit: unit.to_string(),
+        init_system: InitSystem::Systemd,
+        state,
+        sub_state: property("SubState"),
+        description: property("Description"),
+        enabled: property("UnitFileState"),
+        pid,
+        uptime_secs,
+        recent_logs: Vec::new(),
+        warnings: Vec::new(),
+    }
+}
+
+// Without journal access journalctl only prints a hint, with no entries
+fn journal_lines(stdout: &str) -> Vec<String> {
+    stdout.lines()
+        .filter(|line| !line.starts_with("-- "))
+        .map(str::to_string)
+        .collect()
+}
+
+// rc-service and service only say running (0) or not (3, per the LSB), but
+// what they print often includes the pid or a reason
+fn script_status(client: &SSHClient, init_system: InitSystem, unit: &str) -> Result<ServiceStatus> {
+    let command = match init_system {
+        InitSystem::Openrc => format!("rc-service {} status", shell_quote(unit)),
+        _ => format!("PATH=\"$PATH:/usr/sbin:/sbin\" service {} status", shell_quote(unit)),
+    };
+    let (status, stdout, stderr) = client.exec_capture(&command)?;
+    let output = format!("{}{}", stdout, stderr);
+
+    Ok(ServiceStatus {
+        unit: unit.to_string(),
+        init_system,
+        state: script_state(status, &output),
+        sub_state: None,
+        description: None,
+        enabled: None,
+        pid: None,
+        uptime_secs: None,
+        recent_logs: output.lines().map(str::to_string).collect(),
+        warnings: Vec::new(),
+    })
+}
+
+fn script_state(status: i32, output: &str) -> ServiceState {
+    // 4 is "unknown service" to the LSB. OpenRC exits 1 for a missing
+    // service, saying it "does not exist".
+    if status == 4 || output.contains("does not exist") || output.contains("unrecognized service") {
+        return ServiceState::NotFound;
+    }
+    match status {
+        0 => ServiceState::Active,
+        3 => ServiceState::Inactive,
+        1 | 2 => ServiceState::Failed,
+        _ => ServiceState::Unknown,
+    }
+}
+
+fn service_status(client: &SSHClient, unit: &str, log_lines: u32) -> Result<ServiceStatus> {
+    match detect_init_system(client)? {
+        InitSystem::Systemd => systemd_status(client, unit, log_lines),
+        init_system => script_status(client, init_system, unit),
+    }
+}
+
+// Runs the action as root: directly when already logged in as root, and
+// otherwise through sudo, which is reported clearly if it wants a password
+fn run_action(client: &mut SSHClient, unit: &str, action: ServiceAction) -> Result<ServiceActionResult> {
+    let init_system = detect_init_system(client)?;
+    let quoted = shell_quote(unit);
+    let command = match init_system {
+        InitSystem::Systemd => format!("systemctl {} {}", action.verb(), quoted),
+        InitSystem::Openrc => format!("rc-service {} {}", quoted, action.verb()),
+        InitSystem::Sysvinit => format!("PATH=\"$PATH:/usr/sbin:/sbin\" service {} {}", quoted, action.verb()),
+    };
+
+    let (_, uid, _) = client.exec_capture("id -u")?;
+    let result = if uid.trim() == "0" {
+        let (status, stdout, stderr) = client.exec_capture(&command)?;
+        CommandResult::from_output(stdout.into_bytes(), stderr.into_bytes(), status, client.current_directory.clone())
+    } else {
+        client.sudo_execute(&command, None, None)?
+    };
+
+    // Without journal lines, which rarely say more than the action's output
+    let status = service_status(client, unit, 0).ok();
+    Ok(ServiceActionResult { result, status })
+}
+
+// Reports whether a service is running, with its pid, uptime and latest log
+// lines (`log_lines` of them, 20 by default) where the init system has them
+#[tauri::command]
+pub async fn get_service_status(
+    connection_id: String,
+    unit: String,
+    log_lines: Option<u32>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ServiceStatus, errors::SSHError> {
+    validate_unit(&unit).map_err(|e| errors::SSHError::new(errors::SSHErrorKind::InvalidArgument, e.to_string()))?;
+    let log_lines = log_lines.unwrap_or(DEFAULT_LOG_LINES).min(MAX_LOG_LINES);
+    with_client(&connections, &connection_id, move |client| {
+        service_status(client, &unit, log_lines).map_err(|e| format!("Failed to read service status: {}", e))
+    })
+    .await
+}
+
+async fn service_action(
+    connections: State<'_, ConnectionsStore>,
+    connection_id: String,
+    unit: String,
+    action: ServiceAction,
+) -> Result<ServiceActionResult, errors::SSHError> {
+    validate_unit(&unit).map_err(|e| errors::SSHError::new(errors::SSHErrorKind::InvalidArgument, e.to_string()))?;
+    with_client(&connections, &connection_id, move |client| {
+        run_action(client, &unit, action).map_err(|e| format!("Failed to {} service: {}", action.verb(), e))
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn start_service(
+    connection_id: String,
+    unit: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ServiceActionResult, errors::SSHError> {
+    service_action(connections, connection_id, unit, ServiceAction::Start).await
+}
+
+#[tauri::command]
+pub async fn stop_service(
+    connection_id: String,
+    unit: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ServiceActionResult, errors::SSHError> {
+    service_action(connections, connection_id, unit, ServiceAction::Stop).await
+}
+
+#[tauri::command]
+pub async fn restart_service(
+    connection_id: String,
+    unit: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ServiceActionResult, errors::SSHError> {
+    service_action(connections, connection_id, unit, ServiceAction::Restart).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_running_systemd_unit() {
+        let stdout = "Description=OpenSSH server daemon = sshd\n\
+                      LoadState=loaded\n\
+                      ActiveState=active\n\
+                      SubState=running\n\
+                      UnitFileState=enabled\n\
+                      MainPID=812\n\
+                      ActiveEnterTimestampMonotonic=12500000\n\
+                      Uptime=3612.47\n";
+        let status = parse_systemd_show("sshd", stdout);
+        assert_eq!(status.state, ServiceState::Active);
+        assert_eq!(status.sub_state.as_deref(), Some("running"));
+        assert_eq!(status.description.as_deref(), Some("OpenSSH server daemon = sshd"));
+        assert_eq!(status.enabled.as_deref(), Some("enabled"));
+        assert_eq!(status.pid, Some(812));
+        assert_eq!(status.uptime_secs, Some(3600));
+    }
+
+    #[test]
+    fn parses_stopped_and_missing_systemd_units() {
+        let stdout = "Description=Failing thing\nLoadState=loaded\nActiveState=failed\nSubState=failed\n\
+                      UnitFileState=\nMainPID=0\nActiveEnterTimestampMonotonic=0\nUptime=50.00\n";
+        let status = parse_systemd_show("thing", stdout);
+        assert_eq!(status.state, ServiceState::Failed);
+        assert_eq!(status.enabled, None);
+        assert_eq!(status.pid, None);
+        assert_eq!(status.uptime_secs, None);
+
+        let stdout = "Description=nope.service\nLoadState=not-found\nActiveState=inactive\nSubState=dead\nUptime=50.00\n";
+        assert_eq!(parse_systemd_show("nope", stdout).state, ServiceState::NotFound);
+
+        assert_eq!(parse_systemd_show("odd", "ActiveState=maintenance\n").state, ServiceState::Unknown);
+    }
+
+    #[test]
+    fn script_exit_statuses() {
+        for (status, output, state) in [
+            (0, " * status: started", ServiceState::Active),
+            (3, " * status: stopped", ServiceState::Inactive),
+            (1, "", ServiceState::Failed),
+            (4, "", ServiceState::NotFound),
+            (1, " * rc-service: service `nope' does not exist", ServiceState::NotFound),
+            (127, "nope: unrecognized service", ServiceState::NotFound),
+            (5, "", ServiceState::Unknown),
+        ] {
+            assert_eq!(script_state(status, output), state, "{} {:?}", status, output);
+        }
+    }
+
+    #[test]
+    fn journal_hints_are_dropped() {
+        let stdout = "-- No entries --\n2026-10-14T09:00:00+0000 host sshd[812]: Server listening\n";
+        assert_eq!(journal_lines(stdout), ["2026-10-14T09:00:00+0000 host sshd[812]: Server listening"]);
+    }
+
+    #[test]
+    fn unit_names() {
+        for unit in ["sshd", "nginx.service", "getty@tty1.service", "systemd-fsck@dev-disk-by\\x2duuid.service"] {
+            assert!(validate_unit(unit).is_ok(), "{}", unit);
+        }
+        for unit in ["", "--all", "a b", "x;reboot", "$(id)"] {
+            assert!(validate_unit(unit).is_err(), "{}", unit);
+        }
+    }
+}
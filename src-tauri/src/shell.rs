@@ -0,0 +1,189 @@
+// Persistent interactive shell for the frontend's terminal emulator
+//
+// Unlike execute_ssh_command, which opens a new channel per command, this
+// keeps a single PTY shell open on the connection, so environment variables,
+// virtualenvs, `sudo -s` and background jobs survive between inputs. Output
+// is pushed to the frontend as `ssh-shell-output` events, followed by one
+// `ssh-shell-exit` event when the shell ends.
+
+use crate::{drain_available, errors, get_client, take_utf8_prefix, with_client, ConnectionsStore, STREAM_POLL_INTERVAL};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use ssh2::{Channel, PtyModes};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use tauri::{AppHandle, Emitter, State};
+
+static NEXT_SHELL_ID: AtomicU64 = AtomicU64::new(1);
+
+pub struct ShellSession {
+    id: String,
+    channel: Channel,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShellOutputEvent {
+    pub connection_id: String,
+    pub shell_id: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShellExitEvent {
+    pub connection_id: String,
+    pub shell_id: String,
+    pub exit_status: Option<i32>,
+}
+
+// Pumps the shell's output to the frontend until it exits or is closed.
+//
+// Like streaming commands, this only holds the connection's lock (and only
+// switches the session to non-blocking) for one poll at a time, so one-shot
+// commands and shell input can run in between.
+fn pump_shell_output(app: &AppHandle, connections: &ConnectionsStore, connection_id: &str, shell_id: &str) {
+    let mut buf = Vec::new();
+    let mut exit_status = None;
+
+    loop {
+        let (got_data, eof) = {
+            let Ok(client) = get_client(connections, connection_id) else { break };
+            let Ok(mut guard) = client.lock() else { break };
+            let client = &mut *guard;
+
+            // Closed, or replaced by a newer shell
+            let Some(shell) = client.shell.as_mut().filter(|s| s.id == shell_id) else { break };
+
+            let before = buf.len();
+            client.session.set_blocking(false);
+            let result = drain_available(&mut shell.channel, &mut buf);
+            client.session.set_blocking(true);
+
+            // Errors other than WouldBlock mean the channel is gone
+            let eof = result.unwrap_or(true);
+            if eof {
+                let mut shell = client.shell.take().expect("shell checked above");
+                let _ = shell.channel.wait_close();
+                exit_status = shell.channel.exit_status().ok();
+            }
+            (buf.len() > before, eof)
+        };
+
+        let data = if eof {
+            String::from_utf8_lossy(&std::mem::take(&mut buf)).into_owned()
+        } else {
+            take_utf8_prefix(&mut buf)
+        };
+        if !data.is_empty() {
+            let _ = app.emit("ssh-shell-output", ShellOutputEvent {
+                connection_id: connection_id.to_string(),
+                shell_id: shell_id.to_string(),
+                data,
+            });
+        }
+
+        if eof {
+            break;
+        }
+        if !got_data {
+            thread::sleep(STREAM_POLL_INTERVAL);
+        }
+    }
+
+    let _ = app.emit("ssh-shell-exit", ShellExitEvent {
+        connection_id: connection_id.to_string(),
+        shell_id: shell_id.to_string(),
+        exit_status,
+    });
+}
+
+// Opens a login shell on a PTY of the given size and returns its shell id.
+// A connection has at most one shell; starting another one closes the first.
+#[tauri::command]
+pub async fn start_shell(
+    app: AppHandle,
+    connection_id: String,
+    cols: u32,
+    rows: u32,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<String, errors::SSHError> {
+    let shell_id = with_client(&connections, &connection_id, move |client| {
+        let channel = (|| -> Result<Channel> {
+            let mut channel = client.open_channel()?;
+            channel.request_pty("xterm-256color", Some(PtyModes::new()), Some((cols, rows, 0, 0)))?;
+            channel.shell()?;
+            Ok(channel)
+        })()
+        .map_err(|e| format!("Failed to start shell: {}", e))?;
+
+        if let Some(mut old) = client.shell.take() {
+            let _ = old.channel.close();
+        }
+
+        let shell_id = format!("shell-{}", NEXT_SHELL_ID.fetch_add(1, Ordering::SeqCst));
+        client.shell = Some(ShellSession { id: shell_id.clone(), channel });
+        Ok(shell_id)
+    })
+    .await?;
+
+    let connections = connections.inner().clone();
+    let id = shell_id.clone();
+    thread::spawn(move || pump_shell_output(&app, &connections, &connection_id, &id));
+
+    Ok(shell_id)
+}
+
+// Sends keystrokes (or pasted text) to the shell as-is
+#[tauri::command]
+pub async fn write_to_shell(
+    connection_id: String,
+    data: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<(), errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        let shell = client.shell.as_mut().ok_or_else(|| "No shell is open on this connection".to_string())?;
+
+        (|| -> Result<()> {
+            shell.channel.write_all(data.as_bytes()).context("Failed to write to shell")?;
+            shell.channel.flush().context("Failed to write to shell")?;
+            Ok(())
+        })()
+        .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+// Tells the remote PTY about a new terminal size, so full-screen programs redraw
+#[tauri::command]
+pub async fn resize_shell(
+    connection_id: String,
+    cols: u32,
+    rows: u32,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<(), errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        let shell = client.shell.as_mut().ok_or_else(|| "No shell is open on this connection".to_string())?;
+
+        shell.channel.request_pty_size(cols, rows, None, None)
+            .map_err(|e| format!("Failed to resize shell: {}", e))
+    })
+    .await
+}
+
+// Closes the connection's shell. Returns false if none was open.
+#[tauri::command]
+pub async fn close_shell(
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<bool, errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        match client.shell.take() {
+            Some(mut shell) => {
+                let _ = shell.channel.close();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    })
+    .await
+}
@@ -0,0 +1,480 @@
+// CPU, memory, disk, uptime and load figures for a server dashboard
+//
+// Everything comes from one probe script whose sections are marked with
+// `@@name` lines. Linux answers from /proc; BSD and macOS servers have no
+// /proc, so the script also asks `uptime`, sysctl and `df`, and whatever
+// still can't be read is listed in `unavailable` instead of failing.
+//
+// CPU usage needs two samples of /proc/stat. They're taken with two separate
+// commands, so the connection isn't locked while waiting between them.
+
+use crate::{errors, find_client, get_client, ConnectionsStore, SharedClient};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, State};
+
+// Time between the two /proc/stat samples
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_POLL_INTERVAL_MS: u64 = 5000;
+// Each poll takes at least the sample interval anyway
+const MIN_POLL_INTERVAL_MS: u64 = 1000;
+// How often a sleeping poller checks whether it was stopped
+const POLL_STOP_CHECK: Duration = Duration::from_millis(100);
+
+// Filesystems df lists that aren't disks
+const PSEUDO_FILESYSTEMS: &[&str] = &["tmpfs", "devtmpfs", "udev", "devfs", "efivarfs", "shm", "map auto_home"];
+
+const CPU_PROBE: &str = "head -n 1 /proc/stat 2>/dev/null";
+
+const STATS_PROBE: &str = "LC_ALL=C; export LC_ALL; PATH=\"$PATH:/usr/sbin:/sbin\"
+echo @@stat; head -n 1 /proc/stat 2>/dev/null
+echo @@meminfo; cat /proc/meminfo 2>/dev/null
+echo @@uptime; cat /proc/uptime 2>/dev/null
+echo @@loadavg; cat /proc/loadavg 2>/dev/null
+echo @@uptime_command; uptime 2>/dev/null
+echo @@boottime; sysctl -n kern.boottime 2>/dev/null && date +%s
+echo @@cpus; nproc 2>/dev/null || getconf _NPROCESSORS_ONLN 2>/dev/null || sysctl -n hw.ncpu 2>/dev/null
+echo @@df; df -P -k 2>/dev/null
+true";
+
+// Stats pollers running, by connection ID
+pub type StatsPollersStore = Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStats {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    // What can be handed out without swapping, per MemAvailable
+    pub available_bytes: u64,
+    pub swap_total_bytes: u64,
+    pub swap_used_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskUsage {
+    pub filesystem: String,
+    pub mount_point: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+    // As df reports it, which leaves out blocks reserved for root
+    pub use_percent: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemStats {
+    // Busy share of all CPUs over the sample interval
+    pub cpu_percent: Option<f64>,
+    pub cpu_count: Option<u32>,
+    pub memory: Option<MemoryStats>,
+    pub disks: Vec<DiskUsage>,
+    pub uptime_secs: Option<u64>,
+    // 1, 5 and 15 minute averages
+    pub load_average: Option<[f64; 3]>,
+    // Names of the fields above the server didn't provide
+    pub unavailable: Vec<String>,
+}
+
+// Payload of the `system-stats` event sent by start_stats_polling
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemStatsEvent {
+    pub connection_id: String,
+    pub stats: Option<SystemStats>,
+    pub error: Option<String>,
+}
+
+// Splits the probe's output into its `@@name` sections
+fn sections(output: &str) -> HashMap<&str, Vec<&str>> {
+    let mut sections: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut current = None;
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix("@@") {
+            current = Some(name.trim());
+            sections.entry(name.trim()).or_default();
+        } else if let Some(name) = current {
+            sections.entry(name).or_default().push(line);
+        }
+    }
+    sections
+}
+
+// Idle and total jiffies from /proc/stat's `cpu` line. iowait counts as
+// idle, and guest time is already part of user time.
+fn cpu_counters(line: &str) -> Option<(u64, u64)> {
+    let mut fields = line.split_whitespace();
+    if fields.next()? != "cpu" {
+        return None;
+    }
+    let values: Vec<u64> = fields.take(8).map(|f| f.parse().ok()).collect::<Option<_>>()?;
+    if values.len() < 4 {
+        return None;
+    }
+    let idle = values[3] + values.get(4).copied().unwrap_or(0);
+    Some((idle, values.iter().sum()))
+}
+
+fn cpu_percent(first: &str, second: &str) -> Option<f64> {
+    let (idle_before, total_before) = cpu_counters(first)?;
+    let (idle_after, total_after) = cpu_counters(second)?;
+    let total = total_after.checked_sub(total_before).filter(|&t| t > 0)?;
+    let idle = idle_after.saturating_sub(idle_before).min(total);
+    Some(((total - idle) as f64 * 1000.0 / total as f64).round() / 10.0)
+}
+
+fn parse_meminfo(lines: &[&str]) -> Option<MemoryStats> {
+    let values: HashMap<&str, u64> = lines.iter()
+        .filter_map(|line| {
+            let (name, rest) = line.split_once(':')?;
+            Some((name.trim(), rest.split_whitespace().next()?.parse::<u64>().ok()? * 1024))
+        })
+        .collect();
+
+    let total = *values.get("MemTotal")?;
+    // Kernels before 3.14 have no MemAvailable
+    let available = values.get("MemAvailable").copied().unwrap_or_else(|| {
+        ["MemFree", "Buffers", "Cached"].iter().filter_map(|name| values.get(name)).sum()
+    });
+    let swap_total = values.get("SwapTotal").copied().unwrap_or(0);
+    let swap_free = values.get("SwapFree").copied().unwrap_or(0);
+
+    Some(MemoryStats {
+        total_bytes: total,
+        used_bytes: total.saturating_sub(available),
+        available_bytes: available.min(total),
+        swap_total_bytes: swap_total,
+        swap_used_bytes: swap_total.saturating_sub(swap_free),
+    })
+}
+
+// The three numbers after "load average:" (Linux, BSD) or "load averages:"
+// (macOS, which leaves out the commas)
+fn parse_uptime_load(line: &str) -> Option<[f64; 3]> {
+    let (_, rest) = line.split_once("load average")?;
+    let (_, rest) = rest.split_once(':')?;
+    let values: Vec<f64> = rest.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .take(3)
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+fn parse_loadavg(line: &str) -> Option<[f64; 3]> {
+    let values: Vec<f64> = line.split_whitespace().take(3).map(|v| v.parse().ok()).collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+// sysctl's kern.boottime looks like `{ sec = 1760400000, usec = 0 } ...`,
+// followed by the probe's `date +%s`
+fn parse_boottime(lines: &[&str]) -> Option<u64> {
+    let [boottime, now] = lines else { return None };
+    let (_, rest) = boottime.split_once("sec =")?;
+    let booted: u64 = rest.split(',').next()?.trim().parse().ok()?;
+    now.trim().parse::<u64>().ok()?.checked_sub(booted)
+}
+
+// `df -P -k` lines: filesystem, 1024-blocks, used, available, capacity and
+// mount point, where the filesystem and mount point may contain spaces
+fn parse_df_line(line: &str) -> Option<DiskUsage> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let capacity = (4..tokens.len()).find(|&i| {
+        tokens[i].ends_with('%') && tokens[i - 3..i].iter().all(|t| t.parse::<u64>().is_ok())
+    })?;
+    let blocks = |i: usize| tokens[i].parse::<u64>().ok().map(|b| b * 1024);
+
+    Some(DiskUsage {
+        filesystem: tokens[..capacity - 3].join(" "),
+        mount_point: tokens[capacity + 1..].join(" "),
+        total_bytes: blocks(capacity - 3)?,
+        used_bytes: blocks(capacity - 2)?,
+        available_bytes: blocks(capacity - 1)?,
+        use_percent: tokens[capacity].trim_end_matches('%').parse().ok(),
+    })
+}
+
+fn parse_stats(first_sample: &str, output: &str) -> SystemStats {
+    let sections = sections(output);
+    let section = |name: &str| sections.get(name).map(Vec::as_slice).unwrap_or_default();
+    let first_line = |name: &str| section(name).first().copied();
+
+    let cpu_percent = first_line("stat").and_then(|second| cpu_percent(first_sample, second));
+    let cpu_count = first_line("cpus").and_then(|c| c.trim().parse().ok()).filter(|&c: &u32| c > 0);
+    let memory = parse_meminfo(section("meminfo"));
+
+    let uptime_secs = first_line("uptime")
+        .and_then(|line| line.split_whitespace().next()?.parse::<f64>().ok())
+        .map(|secs| secs as u64)
+        .or_else(|| parse_boottime(section("boottime")));
+    let load_average = first_line("loadavg")
+        .and_then(parse_loadavg)
+        .or_else(|| first_line("uptime_command").and_then(parse_uptime_load));
+
+    let disks: Vec<DiskUsage> = section("df").iter()
+        .skip(1)
+        .filter_map(|line| parse_df_line(line))
+        .filter(|disk| disk.total_bytes > 0 && !PSEUDO_FILESYSTEMS.contains(&disk.filesystem.as_str()))
+        .collect();
+
+    let unavailable = [
+        ("cpu_percent", cpu_percent.is_none()),
+        ("cpu_count", cpu_count.is_none()),
+        ("memory", memory.is_none()),
+        ("disks", disks.is_empty()),
+        ("uptime_secs", uptime_secs.is_none()),
+        ("load_average", load_average.is_none()),
+    ]
+    .into_iter()
+    .filter(|&(_, missing)| missing)
+    .map(|(name, _)| name.to_string())
+    .collect();
+
+    SystemStats { cpu_percent, cpu_count, memory, disks, uptime_secs, load_average, unavailable }
+}
+
+// Takes both samples, locking the connection only while each command runs
+fn collect_stats(client: &SharedClient) -> Result<SystemStats> {
+    let (_, first_sample, _) = client.lock()
+        .map_err(|e| anyhow!("Lock error: {}", e))?
+        .exec_capture(CPU_PROBE)?;
+    thread::sleep(CPU_SAMPLE_INTERVAL);
+    let (_, output, _) = client.lock()
+        .map_err(|e| anyhow!("Lock error: {}", e))?
+        .exec_capture(STATS_PROBE)?;
+    Ok(parse_stats(&first_sample, &output))
+}
+
+// Reads the server's CPU, memory, disk, uptime and load figures. Takes a
+// little over half a second, for the CPU sample.
+#[tauri::command]
+pub async fn get_system_stats(
+    connection_id: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<SystemStats, errors::SSHError> {
+    let client = find_client(&connections, &connection_id)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        collect_stats(&client).map_err(|e| errors::SSHError::from_anyhow(&e, format!("Failed to read system stats: {}", e)))
+    })
+    .await
+    .map_err(|e| format!("Connection task failed: {}", e))?
+}
+
+// Sleeps for `interval`, waking early if the poller is stopped
+fn wait_or_stop(stop: &AtomicBool, interval: Duration) {
+    let started = Instant::now();
+    while !stop.load(Ordering::SeqCst) && started.elapsed() < interval {
+        thread::sleep(POLL_STOP_CHECK.min(interval.saturating_sub(started.elapsed())));
+    }
+}
+
+fn poll_stats(app: &AppHandle, connections: &ConnectionsStore, connection_id: &str, stop: &AtomicBool, interval: Duration) {
+    while !stop.load(Ordering::SeqCst) {
+        // Ends with the connection; the reference isn't kept across the wait
+        let Ok(client) = get_client(connections, connection_id) else { break };
+        let event = match collect_stats(&client) {
+            Ok(stats) => SystemStatsEvent { connection_id: connection_id.to_string(), stats: Some(stats), error: None },
+            Err(e) => SystemStatsEvent { connection_id: connection_id.to_string(), stats: None, error: Some(e.to_string()) },
+        };
+        drop(client);
+
+        if !stop.load(Ordering::SeqCst) {
+            let _ = app.emit("system-stats", event);
+        }
+        wait_or_stop(stop, interval);
+    }
+}
+
+// Emits a `system-stats` event every `interval_ms` (5000 by default, at
+// least 1000) until stop_stats_polling or the connection closes. Starting
+// again on the same connection replaces the previous poller.
+#[tauri::command]
+pub async fn start_stats_polling(
+    app: AppHandle,
+    connection_id: String,
+    interval_ms: Option<u64>,
+    connections: State<'_, ConnectionsStore>,
+    pollers: State<'_, StatsPollersStore>,
+) -> Result<(), errors::SSHError> {
+    find_client(&connections, &connection_id)?;
+    let interval = Duration::from_millis(interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS).max(MIN_POLL_INTERVAL_MS));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let mut pollers = pollers.lock().map_err(|e| format!("Lock error: {}", e))?;
+        if let Some(previous) = pollers.insert(connection_id.clone(), stop.clone()) {
+            previous.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let connections = connections.inner().clone();
+    let pollers = pollers.inner().clone();
+    thread::spawn(move || {
+        poll_stats(&app, &connections, &connection_id, &stop, interval);
+
+        // Unless a newer poller has already taken the slot
+        if let Ok(mut pollers) = pollers.lock() {
+            if pollers.get(&connection_id).is_some_and(|current| Arc::ptr_eq(current, &stop)) {
+                pollers.remove(&connection_id);
+            }
+        }
+    });
+    Ok(())
+}
+
+// Stops the connection's stats poller. Returns false if none was running.
+#[tauri::command]
+pub async fn stop_stats_polling(
+    connection_id: String,
+    pollers: State<'_, StatsPollersStore>,
+) -> Result<bool, errors::SSHError> {
+    let mut pollers = pollers.lock().map_err(|e| format!("Lock error: {}", e))?;
+    match pollers.remove(&connection_id) {
+        Some(stop) => {
+            stop.store(true, Ordering::SeqCst);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINUX_FIRST_SAMPLE: &str = "cpu  100 0 50 800 50 0 0 0 0 0";
+
+    const LINUX: &str = "\
+@@stat
+cpu  160 0 70 900 70 0 0 0 0 0
+@@meminfo
+MemTotal:        8000000 kB
+MemFree:         1000000 kB
+MemAvailable:    4000000 kB
+Buffers:          200000 kB
+Cached:          2000000 kB
+SwapTotal:       2000000 kB
+SwapFree:        1500000 kB
+@@uptime
+12345.67 45678.90
+@@loadavg
+0.52 0.58 0.59 1/123 4567
+@@uptime_command
+ 10:15:01 up 3:25,  1 user,  load average: 0.52, 0.58, 0.59
+@@boottime
+@@cpus
+4
+@@df
+Filesystem     1024-blocks     Used Available Capacity Mounted on
+/dev/sda1         20511312 11534372   7912388      60% /
+tmpfs              1018124        0   1018124       0% /dev/shm
+";
+
+    const FREEBSD: &str = "\
+@@stat
+@@meminfo
+@@uptime
+@@loadavg
+@@uptime_command
+ 9:41AM  up 3 days,  2:03, 1 user, load averages: 0.15, 0.20, 0.18
+@@boottime
+{ sec = 1760400000, usec = 123456 } Tue Oct 14 00:00:00 2025
+1760403600
+@@cpus
+2
+@@df
+Filesystem   1024-blocks    Used    Avail Capacity  Mounted on
+/dev/ada0p2     29327292 6543210 20437900    24%    /
+devfs                  1       1        0   100%    /dev
+";
+
+    const MACOS: &str = "\
+@@stat
+@@meminfo
+@@uptime
+@@loadavg
+@@uptime_command
+10:15  up 5 days,  3:02, 2 users, load averages: 1.91 2.05 2.10
+@@boottime
+{ sec = 1760000000, usec = 0 } Thu Oct  9 08:53:20 2025
+1760432000
+@@cpus
+8
+@@df
+Filesystem     1024-blocks      Used Available Capacity  Mounted on
+/dev/disk3s1s1   482797652  10156704 184203592     6%    /
+map auto_home            0         0         0   100%    /System/Volumes/Data/home
+/dev/disk4s1       1000000    250000    750000    25%    /Volumes/My Drive
+";
+
+    fn mounts(stats: &SystemStats) -> Vec<&str> {
+        stats.disks.iter().map(|d| d.mount_point.as_str()).collect()
+    }
+
+    #[test]
+    fn linux_probe() {
+        let stats = parse_stats(LINUX_FIRST_SAMPLE, LINUX);
+        assert_eq!(stats.cpu_percent, Some(40.0));
+        assert_eq!(stats.cpu_count, Some(4));
+        assert_eq!(stats.uptime_secs, Some(12345));
+        assert_eq!(stats.load_average, Some([0.52, 0.58, 0.59]));
+
+        let memory = stats.memory.as_ref().unwrap();
+        assert_eq!(memory.total_bytes, 8_192_000_000);
+        assert_eq!((memory.available_bytes, memory.used_bytes), (4_096_000_000, 4_096_000_000));
+        assert_eq!((memory.swap_total_bytes, memory.swap_used_bytes), (2_048_000_000, 512_000_000));
+
+        assert_eq!(mounts(&stats), ["/"]);
+        let root = &stats.disks[0];
+        assert_eq!(root.filesystem, "/dev/sda1");
+        assert_eq!((root.total_bytes, root.used_bytes, root.available_bytes), (20511312 * 1024, 11534372 * 1024, 7912388 * 1024));
+        assert_eq!(root.use_percent, Some(60));
+        assert!(stats.unavailable.is_empty(), "{:?}", stats.unavailable);
+    }
+
+    #[test]
+    fn freebsd_probe() {
+        let stats = parse_stats("", FREEBSD);
+        assert_eq!(stats.cpu_count, Some(2));
+        assert_eq!(stats.uptime_secs, Some(3600));
+        assert_eq!(stats.load_average, Some([0.15, 0.20, 0.18]));
+        assert_eq!(mounts(&stats), ["/"]);
+        assert_eq!(stats.unavailable, ["cpu_percent", "memory"]);
+    }
+
+    #[test]
+    fn macos_probe() {
+        let stats = parse_stats("", MACOS);
+        assert_eq!(stats.cpu_count, Some(8));
+        assert_eq!(stats.uptime_secs, Some(432000));
+        assert_eq!(stats.load_average, Some([1.91, 2.05, 2.10]));
+        assert_eq!(mounts(&stats), ["/", "/Volumes/My Drive"]);
+        assert_eq!(stats.disks[1].use_percent, Some(25));
+        assert_eq!(stats.unavailable, ["cpu_percent", "memory"]);
+    }
+
+    #[test]
+    fn meminfo_without_memavailable() {
+        let memory = parse_meminfo(&[
+            "MemTotal:        2000000 kB",
+            "MemFree:          500000 kB",
+            "Buffers:          100000 kB",
+            "Cached:           400000 kB",
+        ])
+        .unwrap();
+        assert_eq!(memory.available_bytes, 1_000_000 * 1024);
+        assert_eq!(memory.used_bytes, 1_000_000 * 1024);
+        assert_eq!((memory.swap_total_bytes, memory.swap_used_bytes), (0, 0));
+    }
+
+    #[test]
+    fn nothing_readable_is_all_unavailable() {
+        let stats = parse_stats("", "@@stat\n@@meminfo\n@@df\nFilesystem 1024-blocks Used Available Capacity Mounted on\n");
+        assert_eq!(stats.unavailable, ["cpu_percent", "cpu_count", "memory", "disks", "uptime_secs", "load_average"]);
+        assert_eq!(cpu_percent("cpu 1 2 3", "cpu 1 2 3"), None);
+        assert_eq!(parse_boottime(&["{ sec = 1760000000, usec = 0 }"]), None);
+    }
+}
@@ -0,0 +1,205 @@
+// Answering sudo's password prompt on one-shot commands
+//
+// Given a password, the command's shell exports SUDO_PROMPT so every sudo it
+// runs asks in the same words whatever the locale, and each prompt is
+// answered as it appears on the PTY. The prompt carries a random nonce made
+// for that command, so output that merely looks like a prompt is left alone
+// and never gets the password. sudo turns echo off while it reads, and the
+// prompt's line is cut from stdout as well, so the password never reaches
+// the result.
+//
+// A later prompt is answered too, since a command may run sudo more than
+// once with timestamp_timeout=0. Only when sudo complains about the answer
+// before asking again is the password taken as rejected, and the command is
+// stopped then rather than left to use up the other tries.
+
+use crate::shell_quote;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// What sudo prints after a wrong password, before asking again
+const REJECTED: &str = "Sorry, try again.";
+
+static FALLBACK_NONCE: AtomicU64 = AtomicU64::new(0);
+
+// Sixteen hex digits, from OpenSSL's generator, or from the clock should
+// that ever fail
+fn new_nonce() -> String {
+    let mut bytes = [0u8; 8];
+    if openssl::rand::rand_bytes(&mut bytes).is_err() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        bytes = (nanos ^ FALLBACK_NONCE.fetch_add(1, Ordering::SeqCst).rotate_left(32)).to_le_bytes();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// What the latest output asked for
+#[derive(Debug, PartialEq)]
+pub enum PromptState {
+    Waiting,
+    // A prompt is waiting for password_line
+    Answer,
+    // sudo said the answer was wrong and asked again
+    Rejected,
+}
+
+pub struct PasswordResponder {
+    password: String,
+    // The prompt up to the user's name, which sudo fills in
+    prompt: String,
+    // How much of stdout has been looked through
+    scanned: usize,
+    // Where the answered prompt starts, until the newline sudo prints after
+    // reading the password arrives and the line can be cut
+    answered_line: Option<usize>,
+    // Where the output after the last answered prompt starts
+    answered: Option<usize>,
+}
+
+impl PasswordResponder {
+    pub fn new(password: &str) -> Self {
+        PasswordResponder {
+            password: password.to_string(),
+            prompt: format!("[sudo:{}] password for ", new_nonce()),
+            scanned: 0,
+            answered_line: None,
+            answered: None,
+        }
+    }
+
+    // Goes before the command, in the same form as CommandPriority::shell_prefix
+    pub fn shell_prefix(&self) -> String {
+        format!("SUDO_PROMPT={}; export SUDO_PROMPT; ", shell_quote(&format!("{}%p: ", self.prompt)))
+    }
+
+    pub fn password_line(&self) -> Vec<u8> {
+        format!("{}\n", self.password).into_bytes()
+    }
+
+    // Looks through what stdout has gained since the last call, cutting out
+    // answered prompts
+    pub fn scan(&mut self, stdout: &mut Vec<u8>) -> PromptState {
+        // The output cap may have cut stdout short of where scanning got to
+        self.scanned = self.scanned.min(stdout.len());
+
+        if let Some(start) = self.answered_line {
+            let start = start.min(stdout.len());
+            match find(&stdout[self.scanned..], b"\n") {
+                Some(offset) => {
+                    stdout.drain(start..self.scanned + offset + 1);
+                    self.scanned = start;
+                    self.answered_line = None;
+                    self.answered = Some(start);
+                }
+                None => {
+                    self.scanned = stdout.len();
+                    return PromptState::Waiting;
+                }
+            }
+        }
+
+        let prompt = self.prompt.as_bytes();
+        let Some(offset) = find(&stdout[self.scanned..], prompt) else {
+            // The end may be the start of a prompt still arriving
+            self.scanned = stdout.len().saturating_sub(prompt.len() - 1).max(self.scanned);
+            return PromptState::Waiting;
+        };
+        let start = self.scanned + offset;
+        self.scanned = start;
+        // The prompt is all there once its ": " is
+        let Some(end) = find(&stdout[start + prompt.len()..], b": ") else {
+            return PromptState::Waiting;
+        };
+        if let Some(since) = self.answered {
+            if find(&stdout[since.min(start)..start], REJECTED.as_bytes()).is_some() {
+                stdout.truncate(start);
+                return PromptState::Rejected;
+            }
+        }
+
+        self.answered_line = Some(start);
+        self.scanned = start + prompt.len() + end + 2;
+        PromptState::Answer
+    }
+
+    // Cuts an answered prompt whose line never ended, when the command did
+    pub fn finish(&mut self, stdout: &mut Vec<u8>) {
+        if let Some(start) = self.answered_line.take() {
+            stdout.truncate(start.min(stdout.len()));
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The prompt sudo prints for `responder`, as the shell prefix sets it up
+    fn prompt(responder: &PasswordResponder) -> String {
+        format!("{}alice: ", responder.prompt)
+    }
+
+    #[test]
+    fn nonces_differ_per_command() {
+        let (first, second) = (PasswordResponder::new("pw"), PasswordResponder::new("pw"));
+        assert_ne!(first.prompt, second.prompt);
+        assert!(first.shell_prefix().contains(&first.prompt));
+    }
+
+    #[test]
+    fn answers_a_prompt_split_across_reads() {
+        let mut responder = PasswordResponder::new("pw");
+        let prompt = prompt(&responder);
+        let (head, tail) = prompt.split_at(12);
+
+        let mut stdout = format!("before\n{}", head).into_bytes();
+        assert_eq!(responder.scan(&mut stdout), PromptState::Waiting);
+        stdout.extend_from_slice(tail.as_bytes());
+        assert_eq!(responder.scan(&mut stdout), PromptState::Answer);
+
+        // sudo's newline after the password ends the prompt's line
+        stdout.extend_from_slice(b"\r\nroot\r\n");
+        assert_eq!(responder.scan(&mut stdout), PromptState::Waiting);
+        assert_eq!(stdout, b"before\nroot\r\n");
+    }
+
+    #[test]
+    fn ignores_output_that_looks_like_a_prompt() {
+        let mut responder = PasswordResponder::new("pw");
+        let mut stdout = b"[sudo] password for alice: \n[sudo:0123456789abcdef] password for alice: \n".to_vec();
+        let expected = stdout.clone();
+
+        assert_eq!(responder.scan(&mut stdout), PromptState::Waiting);
+        assert_eq!(stdout, expected);
+    }
+
+    #[test]
+    fn answers_each_sudo_a_command_runs() {
+        let mut responder = PasswordResponder::new("pw");
+        let prompt = prompt(&responder);
+
+        let mut stdout = prompt.clone().into_bytes();
+        assert_eq!(responder.scan(&mut stdout), PromptState::Answer);
+        stdout.extend_from_slice(format!("\r\nfirst\r\n{}", prompt).as_bytes());
+        assert_eq!(responder.scan(&mut stdout), PromptState::Answer);
+        stdout.extend_from_slice(b"\r\nsecond\r\n");
+        assert_eq!(responder.scan(&mut stdout), PromptState::Waiting);
+        assert_eq!(stdout, b"first\r\nsecond\r\n");
+    }
+
+    #[test]
+    fn stops_when_sudo_rejects_the_password() {
+        let mut responder = PasswordResponder::new("wrong");
+        let prompt = prompt(&responder);
+
+        let mut stdout = prompt.clone().into_bytes();
+        assert_eq!(responder.scan(&mut stdout), PromptState::Answer);
+        stdout.extend_from_slice(format!("\r\nSorry, try again.\r\n{}", prompt).as_bytes());
+        assert_eq!(responder.scan(&mut stdout), PromptState::Rejected);
+        assert_eq!(stdout, b"Sorry, try again.\r\n");
+    }
+}
@@ -0,0 +1,376 @@
+// Parsing columnar command output into rows
+//
+// `ps`, `df`, `docker ps` and friends print a header line followed by
+// whitespace-aligned columns. execute_table runs such a command and splits
+// its output into one map per row, keyed by header, so the frontend doesn't
+// need a bespoke parser for every command.
+//
+// Headers like `df -P`'s "Mounted on" and values like `docker ps`'s "2 hours
+// ago" contain spaces, so aligned output is cut by where words sit under the
+// header rather than at every space.
+
+use crate::{command_error, errors, execute_queued, CommandResult, ConnectionsStore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tauri::{AppHandle, State};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TableOptions {
+    // Splits on this string instead of runs of whitespace, e.g. "," or "\t"
+    pub delimiter: Option<String>,
+    // Whether the first line holds the column names (default true)
+    pub has_header: Option<bool>,
+    // Column names to use instead of the first line's. With has_header left
+    // on, the first line is still skipped.
+    pub headers: Option<Vec<String>>,
+    // Lines to skip before the header, for commands that print a preamble
+    pub skip_lines: Option<usize>,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableResult {
+    pub headers: Vec<String>,
+    pub rows: Vec<HashMap<String, String>>,
+    // The raw command result, for the exit status, stderr and so on
+    pub result: CommandResult,
+}
+
+// Splits one line into fields. Quotes around a field group it and are
+// removed, so `"a b",c` or `'my file' 12` come out as two fields either way.
+fn split_fields(line: &str, delimiter: Option<&str>) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_field = false;
+    let mut quote = None;
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(q) = quote {
+            rest = &rest[c.len_utf8()..];
+            if c == q {
+                quote = None;
+            } else {
+                field.push(c);
+            }
+            continue;
+        }
+
+        match delimiter {
+            Some(d) if rest.starts_with(d) => {
+                fields.push(std::mem::take(&mut field));
+                in_field = false;
+                rest = &rest[d.len()..];
+                continue;
+            }
+            None if c.is_whitespace() => {
+                if in_field {
+                    fields.push(std::mem::take(&mut field));
+                    in_field = false;
+                }
+            }
+            // Only a quote that starts a field groups it, so apostrophes in
+            // plain text (`don't`) are left alone
+            _ if !in_field && (c == '"' || c == '\'') => {
+                in_field = true;
+                quote = Some(c);
+            }
+            _ => {
+                in_field = true;
+                field.push(c);
+            }
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    // With a delimiter every line has a last field, even an empty one
+    if in_field || delimiter.is_some() {
+        fields.push(field);
+    }
+    fields
+}
+
+// Pairs a line's fields with the headers. Short rows get empty strings for
+// the missing columns. Extra fields are joined into the last column when
+// splitting on whitespace (so `ps`'s COMMAND keeps its arguments), and get
+// their own column_N keys otherwise.
+fn build_row(headers: &[String], mut fields: Vec<String>, whitespace: bool) -> HashMap<String, String> {
+    if whitespace && !headers.is_empty() && fields.len() > headers.len() {
+        let tail = fields.split_off(headers.len() - 1).join(" ");
+        fields.push(tail);
+    }
+
+    let mut row: HashMap<String, String> = headers.iter().cloned().zip(fields.iter().cloned()).collect();
+    for header in headers.iter().skip(fields.len()) {
+        row.insert(header.clone(), String::new());
+    }
+    for (i, field) in fields.into_iter().enumerate().skip(headers.len()) {
+        row.insert(format!("column_{}", i + 1), field);
+    }
+    row
+}
+
+// The character columns of each word in a line, end exclusive
+fn word_spans(line: &[char]) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in line.iter().enumerate() {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                spans.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, line.len()));
+    }
+    spans
+}
+
+// Which column a word of a row belongs to: the one it sits under, or else
+// the last one starting before it, as left-aligned values run on past their
+// header
+fn column_of(columns: &[(usize, usize)], (start, end): (usize, usize)) -> usize {
+    columns.iter()
+        .position(|&(c_start, c_end)| start < c_end && end > c_start)
+        .or_else(|| columns.iter().rposition(|&(c_start, _)| c_start <= start))
+        .unwrap_or(0)
+}
+
+// Each row's text under each column
+fn cut_rows(columns: &[(usize, usize)], rows: &[Vec<char>]) -> Vec<Vec<Option<(usize, usize)>>> {
+    rows.iter()
+        .map(|row| {
+            let mut cut: Vec<Option<(usize, usize)>> = vec![None; columns.len()];
+            for span in word_spans(row) {
+                let column = &mut cut[column_of(columns, span)];
+                *column = Some(column.map_or(span, |(start, _)| (start, span.1)));
+            }
+            cut
+        })
+        .collect()
+}
+
+// Splits aligned output by the header's words. Header words that a value
+// straddles are one column ("CONTAINER ID"), as is a word one space after
+// the previous that nothing sits under ("Mounted on" when every mount point
+// is short).
+fn parse_aligned(header: &str, lines: Vec<&str>) -> (Vec<String>, Vec<Vec<String>>) {
+    let header: Vec<char> = header.chars().collect();
+    let rows: Vec<Vec<char>> = lines.iter().map(|line| line.chars().collect()).collect();
+
+    let mut columns = word_spans(&header);
+    for row in &rows {
+        for (start, end) in word_spans(row) {
+            let straddled: Vec<usize> = (0..columns.len())
+                .filter(|&i| start < columns[i].1 && end > columns[i].0)
+                .collect();
+            if let (Some(&first), Some(&last)) = (straddled.first(), straddled.last()) {
+                columns[first].1 = columns[last].1;
+                columns.drain(first + 1..=last);
+            }
+        }
+    }
+
+    let mut cut = cut_rows(&columns, &rows);
+    let mut i = 1;
+    while i < columns.len() {
+        if columns[i].0 - columns[i - 1].1 == 1 && cut.iter().all(|row| row[i].is_none()) {
+            columns[i - 1].1 = columns.remove(i).1;
+            cut = cut_rows(&columns, &rows);
+        } else {
+            i += 1;
+        }
+    }
+
+    let headers = columns.iter().map(|&(start, end)| header[start..end].iter().collect()).collect();
+    let rows = rows.iter()
+        .zip(cut)
+        .map(|(row, cut)| {
+            cut.into_iter()
+                .map(|span| span.map(|(start, end)| row[start..end].iter().collect()).unwrap_or_default())
+                .collect()
+        })
+        .collect();
+    (headers, rows)
+}
+
+fn parse_table(output: &str, options: &TableOptions) -> (Vec<String>, Vec<HashMap<String, String>>) {
+    let delimiter = options.delimiter.as_deref().filter(|d| !d.is_empty());
+    let mut lines = output.lines()
+        .skip(options.skip_lines.unwrap_or(0))
+        .filter(|line| !line.trim().is_empty());
+
+    let has_header = options.has_header.unwrap_or(true);
+    if has_header && delimiter.is_none() && options.headers.is_none() {
+        let Some(header) = lines.next() else {
+            return (Vec::new(), Vec::new());
+        };
+        let (headers, rows) = parse_aligned(header, lines.collect());
+        let rows = rows.into_iter().map(|fields| headers.iter().cloned().zip(fields).collect()).collect();
+        return (headers, rows);
+    }
+
+    let first = if has_header {
+        lines.next().map(|line| split_fields(line, delimiter))
+    } else {
+        None
+    };
+
+    let rows: Vec<Vec<String>> = lines.map(|line| split_fields(line, delimiter)).collect();
+
+    let headers = match (&options.headers, first) {
+        (Some(headers), _) => headers.clone(),
+        (None, Some(first)) => first.into_iter().map(|h| h.trim().to_string()).collect(),
+        // No header at all: number the columns by the widest row
+        (None, None) => {
+            let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+            (1..=width).map(|i| format!("column_{}", i)).collect()
+        }
+    };
+
+    let rows = rows.into_iter()
+        .map(|fields| build_row(&headers, fields, delimiter.is_none()))
+        .collect();
+    (headers, rows)
+}
+
+// Runs a command and parses its stdout as a table. The rows are still
+// returned for a failed command, since many print partial tables.
+#[tauri::command]
+pub async fn execute_table(
+    app: AppHandle,
+    connection_id: String,
+    command: String,
+    options: Option<TableOptions>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<TableResult, errors::SSHError> {
+    let options = options.unwrap_or_default();
+    let id = connection_id.clone();
+    let timeout = options.timeout_ms.map(Duration::from_millis);
+    let result = execute_queued(
+        &connections,
+        &connection_id,
+        move |client| client.start_command(&command, timeout, None, None, None, None),
+        move |client, e| Ok(command_error(&app, &id, client, e)),
+    )
+    .await?;
+
+    let (headers, rows) = parse_table(&result.stdout, &options);
+    Ok(TableResult { headers, rows, result })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn whitespace_columns_keep_the_last_columns_spaces() {
+        let output = "  PID TTY          TIME CMD\n    1 ?        00:00:02 /sbin/init splash\n 4242 pts/0    00:00:00 ps\n";
+        let (headers, rows) = parse_table(output, &TableOptions::default());
+        assert_eq!(headers, ["PID", "TTY", "TIME", "CMD"]);
+        assert_eq!(rows, [
+            row(&[("PID", "1"), ("TTY", "?"), ("TIME", "00:00:02"), ("CMD", "/sbin/init splash")]),
+            row(&[("PID", "4242"), ("TTY", "pts/0"), ("TIME", "00:00:00"), ("CMD", "ps")]),
+        ]);
+    }
+
+    #[test]
+    fn df_mount_points_keep_their_spaces() {
+        let output = "\
+Filesystem     1024-blocks     Used Available Capacity Mounted on
+/dev/sda1         20511312 11534372   7912388      60% /
+tmpfs              1018124        0   1018124       0% /dev/shm
+/dev/sdb1        103081248 52428800  45409280      54% /mnt/data disk
+";
+        let (headers, rows) = parse_table(output, &TableOptions::default());
+        assert_eq!(headers, ["Filesystem", "1024-blocks", "Used", "Available", "Capacity", "Mounted on"]);
+        assert_eq!(rows[0], row(&[
+            ("Filesystem", "/dev/sda1"),
+            ("1024-blocks", "20511312"),
+            ("Used", "11534372"),
+            ("Available", "7912388"),
+            ("Capacity", "60%"),
+            ("Mounted on", "/"),
+        ]));
+        assert_eq!(rows[2]["Mounted on"], "/mnt/data disk");
+
+        // Nothing reaches under "on" when every mount point is short
+        let short = "Filesystem     1024-blocks  Used Available Capacity Mounted on\n/dev/sda1         20511312 11534   7912388      60% /\n";
+        let (headers, rows) = parse_table(short, &TableOptions::default());
+        assert_eq!(headers.last().unwrap(), "Mounted on");
+        assert_eq!(rows[0]["Mounted on"], "/");
+    }
+
+    #[test]
+    fn docker_ps_values_with_spaces_stay_in_their_column() {
+        let output = "\
+CONTAINER ID   IMAGE          COMMAND                  CREATED       STATUS                  PORTS                                   NAMES
+4c01db0b339c   nginx:latest   \"/docker-entrypoint.…\"   2 hours ago   Up 2 hours              0.0.0.0:8080->80/tcp, :::8080->80/tcp   web
+d7e8f9a0b1c2   redis:7        \"docker-entrypoint.s…\"   3 days ago    Exited (0) 2 days ago                                           cache
+";
+        let (headers, rows) = parse_table(output, &TableOptions::default());
+        assert_eq!(headers, ["CONTAINER ID", "IMAGE", "COMMAND", "CREATED", "STATUS", "PORTS", "NAMES"]);
+        assert_eq!(rows, [
+            row(&[
+                ("CONTAINER ID", "4c01db0b339c"),
+                ("IMAGE", "nginx:latest"),
+                ("COMMAND", "\"/docker-entrypoint.…\""),
+                ("CREATED", "2 hours ago"),
+                ("STATUS", "Up 2 hours"),
+                ("PORTS", "0.0.0.0:8080->80/tcp, :::8080->80/tcp"),
+                ("NAMES", "web"),
+            ]),
+            row(&[
+                ("CONTAINER ID", "d7e8f9a0b1c2"),
+                ("IMAGE", "redis:7"),
+                ("COMMAND", "\"docker-entrypoint.s…\""),
+                ("CREATED", "3 days ago"),
+                ("STATUS", "Exited (0) 2 days ago"),
+                ("PORTS", ""),
+                ("NAMES", "cache"),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn quotes_group_fields() {
+        assert_eq!(split_fields(r#""a b" 'my file' don't"#, None), ["a b", "my file", "don't"]);
+        assert_eq!(split_fields(r#""a,b",c,"#, Some(",")), ["a,b", "c", ""]);
+        assert_eq!(split_fields("naïve\tcafé", Some("\t")), ["naïve", "café"]);
+    }
+
+    #[test]
+    fn ragged_rows() {
+        let options = TableOptions { delimiter: Some(",".to_string()), ..Default::default() };
+        let (_, rows) = parse_table("name,size\nshort\nlong,1,extra\n", &options);
+        assert_eq!(rows, [
+            row(&[("name", "short"), ("size", "")]),
+            row(&[("name", "long"), ("size", "1"), ("column_3", "extra")]),
+        ]);
+    }
+
+    #[test]
+    fn header_options() {
+        let output = "Filesystem info\nFilesystem Size\n/dev/sda1 20G\n";
+
+        let skipped = TableOptions { skip_lines: Some(1), ..Default::default() };
+        assert_eq!(parse_table(output, &skipped).1, [row(&[("Filesystem", "/dev/sda1"), ("Size", "20G")])]);
+
+        let renamed = TableOptions { skip_lines: Some(1), headers: Some(vec!["fs".into(), "size".into()]), ..Default::default() };
+        assert_eq!(parse_table(output, &renamed).1, [row(&[("fs", "/dev/sda1"), ("size", "20G")])]);
+
+        let none = TableOptions { has_header: Some(false), ..Default::default() };
+        let (headers, rows) = parse_table("a b\nc\n", &none);
+        assert_eq!(headers, ["column_1", "column_2"]);
+        assert_eq!(rows[1], row(&[("column_1", "c"), ("column_2", "")]));
+    }
+}
@@ -0,0 +1,70 @@
+// Helpers for the tests that need a real SSH server
+//
+// Those tests are #[ignore]d. Point them at a server with AETHERSSH_TEST_HOST,
+// AETHERSSH_TEST_PORT (default 22), AETHERSSH_TEST_USER and either
+// AETHERSSH_TEST_PASSWORD or AETHERSSH_TEST_KEY (a private key file), then
+// run `cargo test -- --ignored`. Tests needing a second server read the same
+// variables with another prefix, e.g. AETHERSSH_TEST_TARGET_HOST.
+
+use crate::{auth, ConnectionsStore, SSHClient, SSHConnectionConfig};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub fn config(host: &str, port: u16, username: &str) -> SSHConnectionConfig {
+    SSHConnectionConfig {
+        host: host.to_string(),
+        port,
+        username: username.to_string(),
+        password: None,
+        private_key_path: None,
+        private_key_contents: None,
+        passphrase: None,
+        accept_new_host_key: true,
+        auth_method: None,
+        connect_timeout_ms: Some(10_000),
+        command_timeout_ms: Some(30_000),
+        keepalive_interval_secs: None,
+        jump_host: None,
+        initial_directory: None,
+        max_sessions_wait_ms: None,
+        auto_reconnect: None,
+        max_output_bytes: None,
+    }
+}
+
+// The server described by `<prefix>_HOST` and friends, if it's set
+pub fn config_from_env(prefix: &str) -> Option<SSHConnectionConfig> {
+    let var = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok().filter(|v| !v.is_empty());
+
+    let host = var("HOST")?;
+    let port = var("PORT").map(|p| p.parse().expect("invalid port")).unwrap_or(22);
+    let username = var("USER").unwrap_or_else(|| std::env::var("USER").unwrap_or_default());
+    let mut config = config(&host, port, &username);
+    config.password = var("PASSWORD");
+    config.private_key_path = var("KEY");
+    Some(config)
+}
+
+pub fn test_config() -> SSHConnectionConfig {
+    config_from_env("AETHERSSH_TEST")
+        .expect("set AETHERSSH_TEST_HOST, _USER and _PASSWORD or _KEY to run this test")
+}
+
+pub fn connect(config: &SSHConnectionConfig) -> SSHClient {
+    let mut client = SSHClient::new(config, &mut auth::NoPrompter).expect("connect");
+    client.authenticate(config, &mut auth::NoPrompter).expect("authenticate");
+    client
+}
+
+// Output of a command that has to succeed
+pub fn run(client: &mut SSHClient, command: &str) -> String {
+    let result = client.execute_command(command, None).expect(command);
+    assert!(result.success, "{} failed: {} {}", command, result.stdout, result.stderr);
+    result.stdout.trim().to_string()
+}
+
+// A connections store holding the given clients under the given IDs
+pub fn store(clients: Vec<(&str, SSHClient)>) -> ConnectionsStore {
+    let clients = clients.into_iter().map(|(id, client)| (id.to_string(), Arc::new(Mutex::new(client))));
+    Arc::new(Mutex::new(clients.collect::<HashMap<_, _>>()))
+}
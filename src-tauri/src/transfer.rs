@@ -0,0 +1,661 @@
+// SFTP file transfer commands
+
+use crate::{errors, get_client, with_client, ConnectionsStore};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use ssh2::{ErrorCode, File, OpenFlags, OpenType, Sftp};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, State};
+
+// Files kept in flight at once when the caller doesn't choose a depth
+const DEFAULT_PIPELINE_DEPTH: usize = 4;
+// Every lane is its own SFTP channel, and servers cap channels per connection
+// (OpenSSH's MaxSessions defaults to 10), so stay well below that
+const MAX_PIPELINE_DEPTH: usize = 8;
+// How long to back off when no lane could make progress
+const BATCH_POLL_INTERVAL: Duration = Duration::from_millis(2);
+// Read from each local file at a time. libssh2 turns one write into several
+// outstanding SFTP requests, so this is also how far ahead a lane writes.
+const UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
+// A file whose upload gets nowhere for this long is given up on
+const UPLOAD_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+// Minimum gap between byte-level progress events
+pub(crate) const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+// Read per lock of the connection by resumable downloads, so other commands
+// on the connection get a turn in between
+pub(crate) const DOWNLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+// libssh2's LIBSSH2_ERROR_EAGAIN, returned by non-blocking calls that need retrying
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+
+#[derive(Debug, Deserialize)]
+pub struct FileTransfer {
+    pub local_path: String,
+    pub remote_path: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileUploadResult {
+    pub local_path: String,
+    pub remote_path: String,
+    pub success: bool,
+    pub bytes_transferred: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchUploadResult {
+    pub files_total: usize,
+    pub files_succeeded: usize,
+    pub files_failed: usize,
+    pub bytes_transferred: u64,
+    pub elapsed_ms: u64,
+    pub results: Vec<FileUploadResult>,
+}
+
+// Payload of the `upload-batch-progress` event
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchUploadProgress {
+    pub connection_id: String,
+    pub file_index: usize,
+    pub local_path: String,
+    pub remote_path: String,
+    pub file_bytes_sent: u64,
+    pub file_size: u64,
+    pub file_done: bool,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+// Handle to a command's output saved on the server by execute_to_resumable_file.
+// It only names the file, so the frontend can keep it across a reconnect and
+// pass it back to download_resumable_output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumableOutput {
+    pub remote_path: String,
+    // Size of the output (stdout and stderr together), used to tell when the
+    // download is complete
+    pub size: u64,
+    pub lines: u64,
+    pub exit_status: i32,
+    pub success: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResumableDownload {
+    pub local_path: String,
+    // Where this call picked up, i.e. what an earlier attempt already saved
+    pub resumed_from: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    // Whether the remote temp file was removed after completing
+    pub remote_removed: bool,
+}
+
+// Payload of the `resumable-download-progress` event
+#[derive(Debug, Clone, Serialize)]
+pub struct ResumableDownloadProgress {
+    pub connection_id: String,
+    pub remote_path: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+// Resolves a remote path against the connection's tracked working directory,
+// since SFTP would otherwise resolve relative paths against the home directory
+pub(crate) fn resolve_remote_path(current_directory: &str, path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() || current_directory.is_empty() {
+        path.to_path_buf()
+    } else {
+        Path::new(current_directory).join(path)
+    }
+}
+
+pub(crate) fn would_block(e: &ssh2::Error) -> bool {
+    e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN)
+}
+
+#[derive(Debug, PartialEq)]
+enum UploadStage {
+    Opening,
+    Writing,
+    Closing,
+    Done,
+}
+
+struct UploadJob {
+    index: usize,
+    remote_path: PathBuf,
+    local: std::fs::File,
+    // The piece of the local file being sent, and how much of it has been
+    chunk: Vec<u8>,
+    chunk_written: usize,
+    local_eof: bool,
+    written: u64,
+    stage: UploadStage,
+    file: Option<File>,
+    // When the upload last got anywhere, for spotting one that has stalled
+    last_progress: Instant,
+}
+
+impl UploadJob {
+    fn new(index: usize, remote_path: PathBuf, local: std::fs::File) -> Self {
+        UploadJob {
+            index,
+            remote_path,
+            local,
+            chunk: Vec::new(),
+            chunk_written: 0,
+            local_eof: false,
+            written: 0,
+            stage: UploadStage::Opening,
+            file: None,
+            last_progress: Instant::now(),
+        }
+    }
+
+    // Moves the upload forward as far as it can go without blocking.
+    // Returns whether anything happened, so the caller knows when to back off.
+    fn advance(&mut self, sftp: &Sftp) -> Result<bool> {
+        match self.stage {
+            UploadStage::Opening => {
+                let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+                match sftp.open_mode(&self.remote_path, flags, 0o644, OpenType::File) {
+                    Ok(file) => {
+                        self.file = Some(file);
+                        self.stage = UploadStage::Writing;
+                        Ok(true)
+                    }
+                    Err(e) if would_block(&e) => Ok(false),
+                    Err(e) => Err(anyhow!("Failed to open remote file: {}", e)),
+                }
+            }
+            UploadStage::Writing => {
+                if self.chunk_written == self.chunk.len() {
+                    if self.local_eof {
+                        self.stage = UploadStage::Closing;
+                        return Ok(true);
+                    }
+                    self.chunk.resize(UPLOAD_CHUNK_SIZE, 0);
+                    let n = self.local.read(&mut self.chunk).context("Failed to read local file")?;
+                    self.chunk.truncate(n);
+                    self.chunk_written = 0;
+                    self.local_eof = n == 0;
+                    return Ok(true);
+                }
+
+                let file = self.file.as_mut().context("Remote file is not open")?;
+                // libssh2 splits the buffer into several outstanding SFTP writes, and
+                // on EAGAIN must be called again with the same data to collect the acks
+                match file.write(&self.chunk[self.chunk_written..]) {
+                    Ok(n) => {
+                        self.chunk_written += n;
+                        self.written += n as u64;
+                        Ok(n > 0)
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+                    Err(e) => Err(anyhow!("Failed to write remote file: {}", e)),
+                }
+            }
+            UploadStage::Closing => {
+                let file = self.file.as_mut().context("Remote file is not open")?;
+                match file.close() {
+                    Ok(()) => {
+                        self.file = None;
+                        self.stage = UploadStage::Done;
+                        Ok(true)
+                    }
+                    Err(e) if would_block(&e) => Ok(false),
+                    Err(e) => Err(anyhow!("Failed to close remote file: {}", e)),
+                }
+            }
+            UploadStage::Done => Ok(false),
+        }
+    }
+}
+
+// One SFTP channel uploading a single file at a time
+struct UploadLane {
+    sftp: Sftp,
+    job: Option<UploadJob>,
+}
+
+// SFTP channels and handles talk to the server when dropped, so that happens
+// under the connection's lock, with the session in blocking mode
+fn drop_under_lock<T>(connections: &ConnectionsStore, connection_id: &str, value: T) {
+    match get_client(connections, connection_id) {
+        Ok(client) => {
+            let _client = client.lock().unwrap_or_else(|e| e.into_inner());
+            drop(value);
+        }
+        // Nothing else can be using the session
+        Err(_) => drop(value),
+    }
+}
+
+// Uploads the files over several SFTP channels at once, driving them all in
+// non-blocking mode so that the round-trips for opening, writing and closing
+// different files overlap instead of being paid one file after another.
+// Files are read in chunks, so their size doesn't matter.
+//
+// As with streaming commands, the connection's lock is only held (and the
+// session only non-blocking) for one pass over the lanes at a time.
+fn upload_batch(
+    connections: &ConnectionsStore,
+    connection_id: &str,
+    files: Vec<FileTransfer>,
+    pipeline_depth: usize,
+    on_progress: &dyn Fn(BatchUploadProgress),
+) -> Result<BatchUploadResult> {
+    let started = Instant::now();
+
+    let (mut lanes, current_directory) = {
+        let client = get_client(connections, connection_id)?;
+        let client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+
+        let mut lanes = vec![UploadLane {
+            sftp: client.session.sftp().context("Failed to start SFTP subsystem")?,
+            job: None,
+        }];
+        // Extra lanes are best-effort: the server may refuse more channels
+        while lanes.len() < pipeline_depth {
+            match client.session.sftp() {
+                Ok(sftp) => lanes.push(UploadLane { sftp, job: None }),
+                Err(_) => break,
+            }
+        }
+
+        (lanes, client.current_directory.clone())
+    };
+
+    let files_total = files.len();
+    let mut results: Vec<FileUploadResult> = files.iter()
+        .map(|f| FileUploadResult {
+            local_path: f.local_path.clone(),
+            remote_path: f.remote_path.clone(),
+            success: false,
+            bytes_transferred: 0,
+            error: None,
+        })
+        .collect();
+    let mut sizes: Vec<u64> = files.iter()
+        .map(|f| std::fs::metadata(&f.local_path).map(|m| m.len()).unwrap_or(0))
+        .collect();
+    let mut bytes_total: u64 = sizes.iter().sum();
+    let mut bytes_done: u64 = 0;
+    let mut files_done = 0;
+    let mut pending: VecDeque<usize> = (0..files_total).collect();
+    let mut last_progress = Instant::now();
+
+    let progress = |index: usize, results: &[FileUploadResult], sizes: &[u64], file_done: bool,
+                    files_done: usize, bytes_done: u64, bytes_total: u64| {
+        on_progress(BatchUploadProgress {
+            connection_id: connection_id.to_string(),
+            file_index: index,
+            local_path: results[index].local_path.clone(),
+            remote_path: results[index].remote_path.clone(),
+            file_bytes_sent: results[index].bytes_transferred,
+            file_size: sizes[index],
+            file_done,
+            files_done,
+            files_total,
+            bytes_done,
+            bytes_total,
+        });
+    };
+
+    let outcome = (|| -> Result<()> {
+        loop {
+            // Hand queued files to idle lanes. Local open errors fail that file only.
+            for lane in lanes.iter_mut().filter(|l| l.job.is_none()) {
+                while let Some(index) = pending.pop_front() {
+                    match std::fs::File::open(&files[index].local_path) {
+                        Ok(local) => {
+                            let remote_path = resolve_remote_path(&current_directory, &files[index].remote_path);
+                            lane.job = Some(UploadJob::new(index, remote_path, local));
+                            break;
+                        }
+                        Err(e) => {
+                            results[index].error = Some(format!("Failed to read local file: {}", e));
+                            bytes_total -= sizes[index];
+                            files_done += 1;
+                            progress(index, &results, &sizes, true, files_done, bytes_done, bytes_total);
+                        }
+                    }
+                }
+            }
+
+            if lanes.iter().all(|l| l.job.is_none()) {
+                return Ok(());
+            }
+
+            let mut made_progress = false;
+            let mut finished = Vec::new();
+            {
+                let client = get_client(connections, connection_id)
+                    .context("Connection was closed during the upload")?;
+                let client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+                // Failed jobs may still hold an open handle, which is only
+                // closed once the session is blocking again
+                let mut failed = Vec::new();
+
+                client.session.set_blocking(false);
+                for lane in lanes.iter_mut() {
+                    let Some(job) = lane.job.as_mut() else { continue };
+
+                    let written_before = job.written;
+                    let outcome = loop {
+                        match job.advance(&lane.sftp) {
+                            Ok(true) if job.stage == UploadStage::Done => break Ok(()),
+                            Ok(true) => {
+                                made_progress = true;
+                                job.last_progress = Instant::now();
+                            }
+                            Ok(false) if job.last_progress.elapsed() >= UPLOAD_STALL_TIMEOUT => {
+                                break Err(Some(anyhow!(
+                                    "Upload stalled: no progress for {} s",
+                                    UPLOAD_STALL_TIMEOUT.as_secs()
+                                )));
+                            }
+                            Ok(false) => break Err(None),
+                            Err(e) => break Err(Some(e)),
+                        }
+                    };
+
+                    let sent = job.written - written_before;
+                    results[job.index].bytes_transferred += sent;
+                    bytes_done += sent;
+
+                    match outcome {
+                        Ok(()) => {
+                            made_progress = true;
+                            // The file may have changed size since it was stat'ed
+                            bytes_total = bytes_total - sizes[job.index] + job.written;
+                            sizes[job.index] = job.written;
+                            results[job.index].success = true;
+                            finished.push(job.index);
+                            lane.job = None;
+                        }
+                        Err(Some(e)) => {
+                            made_progress = true;
+                            results[job.index].error = Some(e.to_string());
+                            finished.push(job.index);
+                            failed.extend(lane.job.take());
+                        }
+                        Err(None) => {}
+                    }
+                }
+                client.session.set_blocking(true);
+                drop(failed);
+            }
+
+            for index in finished {
+                files_done += 1;
+                progress(index, &results, &sizes, true, files_done, bytes_done, bytes_total);
+            }
+
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                for job in lanes.iter().filter_map(|l| l.job.as_ref()) {
+                    progress(job.index, &results, &sizes, false, files_done, bytes_done, bytes_total);
+                }
+                last_progress = Instant::now();
+            }
+
+            if !made_progress {
+                thread::sleep(BATCH_POLL_INTERVAL);
+            }
+        }
+    })();
+    drop_under_lock(connections, connection_id, lanes);
+    outcome?;
+
+    let files_succeeded = results.iter().filter(|r| r.success).count();
+    Ok(BatchUploadResult {
+        files_total,
+        files_succeeded,
+        files_failed: files_total - files_succeeded,
+        bytes_transferred: bytes_done,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+        results,
+    })
+}
+
+// Uploads many (local, remote) file pairs, keeping up to `pipeline_depth`
+// files in flight. A failure on one file is reported in its result entry and
+// doesn't stop the rest of the batch. Passing a depth of 1 gives the plain
+// sequential behaviour, which is handy for comparing the two on slow links.
+#[tauri::command]
+pub async fn upload_files_batch(
+    app: AppHandle,
+    connection_id: String,
+    files: Vec<FileTransfer>,
+    pipeline_depth: Option<usize>,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<BatchUploadResult, errors::SSHError> {
+    let pipeline_depth = pipeline_depth
+        .unwrap_or(DEFAULT_PIPELINE_DEPTH)
+        .clamp(1, MAX_PIPELINE_DEPTH);
+
+    // Takes the connection's lock per step itself, so only needs to be off
+    // the async runtime's threads
+    let connections = connections.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let on_progress = |progress: BatchUploadProgress| {
+            let _ = app.emit("upload-batch-progress", progress);
+        };
+        upload_batch(&connections, &connection_id, files, pipeline_depth, &on_progress)
+            .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Batch upload failed: {}", e)))
+    })
+    .await
+    .map_err(|e| format!("Upload task failed: {}", e))?
+}
+
+// Appends the rest of the remote output to `local_path`, starting from however
+// much of it the local file already holds.
+//
+// The connection's lock is taken per chunk rather than for the whole
+// download, since these files can take hours to fetch over a bad link.
+fn download_resumable(
+    app: &AppHandle,
+    connections: &ConnectionsStore,
+    connection_id: &str,
+    output: &ResumableOutput,
+    local_path: &str,
+) -> Result<ResumableDownload> {
+    let client = get_client(connections, connection_id)?;
+    let remote_path = Path::new(&output.remote_path);
+
+    let mut local = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(local_path)
+        .context("Failed to open local file")?;
+    let resumed_from = local.metadata().context("Failed to read local file")?.len();
+    if resumed_from > output.size {
+        anyhow::bail!("The local file is larger than the remote output; remove it to start over");
+    }
+
+    let (sftp, mut remote) = {
+        let client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+        let sftp = client.session.sftp().context("Failed to start SFTP subsystem")?;
+
+        let stat = sftp.stat(remote_path).context("The remote output file is no longer there")?;
+        if stat.size != Some(output.size) {
+            anyhow::bail!("The remote output file changed size since the command ran");
+        }
+
+        let mut remote = sftp.open(remote_path).context("Failed to open remote output file")?;
+        remote.seek(SeekFrom::Start(resumed_from)).context("Failed to seek in remote output file")?;
+        (sftp, remote)
+    };
+
+    let mut bytes_done = resumed_from;
+    let mut last_progress = Instant::now();
+    let result = (|| -> Result<()> {
+        let mut buf = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+        while bytes_done < output.size {
+            let n = {
+                let _client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+                remote.read(&mut buf).context("Failed to read remote output file")?
+            };
+            if n == 0 {
+                anyhow::bail!("The remote output file ended early");
+            }
+
+            local.write_all(&buf[..n]).context("Failed to write local file")?;
+            bytes_done += n as u64;
+
+            if last_progress.elapsed() >= PROGRESS_INTERVAL || bytes_done == output.size {
+                let _ = app.emit("resumable-download-progress", ResumableDownloadProgress {
+                    connection_id: connection_id.to_string(),
+                    remote_path: output.remote_path.clone(),
+                    bytes_done,
+                    bytes_total: output.size,
+                });
+                last_progress = Instant::now();
+            }
+        }
+        local.sync_all().context("Failed to write local file")?;
+        Ok(())
+    })();
+
+    // The SFTP handles talk to the server when dropped, so do that under the lock
+    let client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    drop(remote);
+    result?;
+
+    // Only remove the server's copy once every byte is safely on disk here
+    let remote_removed = sftp.unlink(remote_path).is_ok();
+    drop(sftp);
+    drop(client);
+
+    Ok(ResumableDownload {
+        local_path: local_path.to_string(),
+        resumed_from,
+        bytes_done,
+        bytes_total: output.size,
+        remote_removed,
+    })
+}
+
+// Runs a command with its output (stdout and stderr together) written to a
+// temp file on the server instead of sent back, for outputs too large to fetch
+// in one go over an unreliable link. The returned handle is then passed to
+// download_resumable_output, as many times as it takes.
+#[tauri::command]
+pub async fn execute_to_resumable_file(
+    connection_id: String,
+    command: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ResumableOutput, errors::SSHError> {
+    with_client(&connections, &connection_id, move |client| {
+        let estimate = client.estimate_output_size(&command, true)
+            .map_err(|e| format!("Command execution failed: {}", e))?;
+        let remote_path = estimate.output_file
+            .ok_or_else(|| "Command execution failed: no output file was created".to_string())?;
+
+        Ok(ResumableOutput {
+            remote_path,
+            size: estimate.bytes,
+            lines: estimate.lines,
+            exit_status: estimate.exit_status,
+            success: estimate.success,
+        })
+    })
+    .await
+}
+
+// Downloads the output saved by execute_to_resumable_file to `local_path`.
+// If an earlier attempt was cut off, the partial local file is kept and the
+// download carries on from its end. The server's temp file is removed once
+// the download completes. Works on a new connection to the same server too.
+#[tauri::command]
+pub async fn download_resumable_output(
+    app: AppHandle,
+    connection_id: String,
+    output: ResumableOutput,
+    local_path: String,
+    connections: State<'_, ConnectionsStore>,
+) -> Result<ResumableDownload, errors::SSHError> {
+    let connections = connections.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        download_resumable(&app, &connections, &connection_id, &output, &local_path)
+            .map_err(|e| errors::SSHError::from_anyhow(&e, format!("Download failed: {}", e)))
+    })
+    .await
+    .map_err(|e| format!("Download task failed: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    // Not a pass/fail test: prints how long a batch of small files takes
+    // sequentially and pipelined. The gap grows with the link's latency, so
+    // point it at a distant server for numbers that mean anything.
+    #[test]
+    #[ignore = "benchmark; needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn benchmark_pipelined_upload() {
+        let config = test_support::test_config();
+        let mut client = test_support::connect(&config);
+        let remote_dir = test_support::run(&mut client, "mktemp -d");
+        let connections = test_support::store(vec![("bench", client)]);
+
+        let local_dir = std::env::temp_dir().join(format!("aetherssh-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&local_dir).unwrap();
+        let files: Vec<(String, String)> = (0..50)
+            .map(|i| {
+                let local = local_dir.join(format!("file-{}", i));
+                std::fs::write(&local, vec![b'x'; 16 * 1024]).unwrap();
+                (local.to_string_lossy().into_owned(), format!("{}/file-{}", remote_dir, i))
+            })
+            .collect();
+
+        for depth in [1, DEFAULT_PIPELINE_DEPTH, MAX_PIPELINE_DEPTH] {
+            let batch = files.iter()
+                .map(|(local, remote)| FileTransfer { local_path: local.clone(), remote_path: remote.clone() })
+                .collect();
+            let result = upload_batch(&connections, "bench", batch, depth, &|_| {}).unwrap();
+            assert_eq!(result.files_succeeded, files.len(), "{:?}", result.results);
+            println!("depth {}: {} files in {} ms", depth, result.files_total, result.elapsed_ms);
+        }
+
+        let client = get_client(&connections, "bench").unwrap();
+        test_support::run(&mut client.lock().unwrap(), &format!("rm -r {}", remote_dir));
+        std::fs::remove_dir_all(&local_dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd"]
+    fn uploads_files_larger_than_a_chunk() {
+        let config = test_support::test_config();
+        let mut client = test_support::connect(&config);
+        let remote = format!("{}/big", test_support::run(&mut client, "mktemp -d"));
+        let connections = test_support::store(vec![("big", client)]);
+
+        let local = std::env::temp_dir().join(format!("aetherssh-big-{}", std::process::id()));
+        let data: Vec<u8> = (0..UPLOAD_CHUNK_SIZE * 3 + 17).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&local, &data).unwrap();
+
+        let batch = vec![FileTransfer { local_path: local.to_string_lossy().into_owned(), remote_path: remote.clone() }];
+        let result = upload_batch(&connections, "big", batch, 2, &|_| {}).unwrap();
+        assert_eq!(result.files_succeeded, 1, "{:?}", result.results);
+        assert_eq!(result.bytes_transferred, data.len() as u64);
+
+        let client = get_client(&connections, "big").unwrap();
+        let mut client = client.lock().unwrap();
+        assert_eq!(test_support::run(&mut client, &format!("wc -c < {}", remote)), data.len().to_string());
+        test_support::run(&mut client, &format!("rm -r \"$(dirname {})\"", remote));
+        std::fs::remove_file(&local).unwrap();
+    }
+}
@@ -0,0 +1,834 @@
+// Port forwarding over an existing connection
+//
+// Local tunnels listen on 127.0.0.1 and carry every connection they accept to
+// a host reachable from the server, like `ssh -L`. Dynamic tunnels do the
+// same as a SOCKS5 proxy, with each client choosing its own destination, like
+// `ssh -D`. Remote tunnels have the server listen instead and carry its
+// connections back to a host reachable from here, like `ssh -R`. Tunnels live
+// in their own store so they can be closed by id alone, and end when their
+// connection is disconnected.
+
+use crate::transfer::would_block;
+use crate::{errors, find_client, get_client, with_client, ConnectionsStore, SharedClient};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use ssh2::{Channel, Listener};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::State;
+
+// How long a tunnel's thread backs off when no connection had data to move
+pub(crate) const TUNNEL_POLL_INTERVAL: Duration = Duration::from_millis(5);
+// Data held per direction before we stop reading from the faster side
+const MAX_BUFFERED: usize = 64 * 1024;
+// How long a SOCKS client gets to finish its handshake
+const SOCKS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// SOCKS5 protocol values (RFC 1928)
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const SOCKS_CMD_CONNECT: u8 = 1;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+const SOCKS_REPLY_SUCCEEDED: u8 = 0;
+const SOCKS_REPLY_GENERAL_FAILURE: u8 = 1;
+const SOCKS_REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const SOCKS_REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+static NEXT_TUNNEL_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelKind {
+    Local,
+    Remote,
+    Dynamic,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelInfo {
+    pub tunnel_id: String,
+    pub connection_id: String,
+    pub kind: TunnelKind,
+    // Where the tunnel listens, e.g. "127.0.0.1:5432"
+    pub bind_address: String,
+    // Where accepted connections are carried to
+    pub target: String,
+}
+
+// Updated by the tunnel's thread as it goes
+#[derive(Default)]
+pub(crate) struct TunnelCounters {
+    active_connections: AtomicUsize,
+    total_connections: AtomicU64,
+    // Towards the forward's target
+    bytes_sent: AtomicU64,
+    // Back from the target
+    bytes_received: AtomicU64,
+}
+
+// A tunnel as shown by list_forwards and forward_stats
+#[derive(Debug, Clone, Serialize)]
+pub struct ForwardStatus {
+    #[serde(flatten)]
+    pub info: TunnelInfo,
+    pub active_connections: usize,
+    pub total_connections: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+pub struct TunnelHandle {
+    info: TunnelInfo,
+    stop: Arc<AtomicBool>,
+    counters: Arc<TunnelCounters>,
+}
+
+impl TunnelHandle {
+    fn status(&self) -> ForwardStatus {
+        ForwardStatus {
+            info: self.info.clone(),
+            active_connections: self.counters.active_connections.load(Ordering::Relaxed),
+            total_connections: self.counters.total_connections.load(Ordering::Relaxed),
+            bytes_sent: self.counters.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.counters.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Open tunnels, keyed by tunnel id
+pub type TunnelsStore = Arc<Mutex<HashMap<String, TunnelHandle>>>;
+
+// A local client waiting for its channel to be opened
+struct PendingOpen {
+    socket: TcpStream,
+    host: String,
+    port: u16,
+    origin: SocketAddr,
+    // SOCKS clients also wait on a reply saying whether that worked
+    socks: bool,
+}
+
+// Where a tunnel's new connections come from
+enum Acceptor {
+    Local { listener: TcpListener, remote_host: String, remote_port: u16 },
+    Remote { listener: Listener, local_host: String, local_port: u16 },
+    // Handshakes run on their own threads, which hand the finished requests
+    // back through the channel
+    Dynamic {
+        listener: TcpListener,
+        requests: mpsc::Receiver<PendingOpen>,
+        sender: mpsc::Sender<PendingOpen>,
+    },
+}
+
+fn send_socks_reply(socket: &mut TcpStream, reply: u8) -> io::Result<()> {
+    // The bound address is optional information, so it's left as 0.0.0.0:0
+    socket.write_all(&[SOCKS_VERSION, reply, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+}
+
+// Reads a SOCKS5 CONNECT request, answering the parts of the handshake that
+// don't involve the server. Returns the destination, or None once the client
+// has been told why it was refused.
+fn read_socks_request(socket: &mut TcpStream) -> io::Result<Option<(String, u16)>> {
+    let mut greeting = [0u8; 2];
+    socket.read_exact(&mut greeting)?;
+    if greeting[0] != SOCKS_VERSION {
+        return Ok(None);
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    socket.read_exact(&mut methods)?;
+    if !methods.contains(&SOCKS_NO_AUTH) {
+        socket.write_all(&[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHODS])?;
+        return Ok(None);
+    }
+    socket.write_all(&[SOCKS_VERSION, SOCKS_NO_AUTH])?;
+
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request)?;
+    if request[1] != SOCKS_CMD_CONNECT {
+        // BIND and UDP ASSOCIATE have no equivalent over a direct-tcpip channel
+        send_socks_reply(socket, SOCKS_REPLY_COMMAND_NOT_SUPPORTED)?;
+        return Ok(None);
+    }
+
+    let host = match request[3] {
+        SOCKS_ATYP_IPV4 => {
+            let mut addr = [0u8; 4];
+            socket.read_exact(&mut addr)?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        SOCKS_ATYP_IPV6 => {
+            let mut addr = [0u8; 16];
+            socket.read_exact(&mut addr)?;
+            Ipv6Addr::from(addr).to_string()
+        }
+        SOCKS_ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            socket.read_exact(&mut len)?;
+            let mut name = vec![0u8; len[0] as usize];
+            socket.read_exact(&mut name)?;
+            // Resolved by the server, like ssh -D does
+            String::from_utf8_lossy(&name).into_owned()
+        }
+        _ => {
+            send_socks_reply(socket, SOCKS_REPLY_ADDRESS_TYPE_NOT_SUPPORTED)?;
+            return Ok(None);
+        }
+    };
+
+    let mut port = [0u8; 2];
+    socket.read_exact(&mut port)?;
+    Ok(Some((host, u16::from_be_bytes(port))))
+}
+
+// Runs on its own thread per SOCKS client, so a slow client can't hold up
+// the rest of the tunnel
+fn socks_handshake(mut socket: TcpStream, sender: mpsc::Sender<PendingOpen>) {
+    let result = (|| -> io::Result<Option<PendingOpen>> {
+        // Accepted sockets may inherit the listener's non-blocking mode
+        socket.set_nonblocking(false)?;
+        socket.set_read_timeout(Some(SOCKS_HANDSHAKE_TIMEOUT))?;
+        let origin = socket.peer_addr()?;
+
+        let Some((host, port)) = read_socks_request(&mut socket)? else { return Ok(None) };
+        socket.set_read_timeout(None)?;
+        Ok(Some(PendingOpen { socket, host, port, origin, socks: true }))
+    })();
+
+    if let Ok(Some(open)) = result {
+        let _ = sender.send(open);
+    }
+}
+
+// Takes every connection waiting on a non-blocking listener
+fn accept_all(listener: &TcpListener) -> Result<Vec<(TcpStream, SocketAddr)>> {
+    let mut accepted = Vec::new();
+    loop {
+        match listener.accept() {
+            Ok(pair) => accepted.push(pair),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(accepted),
+            Err(e) => return Err(e).context("Failed to accept a connection"),
+        }
+    }
+}
+
+// One forwarded connection: a local socket joined to an SSH channel
+pub(crate) struct Forwarded {
+    socket: TcpStream,
+    channel: Channel,
+    to_channel: Vec<u8>,
+    to_socket: Vec<u8>,
+    socket_eof: bool,
+    channel_eof: bool,
+    eof_sent: bool,
+    socket_shut: bool,
+}
+
+impl Forwarded {
+    pub(crate) fn new(socket: TcpStream, channel: Channel) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Forwarded {
+            socket,
+            channel,
+            to_channel: Vec::new(),
+            to_socket: Vec::new(),
+            socket_eof: false,
+            channel_eof: false,
+            eof_sent: false,
+            socket_shut: false,
+        })
+    }
+
+    // Moves whatever data is ready in both directions, passing on EOF once a
+    // side's data has all been delivered. Needs the session in non-blocking
+    // mode. Returns whether anything moved.
+    pub(crate) fn pump(&mut self, counters: &TunnelCounters) -> io::Result<bool> {
+        let mut moved = false;
+        let mut chunk = [0u8; 16384];
+
+        if !self.socket_eof && self.to_channel.len() < MAX_BUFFERED {
+            match self.socket.read(&mut chunk) {
+                Ok(0) => self.socket_eof = true,
+                Ok(n) => {
+                    self.to_channel.extend_from_slice(&chunk[..n]);
+                    moved = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let sent = write_ready(&mut self.channel, &mut self.to_channel)?;
+        counters.bytes_sent.fetch_add(sent as u64, Ordering::Relaxed);
+        moved |= sent > 0;
+        if self.socket_eof && self.to_channel.is_empty() && !self.eof_sent {
+            match self.channel.send_eof() {
+                Ok(()) => self.eof_sent = true,
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if !self.channel_eof && self.to_socket.len() < MAX_BUFFERED {
+            match self.channel.read(&mut chunk) {
+                Ok(0) => self.channel_eof = true,
+                Ok(n) => {
+                    self.to_socket.extend_from_slice(&chunk[..n]);
+                    moved = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let received = write_ready(&mut self.socket, &mut self.to_socket)?;
+        counters.bytes_received.fetch_add(received as u64, Ordering::Relaxed);
+        moved |= received > 0;
+        if self.channel_eof && self.to_socket.is_empty() && !self.socket_shut {
+            let _ = self.socket.shutdown(Shutdown::Write);
+            self.socket_shut = true;
+        }
+
+        Ok(moved)
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.eof_sent && self.socket_shut
+    }
+}
+
+// Writes as much of `buf` as the stream takes without blocking, returning
+// how much that was
+fn write_ready(stream: &mut impl Write, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut written = 0;
+    while written < buf.len() {
+        match stream.write(&buf[written..]) {
+            Ok(0) => break,
+            Ok(n) => written += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e),
+        }
+    }
+    buf.drain(..written);
+    Ok(written)
+}
+
+// Accepts new connections and moves data for all open ones, once. The
+// connection's lock is held (and the session non-blocking) only for the pass.
+fn pump_tunnel(
+    client: &SharedClient,
+    acceptor: &mut Acceptor,
+    forwarded: &mut Vec<Forwarded>,
+    counters: &TunnelCounters,
+) -> Result<bool> {
+    let mut pending = Vec::new();
+    match acceptor {
+        Acceptor::Local { listener, remote_host, remote_port } => {
+            for (socket, origin) in accept_all(listener)? {
+                pending.push(PendingOpen {
+                    socket,
+                    host: remote_host.clone(),
+                    port: *remote_port,
+                    origin,
+                    socks: false,
+                });
+            }
+        }
+        Acceptor::Dynamic { listener, requests, sender } => {
+            for (socket, _) in accept_all(listener)? {
+                let sender = sender.clone();
+                thread::spawn(move || socks_handshake(socket, sender));
+            }
+            pending.extend(requests.try_iter());
+        }
+        Acceptor::Remote { .. } => {}
+    }
+
+    let client = client.lock().map_err(|e| anyhow!("Lock error: {}", e))?;
+    let mut moved = !pending.is_empty();
+
+    for mut open in pending {
+        let origin = open.origin.ip().to_string();
+        let channel = client.session.channel_direct_tcpip(&open.host, open.port, Some((&origin, open.origin.port())));
+
+        if open.socks {
+            let reply = if channel.is_ok() { SOCKS_REPLY_SUCCEEDED } else { SOCKS_REPLY_GENERAL_FAILURE };
+            if send_socks_reply(&mut open.socket, reply).is_err() {
+                continue;
+            }
+        }
+
+        // If the server refused the channel, dropping the socket closes it,
+        // which the local client sees as a failed connection
+        if let Ok(channel) = channel {
+            forwarded.push(Forwarded::new(open.socket, channel)?);
+            counters.total_connections.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let mut finished = Vec::new();
+    client.session.set_blocking(false);
+    let result = (|| -> io::Result<()> {
+        if let Acceptor::Remote { listener, local_host, local_port } = acceptor {
+            loop {
+                match listener.accept() {
+                    Ok(channel) => {
+                        moved = true;
+                        match TcpStream::connect((local_host.as_str(), *local_port)) {
+                            Ok(socket) => {
+                                forwarded.push(Forwarded::new(socket, channel)?);
+                                counters.total_connections.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(_) => finished.push(channel),
+                        }
+                    }
+                    Err(e) if would_block(&e) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < forwarded.len() {
+            match forwarded[i].pump(counters) {
+                Ok(m) if !forwarded[i].is_done() => {
+                    moved |= m;
+                    i += 1;
+                }
+                // Finished, or one side failed: either way this one is over
+                _ => {
+                    moved = true;
+                    finished.push(forwarded.swap_remove(i).channel);
+                }
+            }
+        }
+        Ok(())
+    })();
+    client.session.set_blocking(true);
+
+    counters.active_connections.store(forwarded.len(), Ordering::Relaxed);
+
+    // Closing talks to the server, so it happens with the session blocking again
+    for mut channel in finished {
+        let _ = channel.close();
+    }
+    result?;
+
+    Ok(moved)
+}
+
+fn run_tunnel(
+    connections: ConnectionsStore,
+    tunnels: TunnelsStore,
+    info: TunnelInfo,
+    mut acceptor: Acceptor,
+    stop: Arc<AtomicBool>,
+    counters: Arc<TunnelCounters>,
+) {
+    let mut forwarded = Vec::new();
+
+    while !stop.load(Ordering::SeqCst) {
+        // The connection was disconnected
+        let Ok(client) = get_client(&connections, &info.connection_id) else { break };
+
+        match pump_tunnel(&client, &mut acceptor, &mut forwarded, &counters) {
+            Ok(true) => {}
+            Ok(false) => thread::sleep(TUNNEL_POLL_INTERVAL),
+            Err(_) => break,
+        }
+    }
+
+    // Channels and the remote listener notify the server when dropped, which
+    // only works while nothing else has the session in non-blocking mode
+    if let Ok(client) = get_client(&connections, &info.connection_id) {
+        if let Ok(_client) = client.lock() {
+            for f in forwarded.iter_mut() {
+                let _ = f.channel.close();
+            }
+            drop(forwarded);
+            drop(acceptor);
+        }
+    }
+
+    if let Ok(mut tunnels) = tunnels.lock() {
+        tunnels.remove(&info.tunnel_id);
+    }
+}
+
+fn start_tunnel(
+    connections: &ConnectionsStore,
+    tunnels: &TunnelsStore,
+    connection_id: String,
+    kind: TunnelKind,
+    bind_address: String,
+    target: String,
+    acceptor: Acceptor,
+) -> Result<TunnelInfo, errors::SSHError> {
+    let info = TunnelInfo {
+        tunnel_id: format!("tunnel-{}", NEXT_TUNNEL_ID.fetch_add(1, Ordering::SeqCst)),
+        connection_id,
+        kind,
+        bind_address,
+        target,
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let counters = Arc::new(TunnelCounters::default());
+
+    tunnels.lock().map_err(|e| format!("Lock error: {}", e))?
+        .insert(info.tunnel_id.clone(), TunnelHandle {
+            info: info.clone(),
+            stop: stop.clone(),
+            counters: counters.clone(),
+        });
+
+    let (connections, tunnels, thread_info) = (connections.clone(), tunnels.clone(), info.clone());
+    thread::spawn(move || run_tunnel(connections, tunnels, thread_info, acceptor, stop, counters));
+
+    Ok(info)
+}
+
+fn bind_local(local_port: u16) -> Result<TcpListener, errors::SSHError> {
+    let listener = TcpListener::bind(("127.0.0.1", local_port)).map_err(|e| match e.kind() {
+        ErrorKind::AddrInUse => format!("Local port {} is already in use", local_port),
+        _ => format!("Failed to listen on local port {}: {}", local_port, e),
+    })?;
+    listener.set_nonblocking(true)
+        .map_err(|e| format!("Failed to listen on local port {}: {}", local_port, e))?;
+    Ok(listener)
+}
+
+// Stops every tunnel of a connection, for disconnect_ssh
+pub fn close_connection_tunnels(tunnels: &TunnelsStore, connection_id: &str) -> Result<(), String> {
+    let mut tunnels = tunnels.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    tunnels.retain(|_, tunnel| {
+        let keep = tunnel.info.connection_id != connection_id;
+        if !keep {
+            tunnel.stop.store(true, Ordering::SeqCst);
+        }
+        keep
+    });
+    Ok(())
+}
+
+// Listens on 127.0.0.1:local_port and forwards each connection to
+// remote_host:remote_port as seen from the server. A local_port of 0 picks a
+// free port, reported in the result's bind_address.
+#[tauri::command]
+pub async fn open_local_tunnel(
+    connection_id: String,
+    local_port: u16,
+    remote_host: String,
+    remote_port: u16,
+    connections: State<'_, ConnectionsStore>,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<TunnelInfo, errors::SSHError> {
+    open_local(&connections, &tunnels, connection_id, local_port, remote_host, remote_port)
+}
+
+fn open_local(
+    connections: &ConnectionsStore,
+    tunnels: &TunnelsStore,
+    connection_id: String,
+    local_port: u16,
+    remote_host: String,
+    remote_port: u16,
+) -> Result<TunnelInfo, errors::SSHError> {
+    find_client(connections, &connection_id)?;
+
+    let listener = bind_local(local_port)?;
+    let bind_address = listener.local_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_else(|_| format!("127.0.0.1:{}", local_port));
+    let target = format!("{}:{}", remote_host, remote_port);
+
+    start_tunnel(
+        connections,
+        tunnels,
+        connection_id,
+        TunnelKind::Local,
+        bind_address,
+        target,
+        Acceptor::Local { listener, remote_host, remote_port },
+    )
+}
+
+// Same as open_local_tunnel, under the name the forwarding UI uses. The
+// returned tunnel_id is the forward_id for stop_forward.
+#[tauri::command]
+pub async fn start_local_forward(
+    connection_id: String,
+    local_port: u16,
+    remote_host: String,
+    remote_port: u16,
+    connections: State<'_, ConnectionsStore>,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<TunnelInfo, errors::SSHError> {
+    open_local(&connections, &tunnels, connection_id, local_port, remote_host, remote_port)
+}
+
+// Runs a SOCKS5 proxy on 127.0.0.1:local_port whose connections are made
+// from the server, so a browser or other tool can be routed through it. The
+// returned tunnel_id is the forward's id, for close_tunnel.
+#[tauri::command]
+pub async fn start_dynamic_forward(
+    connection_id: String,
+    local_port: u16,
+    connections: State<'_, ConnectionsStore>,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<TunnelInfo, errors::SSHError> {
+    find_client(&connections, &connection_id)?;
+
+    let listener = bind_local(local_port)?;
+    let bind_address = listener.local_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_else(|_| format!("127.0.0.1:{}", local_port));
+    let (sender, requests) = mpsc::channel();
+
+    start_tunnel(
+        &connections,
+        &tunnels,
+        connection_id,
+        TunnelKind::Dynamic,
+        bind_address,
+        "SOCKS5".to_string(),
+        Acceptor::Dynamic { listener, requests, sender },
+    )
+}
+
+// Has the server listen on remote_port (on bind_address, or its default
+// interfaces) and forwards each connection to local_host:local_port from this
+// machine. A remote_port of 0 lets the server pick, reported in bind_address.
+#[tauri::command]
+pub async fn open_remote_tunnel(
+    connection_id: String,
+    remote_port: u16,
+    local_host: String,
+    local_port: u16,
+    bind_address: Option<String>,
+    connections: State<'_, ConnectionsStore>,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<TunnelInfo, errors::SSHError> {
+    let requested_address = bind_address.clone();
+    let (listener, bound_port) = with_client(&connections, &connection_id, move |client| {
+        client.session.channel_forward_listen(remote_port, requested_address.as_deref(), None)
+            .map_err(|e| format!("Server refused to listen on port {}: {}", remote_port, e))
+    })
+    .await?;
+
+    let bind_address = format!("{}:{}", bind_address.as_deref().unwrap_or("0.0.0.0"), bound_port);
+    let target = format!("{}:{}", local_host, local_port);
+
+    start_tunnel(
+        &connections,
+        &tunnels,
+        connection_id,
+        TunnelKind::Remote,
+        bind_address,
+        target,
+        Acceptor::Remote { listener, local_host, local_port },
+    )
+}
+
+// Stops a tunnel and closes its forwarded connections. Returns false if no
+// tunnel has that id.
+#[tauri::command]
+pub async fn close_tunnel(
+    tunnel_id: String,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<bool, errors::SSHError> {
+    close(&tunnels, &tunnel_id)
+}
+
+fn close(tunnels: &TunnelsStore, tunnel_id: &str) -> Result<bool, errors::SSHError> {
+    let mut tunnels = tunnels.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    match tunnels.remove(tunnel_id) {
+        Some(tunnel) => {
+            tunnel.stop.store(true, Ordering::SeqCst);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Same as close_tunnel, for forwards started with start_local_forward or
+// start_dynamic_forward
+#[tauri::command]
+pub async fn stop_forward(
+    forward_id: String,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<bool, errors::SSHError> {
+    close(&tunnels, &forward_id)
+}
+
+#[tauri::command]
+pub async fn list_tunnels(
+    connection_id: String,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<Vec<TunnelInfo>, errors::SSHError> {
+    let tunnels = tunnels.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    let mut list: Vec<TunnelInfo> = tunnels.values()
+        .filter(|t| t.info.connection_id == connection_id)
+        .map(|t| t.info.clone())
+        .collect();
+    list.sort_by(|a, b| a.tunnel_id.cmp(&b.tunnel_id));
+    Ok(list)
+}
+
+// Every open forward with its traffic so far, for one connection or (without
+// a connection id) for all of them
+#[tauri::command]
+pub async fn list_forwards(
+    connection_id: Option<String>,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<Vec<ForwardStatus>, errors::SSHError> {
+    let tunnels = tunnels.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    let mut list: Vec<ForwardStatus> = tunnels.values()
+        .filter(|t| connection_id.as_ref().is_none_or(|id| &t.info.connection_id == id))
+        .map(TunnelHandle::status)
+        .collect();
+    list.sort_by(|a, b| a.info.tunnel_id.cmp(&b.info.tunnel_id));
+    Ok(list)
+}
+
+#[tauri::command]
+pub async fn forward_stats(
+    forward_id: String,
+    tunnels: State<'_, TunnelsStore>,
+) -> Result<ForwardStatus, errors::SSHError> {
+    let tunnels = tunnels.lock().map_err(|e| format!("Lock error: {}", e))?;
+
+    tunnels.get(&forward_id)
+        .map(TunnelHandle::status)
+        .ok_or_else(|| errors::SSHError::new(errors::SSHErrorKind::InvalidArgument, "Forward not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    // Feeds `sent` to read_socks_request over a loopback socket, returning
+    // what it parsed and every byte it answered with
+    fn socks_exchange(sent: &[u8]) -> (Option<(String, u16)>, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        client.write_all(sent).unwrap();
+        let request = read_socks_request(&mut server).unwrap();
+        // Not closed yet: with refused requests' bytes unread, that would
+        // reset the connection before the client has read the answer
+        server.shutdown(Shutdown::Write).unwrap();
+
+        let mut answered = Vec::new();
+        client.read_to_end(&mut answered).unwrap();
+        (request, answered)
+    }
+
+    fn connect_request(address: &[u8], port: u16) -> Vec<u8> {
+        let mut sent = vec![SOCKS_VERSION, 1, SOCKS_NO_AUTH, SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+        sent.extend(address);
+        sent.extend(port.to_be_bytes());
+        sent
+    }
+
+    #[test]
+    fn socks5_domain_is_left_for_the_server_to_resolve() {
+        let mut address = vec![SOCKS_ATYP_DOMAIN, 11];
+        address.extend(b"example.com");
+        let (request, answered) = socks_exchange(&connect_request(&address, 80));
+        assert_eq!(request, Some(("example.com".to_string(), 80)));
+        assert_eq!(answered, [SOCKS_VERSION, SOCKS_NO_AUTH]);
+    }
+
+    #[test]
+    fn socks5_ip_addresses() {
+        let mut ipv4 = vec![SOCKS_ATYP_IPV4];
+        ipv4.extend([10, 0, 0, 1]);
+        assert_eq!(socks_exchange(&connect_request(&ipv4, 22)).0, Some(("10.0.0.1".to_string(), 22)));
+
+        let mut ipv6 = vec![SOCKS_ATYP_IPV6];
+        ipv6.extend(Ipv6Addr::LOCALHOST.octets());
+        assert_eq!(socks_exchange(&connect_request(&ipv6, 443)).0, Some(("::1".to_string(), 443)));
+    }
+
+    #[test]
+    fn socks4a_is_refused() {
+        // SOCKS4a CONNECT to example.com:80, user "u"
+        let mut sent = vec![4, 1, 0, 80, 0, 0, 0, 1, b'u', 0];
+        sent.extend(b"example.com\0");
+        assert_eq!(socks_exchange(&sent), (None, Vec::new()));
+    }
+
+    #[test]
+    fn socks5_without_no_auth_is_refused() {
+        let (request, answered) = socks_exchange(&[SOCKS_VERSION, 1, 2]);
+        assert_eq!(request, None);
+        assert_eq!(answered, [SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHODS]);
+    }
+
+    #[test]
+    fn socks5_bind_and_unknown_address_types_are_refused() {
+        let mut bind = connect_request(&[SOCKS_ATYP_IPV4, 127, 0, 0, 1], 80);
+        bind[4] = 2;
+        let (request, answered) = socks_exchange(&bind);
+        assert_eq!(request, None);
+        assert_eq!(answered[2..4], [SOCKS_VERSION, SOCKS_REPLY_COMMAND_NOT_SUPPORTED]);
+
+        let (request, answered) = socks_exchange(&connect_request(&[9], 80));
+        assert_eq!(request, None);
+        assert_eq!(answered[2..4], [SOCKS_VERSION, SOCKS_REPLY_ADDRESS_TYPE_NOT_SUPPORTED]);
+    }
+
+    // The target is an echo server on this machine, so the sshd has to run
+    // here too for the test to reach it
+    #[test]
+    #[ignore = "needs AETHERSSH_TEST_* pointing at an sshd on this machine"]
+    fn local_forward_round_trip() {
+        let echo = TcpListener::bind("127.0.0.1:0").unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for socket in echo.incoming() {
+                let mut socket = socket.unwrap();
+                let mut reader = socket.try_clone().unwrap();
+                thread::spawn(move || io::copy(&mut reader, &mut socket));
+            }
+        });
+
+        let config = test_support::test_config();
+        let connections = test_support::store(vec![("conn", test_support::connect(&config))]);
+        let tunnels = TunnelsStore::default();
+        let info = open_local(&connections, &tunnels, "conn".to_string(), 0, "127.0.0.1".to_string(), echo_port).unwrap();
+
+        // Two connections at once, each getting its own bytes back
+        let mut sockets: Vec<TcpStream> = (0..2).map(|_| TcpStream::connect(&info.bind_address).unwrap()).collect();
+        for (i, socket) in sockets.iter_mut().enumerate() {
+            socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let sent: Vec<u8> = (0..64 * 1024).map(|n| (n * (i + 1) % 251) as u8).collect();
+            socket.write_all(&sent).unwrap();
+            let mut received = vec![0; sent.len()];
+            socket.read_exact(&mut received).unwrap();
+            assert!(received == sent, "connection {} got different bytes back", i);
+        }
+
+        let counters = tunnels.lock().unwrap()[&info.tunnel_id].counters.clone();
+        assert!(counters.bytes_sent.load(Ordering::SeqCst) >= 128 * 1024);
+
+        assert!(close(&tunnels, &info.tunnel_id).unwrap());
+        assert!(!close(&tunnels, &info.tunnel_id).unwrap());
+        // The listener goes with the tunnel's thread
+        thread::sleep(Duration::from_millis(500));
+        assert!(TcpStream::connect(&info.bind_address).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 import React, { useState, useRef, useEffect } from 'react';
 import {Server,Key,User,Lock,ArrowLeft,Wifi,WifiOff} from 'lucide-react';
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 
 interface SSHConnectionConfig {
   host: string;
@@ -8,13 +9,52 @@ interface SSHConnectionConfig {
   username: string;
   password?: string;
   private_key_path?: string;
+  private_key_contents?: string;
   passphrase?: string;
+  accept_new_host_key?: boolean;
+  connect_timeout_ms?: number;
+  command_timeout_ms?: number;
+  keepalive_interval_secs?: number;
+  // Bastion the connection goes through, which may itself have a jump_host
+  jump_host?: SSHConnectionConfig;
+  initial_directory?: string;
+  max_sessions_wait_ms?: number;
+  auto_reconnect?: boolean;
+  // Per stream; 0 for no limit
+  max_output_bytes?: number;
+}
+
+// Structured failure from connect_ssh, execute_ssh_command and sudo_execute
+interface SSHError {
+  code: string;
+  message: string;
+  // For "auth_failed"
+  methods_tried?: string[];
+  methods_offered?: string[];
+}
+
+// Commands reject with an SSHError; a plain string is kept for anything else
+const errorMessage = (error: unknown) =>
+  typeof error === 'object' && error !== null && 'message' in error ? (error as SSHError).message : String(error);
+
+interface HostKeyInfo {
+  host: string;
+  port: number;
+  key_type: string;
+  fingerprint: string;
 }
 
 interface SSHConnectionResponse {
   success: boolean;
   message: string;
   connection_id?: string;
+  // e.g. "alice@example.com:22", or the profile's name
+  label?: string;
+  error_code?: string;
+  host_key?: HostKeyInfo;
+  // host_key was unknown and has just been recorded
+  first_seen?: boolean;
+  error?: SSHError | null;
 }
 
 interface CommandResult {
@@ -22,6 +62,27 @@ interface CommandResult {
   stderr: string;
   exit_status: number;
   success: boolean;
+  cancelled?: boolean;
+  timed_out?: boolean;
+  disconnected?: boolean;
+  error_code?: string;
+  // Session env variables the command ran with
+  env_names?: string[];
+  // Output past max_output_bytes was dropped
+  truncated?: boolean;
+  dropped_bytes?: number;
+  // Signal that killed the command, e.g. "KILL"
+  exit_signal?: string | null;
+  // The ulimit that ended the command
+  limit_exceeded?: "cpu_time" | "file_size" | null;
+  error?: SSHError | null;
+}
+
+interface KeyboardInteractiveEvent {
+  auth_id: string;
+  username: string;
+  instructions: string;
+  prompts: { text: string; echo: boolean }[];
 }
 
 interface TerminalHistoryItem {
@@ -73,6 +134,13 @@ const SSHInterface: React.FC = () => {
   const handleConnect = async () => {
     setIsLoading(true);
 
+    // Servers using keyboard-interactive auth (e.g. OTP codes) prompt during connect
+    const unlistenPrompts = await listen<KeyboardInteractiveEvent>('ssh-keyboard-interactive', async (event) => {
+      const { auth_id, instructions, prompts } = event.payload;
+      const responses = prompts.map(p => window.prompt(instructions ? `${instructions}\n${p.text}` : p.text) ?? '');
+      await invoke('submit_keyboard_interactive', { authId: auth_id, responses });
+    });
+
     try {
       const config: SSHConnectionConfig = {
         host: connectionConfig.host,
@@ -87,7 +155,21 @@ const SSHInterface: React.FC = () => {
         )
       };
 
-      const response: SSHConnectionResponse = await invoke('connect_ssh', { config });
+      let response: SSHConnectionResponse = await invoke('connect_ssh', { config });
+
+      if (response.error_code === 'host_key_unknown' && response.host_key) {
+        const { host, port, key_type, fingerprint } = response.host_key;
+        // The unknown key may belong to one of the bastions rather than the target
+        let hop: SSHConnectionConfig | undefined = config;
+        while (hop && !(hop.host === host && hop.port === port)) {
+          hop = hop.jump_host;
+        }
+        if (confirm(`The authenticity of host '${host}' can't be established.\n` +
+                    `${key_type} key fingerprint is ${fingerprint}.\n\nTrust this host and save its key?`)) {
+          await invoke('trust_host_key', { host, port, fingerprint, jumpHost: hop?.jump_host });
+          response = await invoke('connect_ssh', { config });
+        }
+      }
 
       if (response.success && response.connection_id) {
         setConnectionId(response.connection_id);
@@ -105,12 +187,15 @@ const SSHInterface: React.FC = () => {
           },
           timestamp: new Date()
         }]);
-      } else {
+      } else if (response.error_code === 'host_key_mismatch') {
+        alert(`WARNING: ${response.message}\n\nThe connection was refused.`);
+      } else if (response.error_code !== 'host_key_unknown') {
         alert(`Connection failed: ${response.message}`);
       }
     } catch (error) {
-      alert(`Error: ${error}`);
+      alert(`Error: ${errorMessage(error)}`);
     } finally {
+      unlistenPrompts();
       setIsLoading(false);
     }
   };
@@ -136,7 +221,7 @@ const SSHInterface: React.FC = () => {
     } catch (error) {
       const errorResult: CommandResult = {
         stdout: '',
-        stderr: `Error executing command: ${error}`,
+        stderr: `Error executing command: ${errorMessage(error)}`,
         exit_status: -1,
         success: false
       };